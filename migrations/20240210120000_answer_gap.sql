@@ -0,0 +1,2 @@
+-- Multi-gap tasks record one answer per gap
+ALTER TABLE user_answer ADD COLUMN gap int NOT NULL DEFAULT 0;
@@ -0,0 +1,12 @@
+create table blitz_result (
+    id bigserial not null,
+    uid bigint not null references user_info(uid) on delete cascade,
+    duration_secs int not null,
+    answered bigint not null,
+    correct bigint not null,
+    avg_response_ms bigint not null,
+    finished_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    PRIMARY KEY (id)
+);
+
+create index blitz_result_uid on blitz_result (uid, duration_secs);
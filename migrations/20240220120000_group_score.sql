@@ -0,0 +1,7 @@
+create table group_score (
+    chat_id bigint not null,
+    uid bigint not null references user_info(uid) on delete cascade,
+    answered bigint not null,
+    correct bigint not null,
+    PRIMARY KEY (chat_id, uid)
+);
@@ -0,0 +1,11 @@
+ALTER TABLE user_info ADD COLUMN public_profile boolean NOT NULL DEFAULT false;
+
+create table user_friend (
+    uid bigint not null references user_info(uid) on delete cascade,
+    friend_uid bigint not null references user_info(uid) on delete cascade,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    PRIMARY KEY (uid, friend_uid)
+);
+
+create index user_answer_uid_answered_at on user_answer (uid, answered_at);
+create index user_answer_answered_at on user_answer (answered_at);
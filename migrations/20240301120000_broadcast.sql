@@ -0,0 +1,19 @@
+create table broadcast (
+    id bigserial not null,
+    author_uid bigint not null,
+    text text not null,
+    status text not null check (status in ('draft', 'sending', 'done', 'cancelled')),
+    -- Chats are delivered in chat_id order, so the last one is enough to resume after restart
+    last_chat_id bigint null,
+    sent bigint not null default 0,
+    failed bigint not null default 0,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    PRIMARY KEY (id)
+);
+
+-- Chats where the bot was blocked or kicked, skipped until the user is active again
+create table chat_blocked (
+    chat_id bigint not null,
+    blocked_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    PRIMARY KEY (chat_id)
+);
@@ -0,0 +1,14 @@
+-- Feedback conversations, both user messages and admin replies
+create table feedback (
+    id bigserial not null,
+    chat_id bigint not null, -- chat of the user
+    from_admin boolean not null,
+    text text not null,
+    -- Message in the feedback chat, replies to it are delivered to the user
+    feedback_message_id int not null,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    PRIMARY KEY (id)
+);
+
+create index feedback_chat_id on feedback (chat_id, created_at);
+create index feedback_message_id on feedback (feedback_message_id);
@@ -0,0 +1,17 @@
+create table task_report (
+    id bigserial not null,
+    task_id bigint not null, -- no ref as might reference deleted task
+    uid bigint not null references user_info(uid) on delete cascade,
+    reason text not null,
+    -- Notification in the feedback chat, replies to it annotate the task
+    feedback_message_id int null,
+    -- Reports are resolved when the task is deactivated or annotated
+    resolved boolean not null default false,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    PRIMARY KEY (id)
+);
+
+create unique index task_report_open on task_report (task_id, uid, reason) where not resolved;
+create index task_report_feedback_message_id on task_report (feedback_message_id);
+
+ALTER TABLE task_info ADD COLUMN annotation text NULL;
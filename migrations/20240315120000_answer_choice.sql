@@ -0,0 +1,3 @@
+-- Chosen button, the text is kept as distractors are picked randomly for every question
+ALTER TABLE user_answer ADD COLUMN answer_index int NULL;
+ALTER TABLE user_answer ADD COLUMN answer_text text NULL;
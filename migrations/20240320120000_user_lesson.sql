@@ -0,0 +1,7 @@
+-- Lessons of the curriculum unlocked by the user, the first lesson is always open and not stored
+create table user_lesson (
+    uid bigint not null references user_info(uid) on delete cascade,
+    lesson_id text not null,
+    unlocked_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    PRIMARY KEY (uid, lesson_id)
+);
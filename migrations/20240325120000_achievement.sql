@@ -0,0 +1,11 @@
+ALTER TABLE user_info ADD COLUMN xp bigint NOT NULL DEFAULT 0;
+
+create table user_achievement (
+    uid bigint not null references user_info(uid) on delete cascade,
+    achievement_id text not null,
+    unlocked_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    PRIMARY KEY (uid, achievement_id)
+);
+
+-- Task accuracy is read on every answer to weight its XP
+create index user_answer_task_id on user_answer (task_id);
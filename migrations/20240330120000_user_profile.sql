@@ -0,0 +1,15 @@
+ALTER TABLE user_info ADD COLUMN language_code text NULL;
+ALTER TABLE user_info ADD COLUMN is_premium boolean NOT NULL DEFAULT false;
+
+-- Changes of profile fields, to investigate support requests
+create table user_profile_change (
+    id bigserial not null,
+    uid bigint not null references user_info(uid) on delete cascade,
+    field text not null,
+    old_value text null,
+    new_value text null,
+    changed_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    PRIMARY KEY (id)
+);
+
+create index user_profile_change_uid on user_profile_change (uid, changed_at);
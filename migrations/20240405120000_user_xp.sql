@@ -0,0 +1,8 @@
+-- XP awards with their time, user_info.xp stays the running total
+create table user_xp (
+    uid bigint not null references user_info(uid) on delete cascade,
+    xp bigint not null,
+    earned_at TIMESTAMP WITH TIME ZONE NOT NULL
+);
+
+create index user_xp_earned_at on user_xp (earned_at);
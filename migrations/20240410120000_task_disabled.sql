@@ -0,0 +1,3 @@
+-- Deactivation by an admin, task imports never change it unlike `active`
+alter table task_info add column disabled_at TIMESTAMP WITH TIME ZONE NULL;
+alter table task_info add column disabled_by bigint NULL;
@@ -0,0 +1,6 @@
+-- Random invite tokens, a friendship needs a link the user shared
+create table friend_invite (
+    token text primary key,
+    uid bigint not null unique references user_info(uid) on delete cascade,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL
+);
@@ -0,0 +1,160 @@
+-- The same schema as the Postgres migrations up to 20240330120000_user_profile.
+-- Times are stored as RFC 3339 text in UTC and compared with julianday().
+
+CREATE TABLE user_info (
+    uid integer NOT NULL,
+    username text NULL,
+    full_name text NOT NULL,
+    language_code text NULL,
+    is_premium boolean NOT NULL DEFAULT false,
+    public_profile boolean NOT NULL DEFAULT false,
+    xp integer NOT NULL DEFAULT 0,
+    created_at text NOT NULL,
+    last_active_at text NOT NULL,
+    PRIMARY KEY (uid)
+);
+
+create index user_info_username on user_info (lower(username));
+
+CREATE TABLE user_state (
+    chat_id integer NOT NULL,
+    filter text,
+    PRIMARY KEY (chat_id)
+);
+
+CREATE TABLE task_info (
+    id integer PRIMARY KEY AUTOINCREMENT,
+    hash integer NOT NULL,
+    active boolean NOT NULL,
+    filters text NOT NULL,
+    task_data text NOT NULL,
+    annotation text NULL
+);
+
+create unique index task_hash on task_info (hash);
+
+create table user_task (
+    id integer PRIMARY KEY AUTOINCREMENT,
+    chat_id integer not null, -- no ref as might reference chat with no user
+    task_id integer not null -- no ref as might reference deleted task
+);
+
+create index user_task_chat_id on user_task (chat_id, id);
+
+create table user_answer (
+    id integer PRIMARY KEY AUTOINCREMENT,
+    uid integer not null references user_info(uid) on delete cascade,
+    task_id integer not null, -- no ref as might reference deleted task
+    gap integer NOT NULL DEFAULT 0,
+    answer_index integer NULL,
+    answer_text text NULL,
+    correct boolean,
+    asked_at text NOT NULL,
+    answered_at text NOT NULL
+);
+
+create index user_answer_uid_answered_at on user_answer (uid, answered_at);
+create index user_answer_answered_at on user_answer (answered_at);
+create index user_answer_task_id on user_answer (task_id);
+
+create table blitz_result (
+    id integer PRIMARY KEY AUTOINCREMENT,
+    uid integer not null references user_info(uid) on delete cascade,
+    duration_secs integer not null,
+    answered integer not null,
+    correct integer not null,
+    avg_response_ms integer not null,
+    finished_at text NOT NULL
+);
+
+create index blitz_result_uid on blitz_result (uid, duration_secs);
+
+create table group_score (
+    chat_id integer not null,
+    uid integer not null references user_info(uid) on delete cascade,
+    answered integer not null,
+    correct integer not null,
+    PRIMARY KEY (chat_id, uid)
+);
+
+create table user_friend (
+    uid integer not null references user_info(uid) on delete cascade,
+    friend_uid integer not null references user_info(uid) on delete cascade,
+    created_at text NOT NULL,
+    PRIMARY KEY (uid, friend_uid)
+);
+
+create table broadcast (
+    id integer PRIMARY KEY AUTOINCREMENT,
+    author_uid integer not null,
+    text text not null,
+    status text not null check (status in ('draft', 'sending', 'done', 'cancelled')),
+    -- Chats are delivered in chat_id order, so the last one is enough to resume after restart
+    last_chat_id integer null,
+    sent integer not null default 0,
+    failed integer not null default 0,
+    created_at text NOT NULL
+);
+
+-- Chats where the bot was blocked or kicked, skipped until the user is active again
+create table chat_blocked (
+    chat_id integer not null,
+    blocked_at text NOT NULL,
+    PRIMARY KEY (chat_id)
+);
+
+-- Feedback conversations, both user messages and admin replies
+create table feedback (
+    id integer PRIMARY KEY AUTOINCREMENT,
+    chat_id integer not null, -- chat of the user
+    from_admin boolean not null,
+    text text not null,
+    -- Message in the feedback chat, replies to it are delivered to the user
+    feedback_message_id integer not null,
+    created_at text NOT NULL
+);
+
+create index feedback_chat_id on feedback (chat_id, created_at);
+create index feedback_message_id on feedback (feedback_message_id);
+
+create table task_report (
+    id integer PRIMARY KEY AUTOINCREMENT,
+    task_id integer not null, -- no ref as might reference deleted task
+    uid integer not null references user_info(uid) on delete cascade,
+    reason text not null,
+    -- Notification in the feedback chat, replies to it annotate the task
+    feedback_message_id integer null,
+    -- Reports are resolved when the task is deactivated or annotated
+    resolved boolean not null default false,
+    created_at text NOT NULL
+);
+
+create unique index task_report_open on task_report (task_id, uid, reason) where not resolved;
+create index task_report_feedback_message_id on task_report (feedback_message_id);
+
+-- Lessons of the curriculum unlocked by the user, the first lesson is always open and not stored
+create table user_lesson (
+    uid integer not null references user_info(uid) on delete cascade,
+    lesson_id text not null,
+    unlocked_at text NOT NULL,
+    PRIMARY KEY (uid, lesson_id)
+);
+
+create table user_achievement (
+    uid integer not null references user_info(uid) on delete cascade,
+    achievement_id text not null,
+    unlocked_at text NOT NULL,
+    PRIMARY KEY (uid, achievement_id)
+);
+
+-- Changes of profile fields, to investigate support requests
+create table user_profile_change (
+    id integer PRIMARY KEY AUTOINCREMENT,
+    uid integer not null references user_info(uid) on delete cascade,
+    field text not null,
+    old_value text null,
+    new_value text null,
+    changed_at text NOT NULL
+);
+
+create index user_profile_change_uid on user_profile_change (uid, changed_at);
@@ -0,0 +1,8 @@
+-- XP awards with their time, user_info.xp stays the running total
+create table user_xp (
+    uid integer not null references user_info(uid) on delete cascade,
+    xp integer not null,
+    earned_at text NOT NULL
+);
+
+create index user_xp_earned_at on user_xp (earned_at);
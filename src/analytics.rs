@@ -0,0 +1,218 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use crate::{
+    bot::bot_services::{Answer, BotStats, TaskStats},
+    model::{Task, TaskId},
+};
+
+/// Aggregates answers per task, ordered by task id. Every gap of multi-gap tasks counts as an answer,
+/// but only first gaps count for the response time, as all gaps share the time the task was asked.
+/// Storages aggregate in the database, this is used by the in-memory one.
+pub fn task_stats(answers: &[Answer]) -> Vec<TaskStats> {
+    let mut answers_by_task: BTreeMap<TaskId, Vec<&Answer>> = BTreeMap::new();
+    for answer in answers {
+        answers_by_task.entry(answer.task_id).or_default().push(answer);
+    }
+
+    answers_by_task
+        .into_iter()
+        .map(|(task_id, answers)| {
+            let mut response_times = answers
+                .iter()
+                .filter(|answer| answer.gap == 0)
+                .map(|answer| Duration::try_from(answer.answered_at - answer.asked_at).unwrap_or_default())
+                .collect::<Vec<_>>();
+
+            let mut distractors: BTreeMap<&str, i64> = BTreeMap::new();
+            for answer in answers.iter().filter(|answer| !answer.correct) {
+                // Answers recorded before the chosen text was stored are not counted
+                if let Some(text) = &answer.answer_text {
+                    *distractors.entry(text).or_default() += 1;
+                }
+            }
+            let mut distractors = distractors
+                .into_iter()
+                .map(|(text, count)| (text.to_owned(), count))
+                .collect::<Vec<_>>();
+            distractors.sort_by_key(|(_, count)| -count);
+
+            TaskStats {
+                task_id,
+                answered: answers.len() as i64,
+                correct: answers.iter().filter(|answer| answer.correct).count() as i64,
+                median_response: median(&mut response_times),
+                distractors,
+            }
+        })
+        .collect()
+}
+
+/// Exports statistics as CSV, `tasks` provide the task texts and may miss deleted tasks.
+pub fn to_csv(stats: &[TaskStats], tasks: &[Task]) -> String {
+    let mut csv = "task_id,task,correct_answer,answered,correct,accuracy,median_response_ms,distractors\n".to_owned();
+    for task_stats in stats {
+        let task = tasks.iter().find(|task| task.id == task_stats.task_id);
+        let distractors = task_stats
+            .distractors
+            .iter()
+            .map(|(text, count)| format!("{text}: {count}"))
+            .collect::<Vec<_>>()
+            .join("; ");
+
+        let fields = [
+            task_stats.task_id.to_string(),
+            csv_field(task.map(|task| task.task.as_str()).unwrap_or_default()),
+            csv_field(task.map(|task| task.correct.as_str()).unwrap_or_default()),
+            task_stats.answered.to_string(),
+            task_stats.correct.to_string(),
+            format!("{:.3}", task_stats.accuracy()),
+            task_stats.median_response.as_millis().to_string(),
+            csv_field(&distractors),
+        ];
+        csv.push_str(&fields.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+/// Users and daily activity as text, shared by `/admin stats` and the `stats` command.
+pub fn bot_stats_report(stats: &BotStats, days: u64) -> String {
+    let mut text = format!(
+        "Users: {total}, new in {days} days: {new}\n",
+        total = stats.total_users,
+        new = stats.new_users
+    );
+
+    if stats.daily.is_empty() {
+        text.push_str("\nNo answers during this period.");
+    }
+    for day in &stats.daily {
+        text.push_str(&format!(
+            "\n{date}: {users} active, {answered} answers, {accuracy}% correct",
+            date = day.day,
+            users = day.active_users,
+            answered = day.answered,
+            accuracy = percent(day.correct, day.answered),
+        ));
+    }
+
+    let answered = stats.daily.iter().map(|day| day.answered).sum::<i64>();
+    let correct = stats.daily.iter().map(|day| day.correct).sum::<i64>();
+    if !stats.daily.is_empty() {
+        text.push_str(&format!(
+            "\n\nAverage DAU: {dau:.1}, answers per day: {per_day:.1}, accuracy: {accuracy}%",
+            dau = stats.daily.iter().map(|day| day.active_users).sum::<i64>() as f64 / days as f64,
+            per_day = answered as f64 / days as f64,
+            accuracy = percent(correct, answered),
+        ));
+    }
+    text
+}
+
+fn percent(part: i64, total: i64) -> i64 {
+    match total {
+        0 => 0,
+        total => part * 100 / total,
+    }
+}
+
+fn median(values: &mut [Duration]) -> Duration {
+    if values.is_empty() {
+        return Duration::ZERO;
+    }
+
+    values.sort();
+    let middle = values.len() / 2;
+    match values.len() % 2 {
+        0 => (values[middle - 1] + values[middle]) / 2,
+        _ => values[middle],
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use time::OffsetDateTime;
+
+    use super::*;
+
+    fn answer(task_id: TaskId, correct: bool, answer_text: &str, response_secs: u64) -> Answer {
+        let answered_at = OffsetDateTime::now_utc();
+        Answer {
+            uid: 1,
+            task_id,
+            gap: 0,
+            answer_index: 0,
+            answer_text: Some(answer_text.to_owned()),
+            correct,
+            asked_at: answered_at - Duration::from_secs(response_secs),
+            answered_at,
+        }
+    }
+
+    #[test]
+    fn test_task_stats() {
+        let stats = task_stats(&[
+            answer(2, true, "kuću", 4),
+            answer(1, false, "kuća", 3),
+            answer(1, false, "kućom", 1),
+            answer(1, false, "kuća", 10),
+            answer(1, true, "kuću", 2),
+        ]);
+
+        assert_eq!(
+            stats,
+            vec![
+                TaskStats {
+                    task_id: 1,
+                    answered: 4,
+                    correct: 1,
+                    median_response: Duration::from_millis(2500),
+                    distractors: vec![("kuća".to_owned(), 2), ("kućom".to_owned(), 1)],
+                },
+                TaskStats {
+                    task_id: 2,
+                    answered: 1,
+                    correct: 1,
+                    median_response: Duration::from_secs(4),
+                    distractors: Vec::new(),
+                },
+            ]
+        );
+        assert_eq!(stats[0].accuracy(), 0.25);
+    }
+
+    #[test]
+    fn test_to_csv() {
+        let stats = task_stats(&[answer(1, false, "a, \"b\"", 1), answer(2, true, "c", 1)]);
+        let task = Task {
+            id: 1,
+            hash: 1,
+            task: "Idem u kuću, sada".into(),
+            masked_task: "Idem u *****, sada".into(),
+            correct: "kuću".into(),
+            base: "kuća".into(),
+            info: Vec::new(),
+            hints: Vec::new(),
+            filters: Vec::new(),
+            wrong_answers: Vec::new(),
+            gaps: Vec::new(),
+        };
+
+        assert_eq!(
+            to_csv(&stats, &[task]),
+            indoc::indoc! {r#"
+                task_id,task,correct_answer,answered,correct,accuracy,median_response_ms,distractors
+                1,"Idem u kuću, sada",kuću,1,0,0.000,1000,"a, ""b"": 1"
+                2,,,1,1,1.000,1000,
+            "#}
+        );
+    }
+}
@@ -0,0 +1,434 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant},
+};
+
+use teloxide::{
+    requests::Requester,
+    types::{ChatId, MessageId},
+    Bot,
+};
+use time::{Date, OffsetDateTime};
+
+use crate::metrics::SendMeasured;
+use crate::model::{Task, TaskId};
+
+use super::{
+    bot_core::BotContext,
+    bot_filter::parse_filter,
+    bot_services::{TaskInfoService, UserStateService},
+};
+
+/// Question messages the learner opened a grammar card for before answering, with the time of the first
+/// card. Spoiler hints in the question can't be tracked, as Telegram doesn't tell bots when they are revealed.
+pub(super) type UsedHints = Arc<Mutex<HashMap<(ChatId, MessageId), Instant>>>;
+
+/// Hints for questions left unanswered for longer are forgotten
+pub(super) const USED_HINT_TTL: Duration = Duration::from_secs(60 * 60 * 24);
+
+/// Active task ids of the category achievement values, looked up on load like the lesson tasks.
+pub(super) type CategoryTasks = Arc<RwLock<Arc<HashMap<&'static str, HashSet<TaskId>>>>>;
+
+const CORRECT_ANSWER_XP: f64 = 10.0;
+/// Wrong answers still give a bit for the effort
+const WRONG_ANSWER_XP: f64 = 2.0;
+const DEFAULT_TASK_ACCURACY: f64 = 0.75;
+/// Answers a task needs before its accuracy is trusted for weighting
+const MIN_TASK_ANSWERS: i64 = 10;
+/// Level `n` needs `LEVEL_XP_STEP * n * (n - 1) / 2` XP: 100 for level 2, 300 for level 3 and so on
+const LEVEL_XP_STEP: i64 = 100;
+const MAX_STREAK_DAYS: usize = 400;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Condition {
+    Answers(i64),
+    StreakDays(i64),
+    CategoryAccuracy {
+        name: &'static str,
+        value: &'static str,
+        accuracy: f64,
+        min_answers: i64,
+    },
+}
+
+#[derive(Debug)]
+struct Achievement {
+    /// Stored with the user, must not change
+    id: &'static str,
+    title: &'static str,
+    description: &'static str,
+    condition: Condition,
+}
+
+const fn case_master(
+    id: &'static str,
+    title: &'static str,
+    description: &'static str,
+    value: &'static str,
+) -> Achievement {
+    Achievement {
+        id,
+        title,
+        description,
+        condition: Condition::CategoryAccuracy {
+            name: "Падеж",
+            value,
+            accuracy: 0.95,
+            min_answers: 50,
+        },
+    }
+}
+
+const ACHIEVEMENTS: &[Achievement] = &[
+    Achievement {
+        id: "answers_1",
+        title: "Первый шаг",
+        description: "ответить на первое задание",
+        condition: Condition::Answers(1),
+    },
+    Achievement {
+        id: "answers_100",
+        title: "Сотня",
+        description: "ответить на 100 заданий",
+        condition: Condition::Answers(100),
+    },
+    Achievement {
+        id: "answers_1000",
+        title: "Тысяча",
+        description: "ответить на 1000 заданий",
+        condition: Condition::Answers(1000),
+    },
+    Achievement {
+        id: "streak_3",
+        title: "Три дня подряд",
+        description: "заниматься 3 дня подряд",
+        condition: Condition::StreakDays(3),
+    },
+    Achievement {
+        id: "streak_7",
+        title: "Неделя подряд",
+        description: "заниматься 7 дней подряд",
+        condition: Condition::StreakDays(7),
+    },
+    Achievement {
+        id: "streak_30",
+        title: "Месяц подряд",
+        description: "заниматься 30 дней подряд",
+        condition: Condition::StreakDays(30),
+    },
+    case_master(
+        "locative_95",
+        "Мастер локатива",
+        "95% правильных из не менее 50 ответов на локатив",
+        "locative",
+    ),
+    case_master(
+        "dative_95",
+        "Мастер датива",
+        "95% правильных из не менее 50 ответов на датив",
+        "dative",
+    ),
+    case_master(
+        "accusative_95",
+        "Мастер аккузатива",
+        "95% правильных из не менее 50 ответов на аккузатив",
+        "accusative",
+    ),
+];
+
+/// Something the learner did, achievements are only checked for the events that can change them.
+#[derive(Debug)]
+enum AchievementEvent<'a> {
+    Answered { task: &'a Task },
+}
+
+impl Condition {
+    fn triggered_by(&self, event: &AchievementEvent) -> bool {
+        match (self, event) {
+            (Condition::Answers(_) | Condition::StreakDays(_), AchievementEvent::Answered { .. }) => true,
+            (Condition::CategoryAccuracy { name, value, .. }, AchievementEvent::Answered { task }) => {
+                task.filters.iter().any(|filter| filter.name == *name && filter.value == *value)
+            }
+        }
+    }
+}
+
+fn answer_xp(correct: bool, task_accuracy: f64, hint_used: bool) -> i64 {
+    let base = if correct { CORRECT_ANSWER_XP } else { WRONG_ANSWER_XP };
+    // Tasks most learners fail give up to twice as much
+    let mut xp = base * (2.0 - task_accuracy.clamp(0.0, 1.0));
+    if hint_used {
+        xp /= 2.0;
+    }
+    (xp.round() as i64).max(1)
+}
+
+fn level(xp: i64) -> i64 {
+    let mut level = 1;
+    while level_xp(level + 1) <= xp {
+        level += 1;
+    }
+    level
+}
+
+fn level_xp(level: i64) -> i64 {
+    LEVEL_XP_STEP * level * (level - 1) / 2
+}
+
+/// Consecutive days ending today or yesterday, `days` are distinct and latest first.
+fn streak_days(days: &[Date], today: Date) -> i64 {
+    let Some(&latest) = days.first() else {
+        return 0;
+    };
+    if latest != today && latest.next_day() != Some(today) {
+        return 0;
+    }
+
+    let mut streak = 1;
+    for pair in days.windows(2) {
+        if pair[1].next_day() != Some(pair[0]) {
+            break;
+        }
+        streak += 1;
+    }
+    streak
+}
+
+impl<T: TaskInfoService, U: UserStateService> BotContext<T, U> {
+    /// Finds the tasks of the category achievements, on start and after the tasks are reloaded.
+    pub(super) async fn load_category_tasks(&self) -> anyhow::Result<()> {
+        let mut category_tasks = HashMap::new();
+        for achievement in ACHIEVEMENTS {
+            if let Condition::CategoryAccuracy { value, .. } = achievement.condition {
+                let ids = self.tasks.get_task_ids(Some(&parse_filter(value))).await?;
+                category_tasks.insert(value, ids.into_iter().collect());
+            }
+        }
+        *self.category_tasks.write().unwrap() = Arc::new(category_tasks);
+        Ok(())
+    }
+
+    /// Gives XP for an answered task after its last gap and checks achievements, telling the learner
+    /// about level-ups. `correct` is true when every gap was answered correctly.
+    pub(super) async fn reward_answer(
+        &self,
+        bot: &Bot,
+        chat_id: ChatId,
+        message_id: MessageId,
+        uid: i64,
+        task: &Task,
+        correct: bool,
+    ) -> anyhow::Result<()> {
+        let hint_used = self.used_hints.lock().unwrap().remove(&(chat_id, message_id)).is_some();
+        let count = self.user_data.get_task_answer_count(task.id).await?;
+        let task_accuracy = match count.answered {
+            answered if answered >= MIN_TASK_ANSWERS => count.correct as f64 / answered as f64,
+            _ => DEFAULT_TASK_ACCURACY,
+        };
+
+        let xp = answer_xp(correct, task_accuracy, hint_used);
+        let total = self.user_data.add_xp(uid, xp).await?;
+        let new_level = level(total);
+        if new_level > level(total - xp) {
+            bot.send_message(
+                chat_id,
+                format!("⭐ Новый уровень: {new_level}! Набрано {total} XP. Достижения: /achievements"),
+            )
+            .send_measured()
+            .await?;
+        }
+
+        self.check_achievements(bot, chat_id, uid, &AchievementEvent::Answered { task })
+            .await
+    }
+
+    async fn check_achievements(
+        &self,
+        bot: &Bot,
+        chat_id: ChatId,
+        uid: i64,
+        event: &AchievementEvent<'_>,
+    ) -> anyhow::Result<()> {
+        let earned = self
+            .user_data
+            .get_achievements(uid)
+            .await?
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect::<HashSet<_>>();
+
+        let category_tasks = self.category_tasks.read().unwrap().clone();
+        let mut answers = None;
+        let mut streak = None;
+        let mut task_counts = None;
+        for achievement in ACHIEVEMENTS {
+            if earned.contains(achievement.id) || !achievement.condition.triggered_by(event) {
+                continue;
+            }
+
+            // Statistics are read only for the achievements that are checked
+            let reached = match achievement.condition {
+                Condition::Answers(target) => {
+                    if answers.is_none() {
+                        answers = Some(self.user_data.count_answered_tasks(uid).await?);
+                    }
+                    answers.unwrap_or_default() >= target
+                }
+                Condition::StreakDays(target) => {
+                    if streak.is_none() {
+                        let days = self.user_data.get_answer_days(uid, MAX_STREAK_DAYS).await?;
+                        streak = Some(streak_days(&days, OffsetDateTime::now_utc().date()));
+                    }
+                    streak.unwrap_or_default() >= target
+                }
+                Condition::CategoryAccuracy {
+                    value,
+                    accuracy,
+                    min_answers,
+                    ..
+                } => {
+                    let Some(task_ids) = category_tasks.get(value) else {
+                        continue;
+                    };
+                    if task_counts.is_none() {
+                        task_counts = Some(self.user_data.get_task_answer_counts(uid).await?);
+                    }
+                    let (answered, correct) = task_counts
+                        .iter()
+                        .flatten()
+                        .filter(|count| task_ids.contains(&count.task_id))
+                        .fold((0, 0), |(answered, correct), count| {
+                            (answered + count.answered, correct + count.correct)
+                        });
+                    answered >= min_answers && correct as f64 >= accuracy * answered as f64
+                }
+            };
+
+            if reached && self.user_data.award_achievement(uid, achievement.id).await? {
+                bot.send_message(
+                    chat_id,
+                    format!("🏆 Достижение «{}»: {}!", achievement.title, achievement.description),
+                )
+                .send_measured()
+                .await?;
+            }
+        }
+        Ok(())
+    }
+
+    pub(super) async fn handle_achievements(&self, bot: &Bot, chat_id: ChatId, uid: i64) -> anyhow::Result<()> {
+        let xp = self.user_data.get_xp(uid).await?;
+        let level = level(xp);
+        let earned = self.user_data.get_achievements(uid).await?;
+
+        let mut text = format!(
+            "⭐ Уровень {level}, {xp} XP, до следующего уровня {} XP\n\n🏆 Достижения {} из {}:\n",
+            level_xp(level + 1) - xp,
+            earned.len(),
+            ACHIEVEMENTS.len()
+        );
+        for achievement in ACHIEVEMENTS {
+            match earned.iter().find(|(id, _)| id == achievement.id) {
+                Some((_, earned_at)) => text.push_str(&format!(
+                    "\n✅ {} — {} ({})",
+                    achievement.title,
+                    achievement.description,
+                    earned_at.date()
+                )),
+                None => text.push_str(&format!("\n▫️ {} — {}", achievement.title, achievement.description)),
+            }
+        }
+
+        bot.send_message(chat_id, text).send_measured().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use time::macros::date;
+
+    use crate::model::FilterValue;
+
+    use super::*;
+
+    #[test]
+    fn test_answer_xp() {
+        assert_eq!(answer_xp(true, 1.0, false), 10);
+        assert_eq!(answer_xp(true, 0.5, false), 15);
+        assert_eq!(answer_xp(true, 0.0, false), 20);
+        assert_eq!(answer_xp(true, 0.5, true), 8);
+        assert_eq!(answer_xp(false, 0.75, false), 3);
+        assert_eq!(answer_xp(false, 1.0, true), 1);
+    }
+
+    #[test]
+    fn test_level() {
+        assert_eq!(level(0), 1);
+        assert_eq!(level(99), 1);
+        assert_eq!(level(100), 2);
+        assert_eq!(level(299), 2);
+        assert_eq!(level(300), 3);
+        assert_eq!(level_xp(4), 600);
+    }
+
+    #[test]
+    fn test_streak_days() {
+        let today = date!(2024 - 03 - 25);
+        assert_eq!(streak_days(&[], today), 0);
+        assert_eq!(
+            streak_days(
+                &[date!(2024 - 03 - 25), date!(2024 - 03 - 24), date!(2024 - 03 - 22)],
+                today
+            ),
+            2
+        );
+        // The streak is kept until the end of the day after the last answer
+        assert_eq!(
+            streak_days(
+                &[date!(2024 - 03 - 24), date!(2024 - 03 - 23), date!(2024 - 03 - 22)],
+                today
+            ),
+            3
+        );
+        assert_eq!(streak_days(&[date!(2024 - 03 - 23)], today), 0);
+        // Over the month boundary
+        assert_eq!(
+            streak_days(&[date!(2024 - 03 - 01), date!(2024 - 02 - 29)], date!(2024 - 03 - 01)),
+            2
+        );
+    }
+
+    #[test]
+    fn test_triggered_by() {
+        let mut task = Task {
+            id: 1,
+            hash: 1,
+            task: String::new(),
+            masked_task: String::new(),
+            correct: String::new(),
+            base: String::new(),
+            info: Vec::new(),
+            hints: Vec::new(),
+            filters: vec![FilterValue {
+                name: "Падеж".into(),
+                value: "dative".into(),
+            }],
+            wrong_answers: Vec::new(),
+            gaps: Vec::new(),
+        };
+        let triggered = |task: &Task| {
+            ACHIEVEMENTS
+                .iter()
+                .filter(|achievement| achievement.condition.triggered_by(&AchievementEvent::Answered { task }))
+                .map(|achievement| achievement.id)
+                .collect::<Vec<_>>()
+        };
+
+        assert!(triggered(&task).contains(&"dative_95"));
+        assert!(!triggered(&task).contains(&"locative_95"));
+        task.filters.clear();
+        assert_eq!(triggered(&task).len(), 6);
+    }
+}
@@ -0,0 +1,358 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use indoc::indoc;
+use teloxide::{
+    requests::Requester,
+    types::{ChatId, InputFile},
+    Bot,
+};
+use time::OffsetDateTime;
+
+use crate::metrics::SendMeasured;
+use crate::{
+    analytics::{bot_stats_report, to_csv},
+    model::{scan_data_directory, scan_grammar_directory, Task, TaskId},
+};
+
+use super::{
+    bot_core::BotContext,
+    bot_services::{TaskInfoService, TaskStats, UserStateService},
+};
+
+const RECENT_ERRORS_LIMIT: usize = 50;
+const DEFAULT_ERRORS_SHOWN: usize = 5;
+/// Keeps the errors list within the Telegram message size
+const MAX_ERRORS_SHOWN: usize = 10;
+const MAX_ERROR_LENGTH: usize = 300;
+const REPORTED_TASKS_SHOWN: usize = 10;
+const TASK_STATS_SHOWN: usize = 5;
+const PROFILE_CHANGES_SHOWN: usize = 5;
+const QUEUED_TASKS_SHOWN: usize = 3;
+/// Accuracy of rarely answered tasks is mostly noise
+const MIN_ANSWERS_FOR_STATS: i64 = 5;
+const ANALYTICS_PERIOD: Duration = Duration::from_secs(60 * 60 * 24 * 30);
+const DEFAULT_STATS_DAYS: u64 = 7;
+const MAX_STATS_DAYS: u64 = 60;
+const DAY: Duration = Duration::from_secs(60 * 60 * 24);
+
+static ADMIN_HELP_TEXT: &str = indoc! {"
+    Admin commands:
+
+    /admin stats [days] — users, answers and accuracy per day (7 days by default)
+    /admin user <username> — look up a user
+    /admin reload — reload tasks, grammar cards and lessons from the data directory
+    /admin deactivate <id> or hash:<hash> — stop giving the task in new queues, reloads keep it deactivated
+    /admin reports — tasks with unresolved error reports
+    /admin tasks — hardest and easiest tasks, most picked distractors for 30 days
+    /admin tasks csv — the same statistics for every task as a CSV file
+    /admin errors [count] — recent errors
+    /broadcast <text> — send an announcement to all chats after a preview
+    "};
+
+#[derive(Debug, Clone)]
+pub(super) struct RecentError {
+    at: OffsetDateTime,
+    chat_id: ChatId,
+    error: String,
+}
+
+pub(super) type RecentErrors = Arc<Mutex<VecDeque<RecentError>>>;
+
+impl<T: TaskInfoService, U: UserStateService> BotContext<T, U> {
+    pub(super) fn is_admin(&self, uid: i64) -> bool {
+        self.admin_ids.contains(&uid)
+    }
+
+    pub(super) fn record_error(&self, chat_id: ChatId, error: String) {
+        let mut errors = self.recent_errors.lock().unwrap();
+        if errors.len() == RECENT_ERRORS_LIMIT {
+            errors.pop_front();
+        }
+        errors.push_back(RecentError {
+            at: OffsetDateTime::now_utc(),
+            chat_id,
+            error,
+        });
+    }
+
+    pub(super) async fn handle_admin(
+        &self,
+        bot: &Bot,
+        command_text: Option<&str>,
+        chat_id: ChatId,
+        uid: i64,
+    ) -> anyhow::Result<()> {
+        let mut args = command_text.unwrap_or_default().split_whitespace();
+        let text = match (args.next(), args.next()) {
+            (Some("stats"), days) => self.admin_stats(days).await?,
+            (Some("user"), Some(username)) => self.admin_user(username).await?,
+            (Some("reload"), None) => self.admin_reload().await?,
+            (Some("deactivate"), Some(task)) => self.admin_deactivate(task, uid).await?,
+            (Some("reports"), None) => self.admin_reports().await?,
+            (Some("tasks"), None) => self.admin_task_stats().await?,
+            (Some("tasks"), Some("csv")) => {
+                self.send_task_stats_csv(bot, chat_id).await?;
+                return Ok(());
+            }
+            (Some("errors"), count) => self.admin_errors(count),
+            _ => ADMIN_HELP_TEXT.to_owned(),
+        };
+
+        bot.send_message(chat_id, text).send_measured().await?;
+        Ok(())
+    }
+
+    async fn admin_stats(&self, days: Option<&str>) -> anyhow::Result<String> {
+        let days = match days.map(str::parse::<u64>) {
+            None => DEFAULT_STATS_DAYS,
+            Some(Ok(days)) if (1..=MAX_STATS_DAYS).contains(&days) => days,
+            Some(_) => return Ok(format!("Days should be a number from 1 to {MAX_STATS_DAYS}")),
+        };
+
+        let stats = self.user_data.get_bot_stats(DAY * days as u32).await?;
+        Ok(bot_stats_report(&stats, days))
+    }
+
+    async fn admin_user(&self, username: &str) -> anyhow::Result<String> {
+        let username = username.trim_start_matches('@');
+        let Some(user) = self.user_data.find_user(username).await? else {
+            return Ok(format!("No user @{username} found"));
+        };
+
+        let week = self.user_data.get_answer_stat(user.uid, DAY * 7).await?;
+        let month = self.user_data.get_answer_stat(user.uid, DAY * 30).await?;
+        let friends = self.user_data.get_friends(user.uid).await?;
+        let public = self.user_data.is_public_profile(user.uid).await?;
+        let filter = self.user_data.get_state(ChatId(user.uid)).await?.filter;
+        let changes = self.user_data.get_profile_changes(user.uid, PROFILE_CHANGES_SHOWN).await?;
+        let queue = self.user_data.peek_tasks(ChatId(user.uid), QUEUED_TASKS_SHOWN).await?;
+
+        let mut text = format!(
+            indoc! {"
+                {full_name} @{username} (uid {uid})
+                Joined: {created_at}
+                Last active: {last_active_at}
+                Language: {language}, premium: {premium}
+
+                Answers in 7 days: {week_correct}/{week_count}, in 30 days: {month_correct}/{month_count}
+                Filter: {filter}
+                Queue: {remaining} tasks, next: {next}
+                Friends: {friends}, public profile: {public}
+            "},
+            full_name = user.full_name,
+            username = user.username.as_deref().unwrap_or(username),
+            uid = user.uid,
+            created_at = user.created_at.date(),
+            last_active_at = user.last_active_at,
+            language = user.language_code.as_deref().unwrap_or("unknown"),
+            premium = user.is_premium,
+            week_correct = week.correct,
+            week_count = week.count,
+            month_correct = month.correct,
+            month_count = month.count,
+            filter = filter.as_deref().unwrap_or("none"),
+            remaining = queue.remaining,
+            next = queue
+                .next
+                .iter()
+                .map(|queued| format!("{}. #{}", queued.position + 1, queued.task_id))
+                .collect::<Vec<_>>()
+                .join(", "),
+            friends = friends.len(),
+            public = public,
+        );
+
+        if !changes.is_empty() {
+            text.push_str("\nProfile changes:");
+        }
+        for change in &changes {
+            text.push_str(&format!(
+                "\n{date} {field}: {old} → {new}",
+                date = change.changed_at.date(),
+                field = change.field,
+                old = change.old_value.as_deref().unwrap_or("none"),
+                new = change.new_value.as_deref().unwrap_or("none"),
+            ));
+        }
+        Ok(text)
+    }
+
+    async fn admin_reload(&self) -> anyhow::Result<String> {
+        let tasks = scan_data_directory(&self.data_dir)?
+            .into_iter()
+            .flat_map(|task_group| task_group.tasks.into_iter())
+            .collect::<Vec<_>>();
+        if tasks.is_empty() {
+            return Ok(format!("No tasks found in {}, nothing reloaded", self.data_dir));
+        }
+
+        let (updated, deactivated) = self.tasks.update_tasks(&tasks).await?;
+        let cards = scan_grammar_directory(&self.data_dir)?;
+        let cards_count = cards.len();
+        *self.grammar_cards.write().unwrap() = cards;
+        self.load_curriculum().await?;
+        self.load_category_tasks().await?;
+        log::info!("Reloaded {updated} tasks and {cards_count} grammar cards, deactivated {deactivated} tasks");
+        Ok(format!(
+            "Reloaded {updated} tasks and {cards_count} grammar cards, deactivated {deactivated} tasks"
+        ))
+    }
+
+    /// Takes a task id, `#123` or `123`, or a hash from the task files, `hash:123`.
+    async fn admin_deactivate(&self, task: &str, uid: i64) -> anyhow::Result<String> {
+        let id = match task.strip_prefix("hash:") {
+            Some(hash) => {
+                let Ok(hash) = hash.trim().parse::<i64>() else {
+                    return Ok("Task hash should be a number".to_owned());
+                };
+                match self.tasks.find_task_by_hash(hash).await? {
+                    Some(id) => id,
+                    None => return Ok(format!("No task with hash {hash}")),
+                }
+            }
+            None => {
+                let Ok(id) = task.trim_start_matches('#').parse::<TaskId>() else {
+                    return Ok("Task id should be a number, use hash:<hash> for a hash".to_owned());
+                };
+                id
+            }
+        };
+
+        Ok(match self.tasks.deactivate_task(id, uid).await? {
+            true => {
+                log::info!("Deactivated task {id} by {uid}");
+                format!("Task {id} deactivated, it will not be given in new queues")
+            }
+            false => format!("No task with id {id} or it is already deactivated"),
+        })
+    }
+
+    async fn admin_reports(&self) -> anyhow::Result<String> {
+        let reported = self.user_data.get_reported_tasks(REPORTED_TASKS_SHOWN).await?;
+        if reported.is_empty() {
+            return Ok("No unresolved task reports".to_owned());
+        }
+
+        let mut text = "Most reported tasks, deactivate with /admin deactivate <id>:".to_owned();
+        for (task_id, count) in reported {
+            let task = self.tasks.get_task(task_id).await?;
+            text.push_str(&format!(
+                "\n\n#{task_id} — {count} reports\n{task}",
+                task = task.map(|task| task.task).unwrap_or_default()
+            ));
+        }
+        Ok(text)
+    }
+
+    async fn admin_task_stats(&self) -> anyhow::Result<String> {
+        let mut stats = self
+            .user_data
+            .get_task_stats(ANALYTICS_PERIOD)
+            .await?
+            .into_iter()
+            .filter(|task_stats| task_stats.answered >= MIN_ANSWERS_FOR_STATS)
+            .collect::<Vec<_>>();
+        if stats.is_empty() {
+            return Ok(format!(
+                "No tasks with at least {MIN_ANSWERS_FOR_STATS} answers in 30 days"
+            ));
+        }
+
+        let mut text = format!(
+            "Tasks with at least {MIN_ANSWERS_FOR_STATS} answers in 30 days: {}",
+            stats.len()
+        );
+
+        stats.sort_by(|a, b| a.accuracy().total_cmp(&b.accuracy()));
+        let shown = stats
+            .iter()
+            .take(TASK_STATS_SHOWN)
+            .chain(stats.iter().rev().take(TASK_STATS_SHOWN))
+            .map(|task_stats| task_stats.task_id)
+            .collect::<Vec<_>>();
+        let tasks = self.tasks.get_tasks(&shown).await?;
+
+        text.push_str("\n\n🔴 Hardest:");
+        for task_stats in stats.iter().take(TASK_STATS_SHOWN) {
+            text.push_str(&format_task_stats(task_stats, &tasks));
+        }
+
+        text.push_str("\n\n🟢 Easiest:");
+        for task_stats in stats.iter().rev().take(TASK_STATS_SHOWN) {
+            text.push_str(&format_task_stats(task_stats, &tasks));
+        }
+
+        let mut distractors = stats
+            .iter()
+            .flat_map(|task_stats| {
+                task_stats
+                    .distractors
+                    .iter()
+                    .map(move |(distractor, count)| (task_stats, distractor, *count))
+            })
+            .collect::<Vec<_>>();
+        distractors.sort_by_key(|(task_stats, _, count)| -(count * 100 / task_stats.answered));
+        text.push_str("\n\n🎯 Most picked distractors:");
+        for (task_stats, distractor, count) in distractors.into_iter().take(TASK_STATS_SHOWN) {
+            text.push_str(&format!(
+                "\n#{task_id}: «{distractor}» picked in {share}% of answers",
+                task_id = task_stats.task_id,
+                share = count * 100 / task_stats.answered,
+            ));
+        }
+
+        text.push_str("\n\nFull statistics: /admin tasks csv");
+        Ok(text)
+    }
+
+    async fn send_task_stats_csv(&self, bot: &Bot, chat_id: ChatId) -> anyhow::Result<()> {
+        let stats = self.user_data.get_task_stats(ANALYTICS_PERIOD).await?;
+        let task_ids = stats.iter().map(|task_stats| task_stats.task_id).collect::<Vec<_>>();
+        let tasks = self.tasks.get_tasks(&task_ids).await?;
+
+        let csv = to_csv(&stats, &tasks);
+        bot.send_document(chat_id, InputFile::memory(csv.into_bytes()).file_name("task_stats.csv"))
+            .send_measured()
+            .await?;
+        Ok(())
+    }
+
+    fn admin_errors(&self, count: Option<&str>) -> String {
+        let count = count
+            .and_then(|count| count.parse::<usize>().ok())
+            .unwrap_or(DEFAULT_ERRORS_SHOWN)
+            .min(MAX_ERRORS_SHOWN);
+
+        let errors = self.recent_errors.lock().unwrap();
+        if errors.is_empty() {
+            return "No errors since the bot started 🎉".to_owned();
+        }
+
+        let mut text = format!("Last {} of {} recent errors:", count.min(errors.len()), errors.len());
+        for error in errors.iter().rev().take(count) {
+            let message = match error.error.char_indices().nth(MAX_ERROR_LENGTH) {
+                Some((index, _)) => format!("{}…", &error.error[..index]),
+                None => error.error.clone(),
+            };
+            text.push_str(&format!("\n\n{} #{}\n{}", error.at, error.chat_id, message));
+        }
+        text
+    }
+}
+
+/// One line of `/admin tasks`, `tasks` provide the task texts and may miss deleted tasks.
+fn format_task_stats(task_stats: &TaskStats, tasks: &[Task]) -> String {
+    let task = tasks.iter().find(|task| task.id == task_stats.task_id);
+    format!(
+        "\n#{task_id} {accuracy:.0}% of {answered}, median {median:.1} s: {task}",
+        task_id = task_stats.task_id,
+        accuracy = task_stats.accuracy() * 100.0,
+        answered = task_stats.answered,
+        median = task_stats.median_response.as_secs_f64(),
+        task = task.map(|task| task.task.as_str()).unwrap_or_default(),
+    )
+}
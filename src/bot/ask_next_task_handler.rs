@@ -5,51 +5,70 @@ use prost::Message;
 use rand::seq::{IteratorRandom, SliceRandom};
 use rand::thread_rng;
 use teloxide::prelude::*;
-use teloxide::types::{InlineKeyboardButton, ParseMode, ReplyMarkup};
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup, ParseMode};
 use teloxide::Bot;
 
 use crate::bot::bot_core::BotErrors;
 use crate::bot::bot_filter::parse_filter;
+use crate::metrics::SendMeasured;
 use crate::model::Task;
 use crate::utils::{escape_telegram_symbols, rus_numeric};
 
 use super::bot_core::BotContext;
 use super::bot_services::{TaskInfoService, UserStateService};
+use super::grammar_handlers::grammar_button;
 use super::proto;
+use super::report_handlers::report_button;
+
+pub(super) struct NextTask {
+    pub task: Task,
+    /// Size of the regenerated queue and the filter used, when the queue had to be refilled
+    pub refilled: Option<(usize, Option<String>)>,
+}
 
 impl<T: TaskInfoService, U: UserStateService> BotContext<T, U> {
-    pub async fn ask_next_task(&self, bot: &Bot, chat_id: ChatId) -> anyhow::Result<()> {
-        let notify;
-        let current_filter;
-        let task = {
-            let task_id = self.user_data.take_next_task(chat_id).await?;
-            let task_id = match task_id {
-                Some(id) => {
-                    notify = None;
-                    current_filter = None;
-                    id
-                }
-                None => {
-                    let user_data = self.user_data.get_state(chat_id).await?;
-                    current_filter = user_data.filter.clone();
-                    let filter = current_filter.as_ref().map(|f| parse_filter(f));
-
-                    let mut tasks = self.tasks.get_task_ids(filter.as_ref()).await?;
-                    tasks.shuffle(&mut thread_rng());
-
-                    notify = Some(tasks.len());
-                    self.user_data.update_tasks(chat_id, &tasks).await?;
-                    self.user_data
-                        .take_next_task(chat_id)
-                        .await?
-                        .ok_or(BotErrors::NoTaskGenerated)?
-                }
-            };
+    pub(super) async fn take_next_task(&self, chat_id: ChatId) -> anyhow::Result<NextTask> {
+        let refilled;
+        let task_id = self.user_data.take_next_task(chat_id).await?;
+        let task_id = match task_id {
+            Some(id) => {
+                refilled = None;
+                id
+            }
+            None => {
+                let user_data = self.user_data.get_state(chat_id).await?;
+                let mut filter = user_data.filter.as_deref().map(parse_filter).unwrap_or_default();
+                filter.single_gap = !chat_id.is_user();
 
-            self.tasks.get_task(task_id).await?.ok_or(BotErrors::NoTaskFound)?
+                let tasks = self.tasks.get_task_ids(Some(&filter)).await?;
+                let tasks = self.order_by_difficulty(chat_id, tasks).await?;
+
+                refilled = Some((tasks.len(), user_data.filter));
+                self.user_data.update_tasks(chat_id, &tasks).await?;
+                self.user_data
+                    .take_next_task(chat_id)
+                    .await?
+                    .ok_or(BotErrors::NoTaskGenerated)?
+            }
         };
 
-        if let Some(generated_tasks) = notify {
+        let task = self.tasks.get_task(task_id).await?.ok_or(BotErrors::NoTaskFound)?;
+        Ok(NextTask { task, refilled })
+    }
+
+    /// Buttons under the question besides the answers, they stay after the answer.
+    pub(super) fn task_buttons(&self, task: &Task) -> Vec<InlineKeyboardButton> {
+        let mut buttons = vec![report_button(task.id)];
+        if !self.grammar_cards_for(task).is_empty() {
+            buttons.push(grammar_button(task.id));
+        }
+        buttons
+    }
+
+    pub async fn ask_next_task(&self, bot: &Bot, chat_id: ChatId) -> anyhow::Result<()> {
+        let NextTask { task, refilled } = self.take_next_task(chat_id).await?;
+
+        if let Some((generated_tasks, current_filter)) = refilled {
             bot.send_message(
             chat_id,
             format!(
@@ -63,11 +82,11 @@ impl<T: TaskInfoService, U: UserStateService> BotContext<T, U> {
             ),
         )
         .parse_mode(ParseMode::MarkdownV2)
-        .send()
+        .send_measured()
         .await?;
         }
 
-        let MessageData { message, buttons } = build_message(&task)?;
+        let MessageData { message, buttons } = build_message(&task, self.distractors)?;
         log::debug!(
             "#{chat_id} asking: {}",
             message[QUESTION_PRELUDE.len()..].trim().lines().next().unwrap_or_default()
@@ -77,13 +96,8 @@ impl<T: TaskInfoService, U: UserStateService> BotContext<T, U> {
         let result = bot
             .send_message(chat_id, message)
             .parse_mode(ParseMode::MarkdownV2)
-            .reply_markup(ReplyMarkup::inline_kb(
-                buttons
-                    .into_iter()
-                    .map(|button| vec![InlineKeyboardButton::callback(button.text, button.command)])
-                    .collect::<Vec<_>>(),
-            ))
-            .send()
+            .reply_markup(buttons_markup(buttons).append_row(self.task_buttons(&task)))
+            .send_measured()
             .await;
 
         match result {
@@ -97,24 +111,29 @@ impl<T: TaskInfoService, U: UserStateService> BotContext<T, U> {
 }
 
 #[derive(Debug, PartialEq)]
-struct SimpleCommand {
+pub(super) struct SimpleCommand {
     text: String,
     command: String,
 }
 
 #[derive(Debug, PartialEq)]
-struct MessageData {
-    message: String,
-    buttons: Vec<SimpleCommand>,
+pub(super) struct MessageData {
+    pub message: String,
+    pub buttons: Vec<SimpleCommand>,
 }
 
 pub const QUESTION_PRELUDE: &str = "➖❔➖❔➖❔➖❔➖❔➖\n\n\n";
 
-fn build_message(task: &Task) -> anyhow::Result<MessageData> {
+pub(super) fn build_message(task: &Task, distractors: usize) -> anyhow::Result<MessageData> {
     let mut message = QUESTION_PRELUDE.to_owned();
     message.push_str(&replace_mask_with_base_word(&task.masked_task, &task.base));
     message.push('\n');
 
+    let gaps_count = task.gaps().len();
+    if gaps_count > 1 {
+        message.push_str(&format!("\n_Пропусков: {gaps_count}, заполняйте их по порядку_\n"));
+    }
+
     for info in &task.info {
         message.push_str("\n\n_");
         message.push_str(info);
@@ -131,12 +150,37 @@ fn build_message(task: &Task) -> anyhow::Result<MessageData> {
 
     message = escape_telegram_symbols(&message, ".-!()");
 
-    let mut variants = vec![(&task.correct, true)];
+    let time = OffsetDateTime::now_utc();
+    let buttons = build_gap_buttons(
+        task,
+        0,
+        distractors,
+        time.unix_timestamp() * 1000 + time.millisecond() as i64,
+        false,
+    )?;
+
+    Ok(MessageData { message, buttons })
+}
+
+/// Buttons of the gap, `time_asked_ts` is when the question was asked and is shared by all its gaps.
+/// `earlier_gap_wrong` is carried to the last gap, which decides if the whole task is correct.
+pub(super) fn build_gap_buttons(
+    task: &Task,
+    gap: usize,
+    distractors: usize,
+    time_asked_ts: i64,
+    earlier_gap_wrong: bool,
+) -> anyhow::Result<Vec<SimpleCommand>> {
+    let gap_data = task.gaps().into_iter().nth(gap).ok_or(BotErrors::NoTaskFound)?;
+
+    let mut variants = vec![(&gap_data.correct, true)];
     variants.extend(
-        task.wrong_answers
+        gap_data
+            .wrong_answers
             .iter()
-            .filter(|v| **v != task.correct)
-            .choose_multiple(&mut thread_rng(), 3)
+            .map(|v| &v.text)
+            .filter(|v| **v != gap_data.correct)
+            .choose_multiple(&mut thread_rng(), distractors)
             .into_iter()
             .map(|answer| (answer, false)),
     );
@@ -146,14 +190,14 @@ fn build_message(task: &Task) -> anyhow::Result<MessageData> {
         .iter()
         .enumerate()
         .map(|(i, (variant, correct))| {
-            let time = OffsetDateTime::now_utc();
-            let time = time.unix_timestamp() * 1000 + (time.millisecond() as i64);
             let command = proto::Command {
                 command: Some(proto::command::Command::QuestionAnswer(proto::QuestionAnswer {
                     task_id: task.id,
                     index: i as i32,
                     is_correct: *correct,
-                    time_asked_ts: time,
+                    time_asked_ts,
+                    gap: gap as i32,
+                    earlier_gap_wrong,
                 })),
             };
             SimpleCommand {
@@ -163,7 +207,15 @@ fn build_message(task: &Task) -> anyhow::Result<MessageData> {
         })
         .collect::<Vec<_>>();
 
-    Ok(MessageData { message, buttons })
+    Ok(buttons)
+}
+
+pub(super) fn buttons_markup(buttons: Vec<SimpleCommand>) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new(
+        buttons
+            .into_iter()
+            .map(|button| vec![InlineKeyboardButton::callback(button.text, button.command)]),
+    )
 }
 
 fn replace_mask_with_base_word(sentence: &str, base: &str) -> String {
@@ -0,0 +1,220 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use teloxide::{requests::Requester, types::ChatId, Bot};
+use time::OffsetDateTime;
+
+use crate::metrics::SendMeasured;
+use crate::utils::rus_numeric;
+
+use super::{
+    bot_core::BotContext,
+    bot_services::{BlitzResult, TaskInfoService, UserStateService},
+};
+
+const DEFAULT_BLITZ_SECONDS: u64 = 60;
+const MIN_BLITZ_SECONDS: u64 = 10;
+const MAX_BLITZ_SECONDS: u64 = 600;
+
+#[derive(Debug, Clone)]
+pub(super) struct BlitzSession {
+    uid: i64,
+    duration_secs: u64,
+    started_at: OffsetDateTime,
+    deadline: OffsetDateTime,
+    answered: i64,
+    correct: i64,
+    response_time: Duration,
+    finished: bool,
+}
+
+pub(super) type BlitzSessions = Arc<Mutex<HashMap<ChatId, BlitzSession>>>;
+
+#[derive(Debug, PartialEq)]
+pub(super) enum BlitzAnswer {
+    NotInBlitz,
+    Accepted,
+    Late,
+}
+
+impl<T: TaskInfoService, U: UserStateService> BotContext<T, U> {
+    pub(super) async fn handle_blitz(
+        &self,
+        bot: &Bot,
+        command_text: Option<&str>,
+        chat_id: ChatId,
+        uid: i64,
+    ) -> anyhow::Result<()> {
+        let duration_secs = match command_text.map(str::trim) {
+            None | Some("") => DEFAULT_BLITZ_SECONDS,
+            Some(text) => match text.parse::<u64>() {
+                Ok(secs) if (MIN_BLITZ_SECONDS..=MAX_BLITZ_SECONDS).contains(&secs) => secs,
+                _ => {
+                    bot.send_message(
+                        chat_id,
+                        format!(
+                            "Укажите длительность блица в секундах от {MIN_BLITZ_SECONDS} до {MAX_BLITZ_SECONDS}, например /blitz 60"
+                        ),
+                    )
+                    .send_measured()
+                    .await?;
+                    return Ok(());
+                }
+            },
+        };
+
+        let started_at = OffsetDateTime::now_utc();
+        let already_running = {
+            let mut sessions = self.blitz_sessions.lock().unwrap();
+            let already_running = sessions.get(&chat_id).is_some_and(|session| !session.finished);
+            if !already_running {
+                sessions.insert(
+                    chat_id,
+                    BlitzSession {
+                        uid,
+                        duration_secs,
+                        started_at,
+                        deadline: started_at + Duration::from_secs(duration_secs),
+                        answered: 0,
+                        correct: 0,
+                        response_time: Duration::ZERO,
+                        finished: false,
+                    },
+                );
+            }
+            already_running
+        };
+
+        if already_running {
+            bot.send_message(chat_id, "Блиц уже идёт, отвечайте быстрее! ⏱")
+                .send_measured()
+                .await?;
+            return Ok(());
+        }
+
+        let bot_clone = bot.clone();
+        let user_data = self.user_data.clone();
+        let sessions = self.blitz_sessions.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_secs(duration_secs)).await;
+            if let Err(err) = finish_blitz(&bot_clone, user_data.as_ref(), &sessions, chat_id, started_at).await {
+                log::error!("#{chat_id} failed to finish blitz: {err}");
+            }
+        });
+
+        bot.send_message(
+            chat_id,
+            format!(
+                "⏱ Блиц на {duration_secs} {seconds}! Отвечайте на как можно больше задач, ответы после окончания времени не засчитываются.",
+                seconds = rus_numeric(duration_secs as usize, "секунд", "секунду", "секунды"),
+            ),
+        )
+        .send_measured()
+        .await?;
+
+        self.ask_next_task(bot, chat_id).await
+    }
+
+    /// Counts an answered task towards the running blitz, if any. Every gap is checked against the deadline,
+    /// but only the last one counts the task, correct when all of its gaps are.
+    pub(super) fn blitz_answer(
+        &self,
+        chat_id: ChatId,
+        last_gap: bool,
+        task_correct: bool,
+        asked_at: OffsetDateTime,
+        answered_at: OffsetDateTime,
+    ) -> BlitzAnswer {
+        let mut sessions = self.blitz_sessions.lock().unwrap();
+        let Some(session) = sessions.get_mut(&chat_id) else {
+            return BlitzAnswer::NotInBlitz;
+        };
+
+        if asked_at < session.started_at || asked_at > session.deadline {
+            return BlitzAnswer::NotInBlitz;
+        }
+        if answered_at > session.deadline {
+            return BlitzAnswer::Late;
+        }
+        if !last_gap {
+            return BlitzAnswer::Accepted;
+        }
+
+        session.answered += 1;
+        if task_correct {
+            session.correct += 1;
+        }
+        session.response_time += Duration::try_from(answered_at - asked_at).unwrap_or_default();
+        BlitzAnswer::Accepted
+    }
+}
+
+async fn finish_blitz(
+    bot: &Bot,
+    user_data: &impl UserStateService,
+    sessions: &BlitzSessions,
+    chat_id: ChatId,
+    started_at: OffsetDateTime,
+) -> anyhow::Result<()> {
+    // Finished session is kept to reject late answers to the last question
+    let session = {
+        let mut sessions = sessions.lock().unwrap();
+        match sessions.get_mut(&chat_id) {
+            Some(session) if session.started_at == started_at && !session.finished => {
+                session.finished = true;
+                Some(session.clone())
+            }
+            _ => None,
+        }
+    };
+    let Some(session) = session else {
+        return Ok(());
+    };
+
+    let result = BlitzResult {
+        uid: session.uid,
+        duration_secs: session.duration_secs as i32,
+        answered: session.answered,
+        correct: session.correct,
+        avg_response_ms: match session.answered {
+            0 => 0,
+            answered => session.response_time.as_millis() as i64 / answered,
+        },
+        finished_at: OffsetDateTime::now_utc(),
+    };
+
+    let best = user_data.get_best_blitz_result(result.uid, result.duration_secs).await?;
+    user_data.record_blitz_result(result.clone()).await?;
+
+    let accuracy = match result.answered {
+        0 => 0,
+        answered => result.correct * 100 / answered,
+    };
+    let per_minute = result.answered as f64 * 60.0 / session.duration_secs as f64;
+
+    let mut text = format!(
+        "⏰ Время вышло!\n\n{correct} правильно из {answered} {tasks} ({accuracy}% правильных).\nСкорость: {per_minute:.1} задач в минуту, в среднем {avg_secs:.1} с на ответ.",
+        correct = result.correct,
+        answered = result.answered,
+        tasks = rus_numeric(result.answered as usize, "задач", "задача", "задачи"),
+        avg_secs = result.avg_response_ms as f64 / 1000.0,
+    );
+
+    match best {
+        Some(best) if best.correct >= result.correct => text.push_str(&format!(
+            "\n\nЛичный рекорд для блица на {duration} с: {correct} правильных.",
+            duration = best.duration_secs,
+            correct = best.correct
+        )),
+        _ if result.correct > 0 => text.push_str("\n\n🏆 Новый личный рекорд!"),
+        _ => {}
+    }
+
+    text.push_str("\n\nНапишите /start, чтобы продолжить в обычном режиме, или /blitz, чтобы сыграть ещё раз.");
+    bot.send_message(chat_id, text).send_measured().await?;
+
+    Ok(())
+}
@@ -1,5 +1,6 @@
 use std::future::Future;
-use std::sync::Arc;
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
 use std::time::Duration;
 
 use anyhow::{Context, Result};
@@ -10,13 +11,26 @@ use prost::Message;
 use teloxide::dptree::deps;
 use teloxide::prelude::*;
 use teloxide::types::{InlineKeyboardButtonKind, InlineKeyboardMarkup, MessageEntity, MessageEntityKind};
+use teloxide::update_listeners::webhooks;
 use teloxide::Bot;
 use thiserror::Error;
 use time::OffsetDateTime;
 use tokio::join;
+use url::Url;
 
-use crate::bot::ask_next_task_handler::QUESTION_PRELUDE;
+use crate::bot::achievement_handlers::{CategoryTasks, UsedHints};
+use crate::bot::admin_handlers::RecentErrors;
+use crate::bot::ask_next_task_handler::{build_gap_buttons, buttons_markup, QUESTION_PRELUDE};
+use crate::bot::blitz_handlers::{BlitzAnswer, BlitzSessions};
 use crate::bot::bot_services::Answer;
+use crate::bot::bot_services_measured::Measured;
+use crate::bot::difficulty::TaskDifficultyCache;
+use crate::bot::grammar_handlers::GrammarCards;
+use crate::bot::group_quiz_handlers::GroupQuizzes;
+use crate::bot::lesson_handlers::CurriculumState;
+use crate::bot::placement_handlers::{PlacementAnswer, PlacementSessions};
+use crate::metrics::{metrics, SendMeasured};
+use crate::model::scan_grammar_directory;
 use crate::utils::rus_numeric;
 
 use super::bot_services::{TaskInfoService, UserInfo, UserStateService};
@@ -28,15 +42,80 @@ pub(super) struct BotContext<T: TaskInfoService, U: UserStateService> {
     pub(super) tasks: Arc<T>,
     pub(super) user_data: Arc<U>,
     pub(super) feedback_chat_id: Option<ChatId>,
+    pub(super) blitz_sessions: BlitzSessions,
+    pub(super) group_quizzes: GroupQuizzes,
+    pub(super) placement_sessions: PlacementSessions,
+    pub(super) grammar_cards: GrammarCards,
+    pub(super) curriculum: CurriculumState,
+    pub(super) used_hints: UsedHints,
+    pub(super) category_tasks: CategoryTasks,
+    pub(super) task_difficulty: TaskDifficultyCache,
+    pub(super) admin_ids: Arc<Vec<i64>>,
+    pub(super) data_dir: Arc<String>,
+    pub(super) recent_errors: RecentErrors,
+    pub(super) target_success: f64,
+    pub(super) stats_every: u64,
+    pub(super) answer_delay: Duration,
+    pub(super) distractors: usize,
+}
+
+impl<T: TaskInfoService, U: UserStateService> Clone for BotContext<T, U> {
+    fn clone(&self) -> Self {
+        Self {
+            tasks: self.tasks.clone(),
+            user_data: self.user_data.clone(),
+            feedback_chat_id: self.feedback_chat_id,
+            blitz_sessions: self.blitz_sessions.clone(),
+            group_quizzes: self.group_quizzes.clone(),
+            placement_sessions: self.placement_sessions.clone(),
+            grammar_cards: self.grammar_cards.clone(),
+            curriculum: self.curriculum.clone(),
+            used_hints: self.used_hints.clone(),
+            category_tasks: self.category_tasks.clone(),
+            task_difficulty: self.task_difficulty.clone(),
+            admin_ids: self.admin_ids.clone(),
+            data_dir: self.data_dir.clone(),
+            recent_errors: self.recent_errors.clone(),
+            target_success: self.target_success,
+            stats_every: self.stats_every,
+            answer_delay: self.answer_delay,
+            distractors: self.distractors,
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct BotConfig {
     pub token: String,
     pub feedback_chat_id: Option<ChatId>,
+    /// Users allowed to run `/admin` commands
+    pub admin_ids: Vec<i64>,
+    /// Directory with task files, used to reload tasks
+    pub data_dir: String,
+    /// Expected success rate new task queues are ordered around, between 0 and 1
+    pub target_success: f64,
+    /// Progress message after every that many answers, 0 turns it off
+    pub stats_every: u64,
+    /// Pause between an answer and the next task
+    pub answer_delay: Duration,
+    /// Wrong answers shown next to the correct one
+    pub distractors: usize,
+    pub updates: UpdatesMode,
+}
+
+/// How the bot receives updates from Telegram.
+#[derive(Debug, Clone)]
+pub enum UpdatesMode {
+    Polling,
+    /// Telegram posts updates to the url, the bot listens on the local address
+    Webhook {
+        url: Url,
+        listen: SocketAddr,
+    },
 }
 
-#[derive(Error, Debug)]
+#[derive(Error, Debug, strum::IntoStaticStr)]
+#[strum(serialize_all = "snake_case")]
 pub enum BotErrors {
     #[error("No task found")]
     NoTaskFound,
@@ -56,7 +135,15 @@ pub enum BotErrors {
     NoFeedbackChatId,
 }
 
-type HandlerResult = Result<(), Box<dyn std::error::Error + Send + Sync>>;
+/// How often buffered user state is written, see [`UserStateService::flush`].
+const FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+type HandlerResult = anyhow::Result<()>;
+
+/// Metric label of the error, the [`BotErrors`] variant or `other`.
+fn error_kind(err: &anyhow::Error) -> &'static str {
+    err.downcast_ref::<BotErrors>().map(Into::into).unwrap_or("other")
+}
 
 pub async fn setup_and_run_bot(
     config: BotConfig,
@@ -66,81 +153,221 @@ pub async fn setup_and_run_bot(
     let bot = Bot::new(config.token);
 
     let context = BotContext {
-        tasks: Arc::new(tasks),
-        user_data: Arc::new(user_state),
+        tasks: Arc::new(Measured(tasks)),
+        user_data: Arc::new(Measured(user_state)),
         feedback_chat_id: config.feedback_chat_id,
+        blitz_sessions: BlitzSessions::default(),
+        group_quizzes: GroupQuizzes::default(),
+        placement_sessions: PlacementSessions::default(),
+        grammar_cards: Arc::new(RwLock::new(scan_grammar_directory(&config.data_dir)?)),
+        curriculum: CurriculumState::default(),
+        used_hints: UsedHints::default(),
+        category_tasks: CategoryTasks::default(),
+        task_difficulty: TaskDifficultyCache::default(),
+        admin_ids: Arc::new(config.admin_ids),
+        data_dir: Arc::new(config.data_dir),
+        recent_errors: RecentErrors::default(),
+        target_success: config.target_success,
+        stats_every: config.stats_every,
+        answer_delay: config.answer_delay,
+        distractors: config.distractors,
     };
 
-    run_dispatcher(bot, context).await
+    context.load_curriculum().await?;
+    context.load_category_tasks().await?;
+
+    if let Err(err) = context.resume_broadcasts(&bot).await {
+        log::error!("Failed to resume broadcasts: {err}");
+    }
+
+    let user_data = context.user_data.clone();
+    let flush_task = tokio::spawn({
+        let user_data = user_data.clone();
+        async move {
+            let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Err(err) = user_data.flush().await {
+                    log::error!("Failed to flush user state: {err}");
+                }
+            }
+        }
+    });
+
+    // The dispatcher returns after Ctrl-C, the state is flushed before exit
+    let result = run_dispatcher(bot, context, config.updates).await;
+    metrics().set_ready(false);
+    flush_task.abort();
+    user_data.flush().await.context("Failed to flush user state")?;
+    result
 }
 
 async fn run_dispatcher<T: TaskInfoService + 'static, U: UserStateService + 'static>(
     bot: Bot,
     context: BotContext<T, U>,
+    updates: UpdatesMode,
 ) -> Result<()> {
-    Dispatcher::builder(
-        bot,
+    let mut dispatcher = Dispatcher::builder(
+        bot.clone(),
         dptree::entry()
             .branch(Update::filter_message().endpoint(
                 |bot: Bot, message: teloxide::types::Message, ctx: Arc<BotContext<T, U>>| async move {
-                    ctx.handle_message(bot, message).await
+                    metrics().updates.with_label_values(&["message"]).inc();
+                    if let Some(user) = message.from() {
+                        metrics().record_active_user(user.id.0 as i64);
+                    }
+                    count_unhandled(ctx.handle_message(bot, message).await)
                 },
             ))
             .branch(Update::filter_callback_query().endpoint(
                 |bot: Bot, query: CallbackQuery, ctx: Arc<BotContext<T, U>>| async move {
-                    ctx.handle_callback_query(bot, query).await
+                    metrics().updates.with_label_values(&["callback_query"]).inc();
+                    metrics().record_active_user(query.from.id.0 as i64);
+                    count_unhandled(ctx.handle_callback_query(bot, query).await)
                 },
             )),
     )
     .dependencies(deps![Arc::new(context)])
     .enable_ctrlc_handler()
-    .build()
-    .dispatch()
-    .await;
+    .build();
+
+    metrics().set_ready(true);
+    match updates {
+        UpdatesMode::Polling => dispatcher.dispatch().await,
+        UpdatesMode::Webhook { url, listen } => {
+            log::info!("Receiving updates on {listen} from {url}");
+            let listener = webhooks::axum(bot, webhooks::Options::new(listen, url)).await?;
+            dispatcher
+                .dispatch_with_listener(listener, LoggingErrorHandler::with_custom_text("Webhook error"))
+                .await
+        }
+    }
 
     Ok(())
 }
 
+/// Errors the handlers could not report to the user, the dispatcher only logs them.
+fn count_unhandled(result: HandlerResult) -> HandlerResult {
+    if let Err(err) = &result {
+        metrics().handler_errors.with_label_values(&[error_kind(err)]).inc();
+    }
+    result
+}
+
 static HELP_TEXT: &str = indoc! {"
     Hi there! This bot will help you to learn cases in Serbian language (or at least try to).            
 
     You can start by typing /start command. Return to this message with /help or any other text.
+    See how many tasks are left with /queue.
+
+    Use /grammar locative to read about a case, or tap 📖 under a question.
+    Follow the course step by step with /lessons.
+    Earn XP for answers and collect /achievements.
+    New here? Take a short /placement test to find tasks of your level.
+    Try /blitz 60 to answer as many tasks as you can in 60 seconds.
+    Add the bot to a group and use /quiz there to compete with friends.
+    See weekly rankings with /top and invite friends with /invite.
+    Download your data with /export or delete it with /forget.
     "};
 
 impl<T: TaskInfoService, U: UserStateService> BotContext<T, U> {
     async fn handle_message(&self, bot: Bot, message: teloxide::types::Message) -> HandlerResult {
         let chat_id = message.chat.id;
         self.handle(&bot.clone(), chat_id, || async {
-            if let Some(user) = message.from() {
-                self.user_data.touch_user(&UserInfo::from_tg_user(user)).await?;
-            } else {
-                log::debug!("#{} got message from unknown user", chat_id);
+            let new_user = match message.from() {
+                Some(user) => self.user_data.touch_user(&UserInfo::from_tg_user(user)).await?,
+                None => {
+                    log::debug!("#{} got message from unknown user", chat_id);
+                    false
+                }
+            };
+
+            if Some(chat_id) == self.feedback_chat_id {
+                self.handle_feedback_chat(&bot, &message).await?;
+                return Ok(());
             }
 
+            if is_group_chat(&message.chat) && message.text().is_none() {
+                return Ok(());
+            }
             let text = message.text().ok_or(anyhow::anyhow!("Not a text message"))?;
 
             let (command, text) = if let Some(command) = text.trim().strip_prefix('/') {
                 let mut parts = command.splitn(2, ' ');
-                (parts.next().unwrap_or_default(), parts.next())
+                let command = parts.next().unwrap_or_default();
+                // In groups commands are addressed as /command@bot_name
+                let command = command.split('@').next().unwrap_or_default();
+                (command, parts.next())
             } else {
                 ("", Some(text))
             };
 
+            if is_group_chat(&message.chat) {
+                self.handle_group_message(&bot, command, text, chat_id).await?;
+                return Ok(());
+            }
+
+            let uid = message.from().map(|user| user.id.0 as i64).unwrap_or(chat_id.0);
             match command {
                 "start" => {
+                    let full_name = message.from().map(|user| user.full_name()).unwrap_or_default();
+                    self.accept_invite(&bot, text, chat_id, uid, &full_name).await?;
+                    if self.cancel_placement(chat_id) {
+                        // The rest of the test sample is replaced with a regular queue
+                        self.user_data.update_tasks(chat_id, &[]).await?;
+                    }
+                    if new_user {
+                        self.offer_placement(&bot, chat_id).await?;
+                    }
                     self.ask_next_task(&bot, chat_id).await?;
                 }
+                "grammar" => {
+                    self.handle_grammar(&bot, text, chat_id).await?;
+                }
+                "achievements" => {
+                    self.handle_achievements(&bot, chat_id, uid).await?;
+                }
+                "lessons" => {
+                    self.handle_lessons(&bot, chat_id, uid).await?;
+                }
+                "placement" => {
+                    self.handle_placement(&bot, chat_id).await?;
+                }
+                "export" => {
+                    self.handle_export(&bot, chat_id, uid).await?;
+                }
+                "forget" => {
+                    self.handle_forget(&bot, chat_id).await?;
+                }
                 "feedback" => {
                     self.send_feedback(&bot, text, &message).await?;
                 }
                 "filter" => {
                     self.handle_filter(&bot, text, chat_id).await?;
                 }
+                "queue" => {
+                    self.handle_queue(&bot, chat_id).await?;
+                }
                 "filter-reset" => {
                     self.handle_filter(&bot, Some("-"), chat_id).await?;
                 }
+                "blitz" => {
+                    self.handle_blitz(&bot, text, chat_id, uid).await?;
+                }
+                "top" => {
+                    self.handle_top(&bot, text, chat_id, uid).await?;
+                }
+                "invite" => {
+                    self.send_invite(&bot, chat_id, uid).await?;
+                }
+                "admin" if self.is_admin(uid) => {
+                    self.handle_admin(&bot, text, chat_id, uid).await?;
+                }
+                "broadcast" if self.is_admin(uid) => {
+                    self.handle_broadcast(&bot, text, chat_id, uid).await?;
+                }
                 _ => {
-                    bot.send_message(chat_id, HELP_TEXT).send().await?;
+                    bot.send_message(chat_id, HELP_TEXT).send_measured().await?;
                 }
             }
             Ok(())
@@ -148,59 +375,84 @@ impl<T: TaskInfoService, U: UserStateService> BotContext<T, U> {
         .await
     }
 
-    async fn send_feedback(&self, bot: &Bot, text: Option<&str>, message: &teloxide::types::Message) -> Result<()> {
-        let feedback_chat_id = self.feedback_chat_id.ok_or(BotErrors::NoFeedbackChatId)?;
-
-        let text = match text {
-            Some(text) => text,
-            None => {
-                bot.send_message(message.chat.id, "Пожалуйста, напишите текст, что хотите отправить. Можно ответить на сообщение бота, чтобы сослаться на него.").send().await?;
-                return Ok(());
-            }
-        };
-
-        let username = message
-            .from()
-            .as_ref()
-            .map(|user| {
-                format!(
-                    "@{} ({})",
-                    user.username.as_deref().unwrap_or("unknown"),
-                    user.full_name()
-                )
-            })
-            .unwrap_or_default();
-
-        let reply = message
-            .reply_to_message()
-            .map(|r| r.text().unwrap_or_default())
-            .map(|r| format!("\n\nReply to:\n\n{}", r))
-            .unwrap_or_default();
-
-        let feedback_text = format!("Feedback from {username}:\n\n{text}{reply}");
-        bot.send_message(feedback_chat_id, feedback_text).send().await?;
-        bot.send_message(message.chat.id, "Спасибо за отзыв!").send().await?;
-
-        Ok(())
-    }
-
     async fn handle_callback_query(&self, bot: Bot, query: CallbackQuery) -> HandlerResult {
         let message = query.message.as_ref().ok_or(BotErrors::NoMessageFound)?;
         self.user_data.touch_user(&UserInfo::from_tg_user(&query.from)).await?;
 
         let chat_id = message.chat.id;
         self.handle(&bot, chat_id, || async {
-            bot.answer_callback_query(query.id).send().await?;
-
-            let data = query.data.ok_or(BotErrors::NoData)?;
+            let data = query.data.as_ref().ok_or(BotErrors::NoData)?;
             let command = parse_command(data.as_str())?;
             let command = command.command.ok_or(BotErrors::WrongQuery)?;
 
-            // For now the only command is answer
+            if is_group_chat(&message.chat) {
+                match &command {
+                    Command::QuestionAnswer(answer) => self.handle_group_answer(&bot, &query, answer, message).await?,
+                    Command::DeactivateTask(deactivate) => {
+                        self.handle_deactivate_task(&bot, &query, deactivate, message).await?
+                    }
+                    Command::BroadcastAction(_)
+                    | Command::ReportTask(_)
+                    | Command::StartPlacement(_)
+                    | Command::ApplyFilter(_)
+                    | Command::ShowGrammar(_)
+                    | Command::StartLesson(_)
+                    | Command::ForgetMe(_) => {
+                        bot.answer_callback_query(query.id.clone()).send_measured().await?;
+                    }
+                }
+                return Ok(());
+            }
+
+            // Deactivation answers with an alert for non-admins
+            if !matches!(command, Command::DeactivateTask(_)) {
+                bot.answer_callback_query(query.id.clone()).send_measured().await?;
+            }
+
             match &command {
                 Command::QuestionAnswer(answer) => {
                     self.handle_answer(&bot, query.from.id, chat_id, answer, message).await
                 }
+                Command::BroadcastAction(action) => {
+                    self.handle_broadcast_action(&bot, &query, action, message).await?;
+                    Ok(())
+                }
+                Command::ReportTask(report) => {
+                    self.handle_report_task(&bot, &query, report, message).await?;
+                    Ok(())
+                }
+                Command::DeactivateTask(deactivate) => {
+                    self.handle_deactivate_task(&bot, &query, deactivate, message).await?;
+                    Ok(())
+                }
+                Command::StartPlacement(_) => {
+                    bot.edit_message_reply_markup(chat_id, message.id)
+                        .reply_markup(InlineKeyboardMarkup::default())
+                        .send_measured()
+                        .await?;
+                    self.handle_placement(&bot, chat_id).await?;
+                    Ok(())
+                }
+                Command::StartLesson(start) => {
+                    self.handle_start_lesson(&bot, chat_id, query.from.id.0 as i64, start).await?;
+                    Ok(())
+                }
+                Command::ForgetMe(forget) => {
+                    self.handle_forget_me(&bot, query.from.id.0 as i64, forget, message).await?;
+                    Ok(())
+                }
+                Command::ShowGrammar(show) => {
+                    self.handle_show_grammar(&bot, show, message).await?;
+                    Ok(())
+                }
+                Command::ApplyFilter(apply) => {
+                    bot.edit_message_reply_markup(chat_id, message.id)
+                        .reply_markup(InlineKeyboardMarkup::default())
+                        .send_measured()
+                        .await?;
+                    self.change_filter(&bot, &apply.filter, chat_id).await?;
+                    Ok(())
+                }
             }
         })
         .await
@@ -214,92 +466,152 @@ impl<T: TaskInfoService, U: UserStateService> BotContext<T, U> {
         answer: &proto::QuestionAnswer,
         message: &teloxide::types::Message,
     ) -> HandlerResult {
-        log::debug!("#{chat_id} got answer correct={correct}", correct = answer.is_correct);
-
-        let buttons = &message
-            .reply_markup()
-            .ok_or(BotErrors::NoReplyMarkup)
-            .context("reply_markup")?
-            .inline_keyboard;
-
-        let mut correct_text = &buttons[0][0].text;
-        let mut answer_text = &buttons[0][0].text;
-        for button in buttons {
-            let button_command = if let InlineKeyboardButtonKind::CallbackData(command) = &button[0].kind {
-                parse_command(command)?
-            } else {
-                return Err(BotErrors::BadCallbackDataInButton.into());
-            };
-            let button_command = button_command.command.ok_or(BotErrors::WrongQuery)?;
+        log::debug!(
+            "#{chat_id} got answer correct={correct} gap={gap}",
+            correct = answer.is_correct,
+            gap = answer.gap
+        );
 
-            let Command::QuestionAnswer(button_answer) = button_command;
+        let task = self.tasks.get_task(answer.task_id).await?.ok_or(BotErrors::NoTaskFound)?;
+        let gaps = task.gaps();
+        let gaps_count = gaps.len();
+        let gap = answer.gap as usize;
 
-            if button_answer.index == answer.index {
-                answer_text = &button[0].text;
-            }
-            if button_answer.is_correct {
-                correct_text = &button[0].text;
-            }
+        let (answer_text, correct_text) = answer_texts(message, answer.index)?;
+
+        let time = OffsetDateTime::from_unix_timestamp(answer.time_asked_ts / 1000)?
+            + Duration::from_millis((answer.time_asked_ts % 1000) as u64);
+        let answered_at = OffsetDateTime::now_utc();
+
+        let last_gap = gap + 1 >= gaps_count;
+        let task_correct = answer.is_correct && !answer.earlier_gap_wrong;
+        let blitz = self.blitz_answer(chat_id, last_gap, task_correct, time, answered_at);
+        if blitz == BlitzAnswer::Late {
+            bot.edit_message_reply_markup(chat_id, message.id)
+                .reply_markup(InlineKeyboardMarkup::default())
+                .send_measured()
+                .await?;
+            bot.send_message(chat_id, "⏰ Время блица вышло, этот ответ не засчитан.")
+                .send_measured()
+                .await?;
+            return Ok(());
         }
 
-        let text = message.text().ok_or(BotErrors::NoMessageFound)?;
-        let (mut text, entities_offset) = if let Some(prefix) = text.strip_prefix(QUESTION_PRELUDE) {
-            (prefix.to_owned(), QUESTION_PRELUDE.chars().count())
-        } else {
-            (text.to_owned(), 0)
+        let placement = self.placement_answer(chat_id, task.id, last_gap, task_correct);
+
+        let record_answer = self.user_data.record_anwer(Answer {
+            uid: user_id.0 as i64,
+            task_id: answer.task_id,
+            gap: answer.gap,
+            answer_index: answer.index,
+            answer_text: Some(answer_text.clone()),
+            correct: answer.is_correct,
+            asked_at: time,
+            answered_at,
+        });
+
+        let explanation = match answer.is_correct {
+            true => None,
+            false => gaps.get(gap).and_then(|gap| gap.explanation_for(&answer_text)),
         };
 
-        text.push_str("\n\n");
-        if !answer.is_correct {
-            text.push_str("\n❌ ");
-            text.push_str(answer_text);
+        let text = message.text().ok_or(BotErrors::NoMessageFound)?;
+        if !last_gap {
+            let mut text = text.to_owned();
+            text.push_str(&gap_result(
+                gap,
+                answer.is_correct,
+                &answer_text,
+                &correct_text,
+                explanation,
+            ));
+
+            let mut call = bot.edit_message_text(chat_id, message.id, text).reply_markup(
+                buttons_markup(build_gap_buttons(
+                    &task,
+                    gap + 1,
+                    self.distractors,
+                    answer.time_asked_ts,
+                    answer.earlier_gap_wrong || !answer.is_correct,
+                )?)
+                .append_row(self.task_buttons(&task)),
+            );
+            if let Some(entities) = message.entities() {
+                call = call.entities(entities.to_vec());
+            }
+
+            let (send, record) = join!(call.send_measured(), record_answer);
+            send?;
+            record?;
+            return Ok(());
         }
-        text.push_str("\n✅ ");
-        text.push_str(correct_text);
 
+        let (mut text, fixed_entities) = reveal_question(text, message.entities());
+
+        if gaps_count > 1 {
+            text.push_str(&gap_result(
+                gap,
+                answer.is_correct,
+                &answer_text,
+                &correct_text,
+                explanation,
+            ));
+        } else {
+            text.push_str("\n\n");
+            if !answer.is_correct {
+                text.push_str("\n❌ ");
+                text.push_str(&answer_text);
+            }
+            text.push_str("\n✅ ");
+            text.push_str(&correct_text);
+            if let Some(explanation) = explanation {
+                text.push_str("\n💡 ");
+                text.push_str(explanation);
+            }
+        }
+        text.push_str("\n\n📝 ");
+        text.push_str(&task.task);
+
+        let report_markup = InlineKeyboardMarkup::new([self.task_buttons(&task)]);
         bot.edit_message_reply_markup(chat_id, message.id)
-            .reply_markup(InlineKeyboardMarkup::default())
-            .send()
+            .reply_markup(report_markup.clone())
+            .send_measured()
             .await?;
 
-        let fixed_entities = message.entities().map(|entities| {
-            entities
-                .iter()
-                .filter(|entity| !matches!(entity.kind, MessageEntityKind::Spoiler))
-                .map(|entity| MessageEntity {
-                    kind: entity.kind.clone(),
-                    offset: entity.offset - entities_offset,
-                    length: entity.length,
-                })
-                .collect::<Vec<_>>()
-        });
-
-        let mut call = bot.edit_message_text(chat_id, message.id, text);
+        let mut call = bot.edit_message_text(chat_id, message.id, text).reply_markup(report_markup);
         if let Some(entities) = fixed_entities {
             call = call.entities(entities)
         }
 
-        let time = OffsetDateTime::from_unix_timestamp(answer.time_asked_ts / 1000)?
-            + Duration::from_millis((answer.time_asked_ts % 1000) as u64);
-
-        let record_answer = self.user_data.record_anwer(Answer {
-            uid: user_id.0 as i64,
-            task_id: answer.task_id,
-            correct: answer.is_correct,
-            asked_at: time,
-            answered_at: OffsetDateTime::now_utc(),
-        });
-
-        let (send, record) = join!(call.send(), record_answer);
+        let (send, record) = join!(call.send_measured(), record_answer);
         send?;
         record?;
+        self.reward_answer(bot, chat_id, message.id, user_id.0 as i64, &task, task_correct)
+            .await?;
+
+        if blitz == BlitzAnswer::Accepted {
+            self.ask_next_task(bot, chat_id).await?;
+            return Ok(());
+        }
+
+        match placement {
+            PlacementAnswer::Accepted => {
+                self.ask_next_task(bot, chat_id).await?;
+                return Ok(());
+            }
+            PlacementAnswer::Finished(answers) => {
+                self.finish_placement(bot, chat_id, &answers).await?;
+                return Ok(());
+            }
+            PlacementAnswer::NotInPlacement => {}
+        }
 
         let stat = self
             .user_data
             .get_answer_stat(user_id.0 as i64, Duration::from_secs(60 * 60 * 24))
             .await?;
 
-        if stat.count % 5 == 0 {
+        if self.stats_every > 0 && stat.count % self.stats_every as i64 == 0 {
             let percent = stat.correct * 100 / stat.count;
             let progress = match percent {
                 0..=30 => "Поднажмём! 🥺",
@@ -315,11 +627,14 @@ impl<T: TaskInfoService, U: UserStateService> BotContext<T, U> {
                     tasks = rus_numeric(stat.count as usize, "задач", "задача", "задачи"),
                 ),
             )
-            .send()
+            .send_measured()
             .await?;
         }
 
-        tokio::time::sleep(Duration::from_secs(1)).await;
+        // Unlocks don't wait for the stats message, the check is cheap for tasks outside lessons
+        self.check_lesson_unlocks(bot, chat_id, user_id.0 as i64, task.id).await?;
+
+        tokio::time::sleep(self.answer_delay).await;
 
         self.ask_next_task(bot, chat_id).await?;
 
@@ -337,6 +652,8 @@ impl<T: TaskInfoService, U: UserStateService> BotContext<T, U> {
             Ok(_) => Ok(()),
             Err(err) => {
                 log::error!("Error: {}", err);
+                metrics().handler_errors.with_label_values(&[error_kind(&err)]).inc();
+                self.record_error(chat_id, err.to_string());
 
                 bot.send_message(
                     chat_id,
@@ -345,7 +662,7 @@ impl<T: TaskInfoService, U: UserStateService> BotContext<T, U> {
                         err
                     ),
                 )
-                .send()
+                .send_measured()
                 .await?;
                 Ok(())
             }
@@ -353,7 +670,95 @@ impl<T: TaskInfoService, U: UserStateService> BotContext<T, U> {
     }
 }
 
-fn parse_command(command: &str) -> Result<proto::Command> {
+/// Strips the question prelude and uncovers spoilers, so the question can be shown with its answer.
+pub(super) fn reveal_question(text: &str, entities: Option<&[MessageEntity]>) -> (String, Option<Vec<MessageEntity>>) {
+    let (text, entities_offset) = if let Some(prefix) = text.strip_prefix(QUESTION_PRELUDE) {
+        (prefix.to_owned(), QUESTION_PRELUDE.chars().count())
+    } else {
+        (text.to_owned(), 0)
+    };
+
+    let fixed_entities = entities.map(|entities| {
+        entities
+            .iter()
+            .filter(|entity| !matches!(entity.kind, MessageEntityKind::Spoiler))
+            .map(|entity| MessageEntity {
+                kind: entity.kind.clone(),
+                offset: entity.offset - entities_offset,
+                length: entity.length,
+            })
+            .collect::<Vec<_>>()
+    });
+
+    (text, fixed_entities)
+}
+
+fn gap_result(
+    gap: usize,
+    is_correct: bool,
+    answer_text: &str,
+    correct_text: &str,
+    explanation: Option<&str>,
+) -> String {
+    let mut result = if gap == 0 { "\n\n".to_owned() } else { "\n".to_owned() };
+    result.push_str(&format!("{}) ", gap + 1));
+    if !is_correct {
+        result.push_str("❌ ");
+        result.push_str(answer_text);
+        result.push(' ');
+    }
+    result.push_str("✅ ");
+    result.push_str(correct_text);
+    if let Some(explanation) = explanation {
+        result.push_str("\n💡 ");
+        result.push_str(explanation);
+    }
+    result
+}
+
+pub(super) fn is_group_chat(chat: &teloxide::types::Chat) -> bool {
+    chat.is_group() || chat.is_supergroup()
+}
+
+/// Finds the chosen and the correct answer texts among the question buttons.
+pub(super) fn answer_texts(message: &teloxide::types::Message, index: i32) -> Result<(String, String)> {
+    let buttons = &message
+        .reply_markup()
+        .ok_or(BotErrors::NoReplyMarkup)
+        .context("reply_markup")?
+        .inline_keyboard;
+
+    let mut correct_text = &buttons[0][0].text;
+    let mut answer_text = &buttons[0][0].text;
+    for button in buttons {
+        let button_command = if let InlineKeyboardButtonKind::CallbackData(command) = &button[0].kind {
+            parse_command(command)?
+        } else {
+            return Err(BotErrors::BadCallbackDataInButton.into());
+        };
+        let button_command = button_command.command.ok_or(BotErrors::WrongQuery)?;
+
+        let Command::QuestionAnswer(button_answer) = button_command else {
+            continue;
+        };
+
+        if button_answer.index == index {
+            answer_text = &button[0].text;
+        }
+        if button_answer.is_correct {
+            correct_text = &button[0].text;
+        }
+    }
+
+    Ok((answer_text.clone(), correct_text.clone()))
+}
+
+pub(super) fn encode_command(command: Command) -> String {
+    let command = proto::Command { command: Some(command) };
+    STANDARD.encode(command.encode_to_vec())
+}
+
+pub(super) fn parse_command(command: &str) -> Result<proto::Command> {
     let command = STANDARD.decode(command)?;
     let command = proto::Command::decode(&command[..])?;
     Ok(command)
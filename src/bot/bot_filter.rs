@@ -10,6 +10,14 @@ pub struct FilterGroup {
 #[derive(Debug, PartialEq, Default)]
 pub struct Filter {
     pub groups: Vec<FilterGroup>,
+    /// Leaves out multi-gap tasks, group members answer a question only once
+    pub single_gap: bool,
+}
+
+impl Filter {
+    pub fn matches(&self, task: &Task) -> bool {
+        (!self.single_gap || task.gaps.len() <= 1) && match_task(&task.filters, self)
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -77,12 +85,16 @@ pub(super) fn parse_filter(filter: &str) -> Filter {
         }
         groups.push(FilterGroup { values });
     }
-    Filter { groups }
+    Filter {
+        groups,
+        single_gap: false,
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::model::Gap;
 
     #[test]
     fn test_parse_filter() {
@@ -161,6 +173,37 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_single_gap() {
+        let gap = |correct: &str| Gap {
+            correct: correct.into(),
+            wrong_answers: Vec::new(),
+        };
+        let mut task = Task {
+            id: 1,
+            hash: 1,
+            task: "a b".into(),
+            masked_task: "***** *****".into(),
+            correct: String::new(),
+            base: String::new(),
+            info: Vec::new(),
+            hints: Vec::new(),
+            filters: vec![FilterValue {
+                name: "test".into(),
+                value: "a".into(),
+            }],
+            wrong_answers: Vec::new(),
+            gaps: vec![gap("a"), gap("b")],
+        };
+
+        let mut filter = parse_filter("a");
+        assert!(filter.matches(&task));
+        filter.single_gap = true;
+        assert!(!filter.matches(&task));
+        task.gaps.truncate(1);
+        assert!(filter.matches(&task));
+    }
+
     #[test]
     fn test_match() {
         let filter = parse_filter("a,b,c; d,e,f");
@@ -1,13 +1,18 @@
-use std::{future::Future, time::Duration};
+use std::{
+    collections::{BTreeMap, HashMap},
+    future::Future,
+    time::Duration,
+};
 
-use teloxide::types::ChatId;
-use time::OffsetDateTime;
+use serde::{Deserialize, Serialize};
+use teloxide::types::{ChatId, MessageId};
+use time::{Date, OffsetDateTime};
 
-use crate::model::{Task, TaskId};
+use crate::model::{FilterValue, Task, TaskId};
 
 use super::bot_filter::{Filter, FilterInfo};
 
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct UserData {
     pub filter: Option<String>,
 }
@@ -18,11 +23,13 @@ impl UserData {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UserInfo {
     pub uid: i64,
     pub username: Option<String>,
     pub full_name: String,
+    pub language_code: Option<String>,
+    pub is_premium: bool,
     pub created_at: OffsetDateTime,
     pub last_active_at: OffsetDateTime,
 }
@@ -34,6 +41,8 @@ impl Default for UserInfo {
             uid: 0,
             username: None,
             full_name: "".into(),
+            language_code: None,
+            is_premium: false,
             created_at: now,
             last_active_at: now,
         }
@@ -46,27 +55,90 @@ impl UserInfo {
             uid: user.id.0 as i64,
             username: user.username.clone(),
             full_name: user.full_name(),
+            language_code: user.language_code.clone(),
+            is_premium: user.is_premium,
             created_at: OffsetDateTime::now_utc(),
             last_active_at: OffsetDateTime::now_utc(),
         }
     }
 
+    /// Profile fields that differ in the updated info, booleans as `true` and `false`.
+    pub fn profile_changes(&self, updated: &UserInfo, changed_at: OffsetDateTime) -> Vec<ProfileChange> {
+        let fields = [
+            ("username", self.username.clone(), updated.username.clone()),
+            (
+                "full_name",
+                Some(self.full_name.clone()),
+                Some(updated.full_name.clone()),
+            ),
+            (
+                "language_code",
+                self.language_code.clone(),
+                updated.language_code.clone(),
+            ),
+            (
+                "is_premium",
+                Some(self.is_premium.to_string()),
+                Some(updated.is_premium.to_string()),
+            ),
+        ];
+        fields
+            .into_iter()
+            .filter(|(_, old_value, new_value)| old_value != new_value)
+            .map(|(field, old_value, new_value)| ProfileChange {
+                field: field.to_owned(),
+                old_value,
+                new_value,
+                changed_at,
+            })
+            .collect()
+    }
+
     #[cfg(test)]
     pub fn new(uid: i64, username: Option<&str>, full_name: &str) -> Self {
         Self {
             uid,
             username: username.map(|s| s.into()),
             full_name: full_name.into(),
+            language_code: None,
+            is_premium: false,
             created_at: OffsetDateTime::now_utc(),
             last_active_at: OffsetDateTime::now_utc(),
         }
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct TaskQueue {
+    pub remaining: usize,
+    /// First tasks in the order they are taken
+    pub next: Vec<QueuedTask>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QueuedTask {
+    /// Zero for the task taken next
+    pub position: usize,
+    pub task_id: TaskId,
+}
+
+/// Change of a profile field, `None` for an empty value.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProfileChange {
+    pub field: String,
+    pub old_value: Option<String>,
+    pub new_value: Option<String>,
+    pub changed_at: OffsetDateTime,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Answer {
     pub uid: i64,
     pub task_id: i64,
+    pub gap: i32,
+    /// Chosen button, the text is kept as distractors are picked randomly
+    pub answer_index: i32,
+    pub answer_text: Option<String>,
     pub correct: bool,
     pub asked_at: OffsetDateTime,
     pub answered_at: OffsetDateTime,
@@ -78,22 +150,365 @@ pub struct AnswerStat {
     pub correct: i64,
 }
 
-pub trait UserStateService: std::fmt::Debug + Sync + Send {
+/// Answers of a task, every gap of multi-gap tasks counts as an answer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TaskAnswerCount {
+    pub task_id: TaskId,
+    pub answered: i64,
+    pub correct: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlitzResult {
+    pub uid: i64,
+    pub duration_secs: i32,
+    pub answered: i64,
+    pub correct: i64,
+    pub avg_response_ms: i64,
+    pub finished_at: OffsetDateTime,
+}
+
+#[derive(Debug, Clone)]
+pub struct GroupScore {
+    pub uid: i64,
+    pub full_name: String,
+    pub answered: i64,
+    pub correct: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct LeaderboardEntry {
+    pub uid: i64,
+    pub full_name: String,
+    pub answered: i64,
+    pub correct: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct XpLeaderboardEntry {
+    pub uid: i64,
+    pub full_name: String,
+    pub xp: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct DailyStat {
+    pub day: Date,
+    pub active_users: i64,
+    pub answered: i64,
+    pub correct: i64,
+}
+
+/// Answers statistics of a task, used to find too easy, too hard or broken tasks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TaskStats {
+    pub task_id: TaskId,
+    pub answered: i64,
+    pub correct: i64,
+    pub median_response: Duration,
+    /// Wrong answers with the number of times they were picked, most picked first
+    pub distractors: Vec<(String, i64)>,
+}
+
+impl TaskStats {
+    pub fn accuracy(&self) -> f64 {
+        match self.answered {
+            0 => 0.0,
+            answered => self.correct as f64 / answered as f64,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BotStats {
+    pub total_users: i64,
+    pub new_users: i64,
+    pub daily: Vec<DailyStat>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum BroadcastStatus {
+    Draft,
+    Sending,
+    Done,
+    Cancelled,
+}
+
+impl BroadcastStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BroadcastStatus::Draft => "draft",
+            BroadcastStatus::Sending => "sending",
+            BroadcastStatus::Done => "done",
+            BroadcastStatus::Cancelled => "cancelled",
+        }
+    }
+
+    pub fn parse(status: &str) -> anyhow::Result<Self> {
+        match status {
+            "draft" => Ok(BroadcastStatus::Draft),
+            "sending" => Ok(BroadcastStatus::Sending),
+            "done" => Ok(BroadcastStatus::Done),
+            "cancelled" => Ok(BroadcastStatus::Cancelled),
+            _ => Err(anyhow::format_err!("Unknown broadcast status {status}")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Broadcast {
+    pub id: i64,
+    pub author_uid: i64,
+    pub text: String,
+    pub status: BroadcastStatus,
+    /// Chats are delivered in chat id order, so the last one is enough to resume
+    pub last_chat_id: Option<i64>,
+    pub sent: i64,
+    pub failed: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeedbackMessage {
+    pub chat_id: ChatId,
+    pub from_admin: bool,
+    pub text: String,
+    pub created_at: OffsetDateTime,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TaskReportReason {
+    WrongAnswer,
+    BadDistractor,
+    BadTranslation,
+    Typo,
+}
+
+impl TaskReportReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TaskReportReason::WrongAnswer => "wrong_answer",
+            TaskReportReason::BadDistractor => "bad_distractor",
+            TaskReportReason::BadTranslation => "bad_translation",
+            TaskReportReason::Typo => "typo",
+        }
+    }
+
+    pub fn parse(reason: &str) -> anyhow::Result<Self> {
+        match reason {
+            "wrong_answer" => Ok(TaskReportReason::WrongAnswer),
+            "bad_distractor" => Ok(TaskReportReason::BadDistractor),
+            "bad_translation" => Ok(TaskReportReason::BadTranslation),
+            "typo" => Ok(TaskReportReason::Typo),
+            _ => Err(anyhow::format_err!("Unknown task report reason {reason}")),
+        }
+    }
+}
+
+pub trait UserStateService: std::fmt::Debug + Sync + Send + 'static {
+    /// Saves the user profile and the activity time, returns true for a new user.
+    /// Changed profile fields of a known user are logged, see [`UserStateService::get_profile_changes`].
     fn touch_user(&self, user: &UserInfo) -> impl Future<Output = anyhow::Result<bool>> + Send;
+    /// Last profile changes of the user, oldest first.
+    fn get_profile_changes(
+        &self,
+        user_id: i64,
+        limit: usize,
+    ) -> impl Future<Output = anyhow::Result<Vec<ProfileChange>>> + Send;
     fn get_state(&self, chat_id: ChatId) -> impl Future<Output = anyhow::Result<UserData>> + Send;
     fn update_state(&self, chat_id: ChatId, update: UserData) -> impl Future<Output = anyhow::Result<()>> + Send;
+    /// Replaces the task queue of the chat, tasks are taken in the given order, duplicates included.
     fn update_tasks(&self, chat_id: ChatId, tasks: &[TaskId]) -> impl Future<Output = anyhow::Result<()>> + Send;
+    /// Removes and returns the first task of the queue, other copies of the same task stay in the queue.
     fn take_next_task(&self, chat_id: ChatId) -> impl Future<Output = anyhow::Result<Option<TaskId>>> + Send;
+    /// Size of the queue and up to `limit` first tasks, without taking them.
+    fn peek_tasks(&self, chat_id: ChatId, limit: usize) -> impl Future<Output = anyhow::Result<TaskQueue>> + Send;
     fn record_anwer(&self, answer: Answer) -> impl Future<Output = anyhow::Result<()>> + Send;
+    /// All answers during the period, oldest first, for analytics.
+    fn get_answers(&self, period: Duration) -> impl Future<Output = anyhow::Result<Vec<Answer>>> + Send;
+    /// Answers per task during the period, ordered by task id. Every gap of multi-gap tasks counts as an answer.
+    fn get_task_stats(&self, period: Duration) -> impl Future<Output = anyhow::Result<Vec<TaskStats>>> + Send;
+    /// Answered tasks during the period and how many of them had every gap correct.
     fn get_answer_stat(
         &self,
         user_id: i64,
         period: Duration,
     ) -> impl Future<Output = anyhow::Result<AnswerStat>> + Send;
+    /// Answer counts per task of the user over all time.
+    fn get_task_answer_counts(&self, user_id: i64)
+        -> impl Future<Output = anyhow::Result<Vec<TaskAnswerCount>>> + Send;
+    /// Answer counts per task of all users over all time, scans every answer so callers cache it.
+    fn get_global_task_answer_counts(&self) -> impl Future<Output = anyhow::Result<Vec<TaskAnswerCount>>> + Send;
+    fn get_task_answer_count(&self, task_id: TaskId) -> impl Future<Output = anyhow::Result<TaskAnswerCount>> + Send;
+    /// Tasks answered by the user over all time, a multi-gap task counts once.
+    fn count_answered_tasks(&self, user_id: i64) -> impl Future<Output = anyhow::Result<i64>> + Send;
+    /// Distinct UTC days with answers of the user, latest first.
+    fn get_answer_days(&self, user_id: i64, limit: usize) -> impl Future<Output = anyhow::Result<Vec<Date>>> + Send;
+    /// Adds experience points to the user, returns the new total. Every award is kept for weekly rankings.
+    fn add_xp(&self, user_id: i64, xp: i64) -> impl Future<Output = anyhow::Result<i64>> + Send;
+    fn get_xp(&self, user_id: i64) -> impl Future<Output = anyhow::Result<i64>> + Send;
+    /// Returns false if the user already had the achievement.
+    fn award_achievement(
+        &self,
+        user_id: i64,
+        achievement_id: &str,
+    ) -> impl Future<Output = anyhow::Result<bool>> + Send;
+    /// Earned achievements with the time they were earned, oldest first.
+    fn get_achievements(
+        &self,
+        user_id: i64,
+    ) -> impl Future<Output = anyhow::Result<Vec<(String, OffsetDateTime)>>> + Send;
+    fn record_blitz_result(&self, result: BlitzResult) -> impl Future<Output = anyhow::Result<()>> + Send;
+    /// Best result by correct answers, then by accuracy, among blitzes of the same duration.
+    fn get_best_blitz_result(
+        &self,
+        user_id: i64,
+        duration_secs: i32,
+    ) -> impl Future<Output = anyhow::Result<Option<BlitzResult>>> + Send;
+    /// Adds answers to the group leaderboard, `results` are pairs of user id and correctness.
+    fn update_group_scores(
+        &self,
+        chat_id: ChatId,
+        results: &[(i64, bool)],
+    ) -> impl Future<Output = anyhow::Result<()>> + Send;
+    fn get_group_scoreboard(
+        &self,
+        chat_id: ChatId,
+        limit: usize,
+    ) -> impl Future<Output = anyhow::Result<Vec<GroupScore>>> + Send;
+    fn set_public_profile(&self, user_id: i64, public: bool) -> impl Future<Output = anyhow::Result<()>> + Send;
+    fn is_public_profile(&self, user_id: i64) -> impl Future<Output = anyhow::Result<bool>> + Send;
+    /// Makes users friends of each other, returns false if they already were or the friend is unknown.
+    fn add_friend(&self, user_id: i64, friend_id: i64) -> impl Future<Output = anyhow::Result<bool>> + Send;
+    fn get_friends(&self, user_id: i64) -> impl Future<Output = anyhow::Result<Vec<i64>>> + Send;
+    /// Invite token of the user, `new_token` is stored if the user has none yet.
+    fn get_invite_token(&self, user_id: i64, new_token: &str) -> impl Future<Output = anyhow::Result<String>> + Send;
+    /// The user who shared the invite token.
+    fn find_invite_owner(&self, token: &str) -> impl Future<Output = anyhow::Result<Option<i64>>> + Send;
+    /// Ids of the curriculum lessons unlocked by the user.
+    fn get_unlocked_lessons(&self, user_id: i64) -> impl Future<Output = anyhow::Result<Vec<String>>> + Send;
+    /// Returns false if the lesson was already unlocked.
+    fn unlock_lesson(&self, user_id: i64, lesson_id: &str) -> impl Future<Output = anyhow::Result<bool>> + Send;
+    /// Ranks users by correct answers during the period. Without `user_ids` only public profiles are ranked.
+    fn get_leaderboard(
+        &self,
+        period: Duration,
+        user_ids: Option<&[i64]>,
+        limit: usize,
+    ) -> impl Future<Output = anyhow::Result<Vec<LeaderboardEntry>>> + Send;
+    /// Ranks users by XP earned during the period, visibility rules are the same as in [`UserStateService::get_leaderboard`].
+    fn get_xp_leaderboard(
+        &self,
+        period: Duration,
+        user_ids: Option<&[i64]>,
+        limit: usize,
+    ) -> impl Future<Output = anyhow::Result<Vec<XpLeaderboardEntry>>> + Send;
+    /// Users count and activity per UTC day during the period, ordered by day.
+    fn get_bot_stats(&self, period: Duration) -> impl Future<Output = anyhow::Result<BotStats>> + Send;
+    /// Case-insensitive lookup by Telegram username without the `@`.
+    fn find_user(&self, username: &str) -> impl Future<Output = anyhow::Result<Option<UserInfo>>> + Send;
+    fn create_broadcast(&self, author_uid: i64, text: &str) -> impl Future<Output = anyhow::Result<i64>> + Send;
+    fn get_broadcast(&self, id: i64) -> impl Future<Output = anyhow::Result<Option<Broadcast>>> + Send;
+    fn get_broadcasts(&self, status: BroadcastStatus) -> impl Future<Output = anyhow::Result<Vec<Broadcast>>> + Send;
+    /// Changes the status only if it is `from` now, returns whether it was changed.
+    fn update_broadcast_status(
+        &self,
+        id: i64,
+        from: BroadcastStatus,
+        to: BroadcastStatus,
+    ) -> impl Future<Output = anyhow::Result<bool>> + Send;
+    /// Chats to deliver broadcasts to, ordered by chat id. Blocked chats are skipped until the user is active again.
+    fn get_broadcast_chats(
+        &self,
+        after_chat_id: Option<i64>,
+        limit: usize,
+    ) -> impl Future<Output = anyhow::Result<Vec<ChatId>>> + Send;
+    fn count_broadcast_chats(&self) -> impl Future<Output = anyhow::Result<i64>> + Send;
+    /// Moves the broadcast progress past the chat.
+    fn record_broadcast_delivery(
+        &self,
+        id: i64,
+        chat_id: ChatId,
+        delivered: bool,
+    ) -> impl Future<Output = anyhow::Result<()>> + Send;
+    fn record_blocked_chat(&self, chat_id: ChatId) -> impl Future<Output = anyhow::Result<()>> + Send;
+    /// Stores a feedback conversation message, `feedback_message_id` is its message in the feedback chat.
+    fn record_feedback(
+        &self,
+        chat_id: ChatId,
+        from_admin: bool,
+        text: &str,
+        feedback_message_id: MessageId,
+    ) -> impl Future<Output = anyhow::Result<()>> + Send;
+    /// Finds the user chat of the conversation by a message in the feedback chat.
+    fn find_feedback_chat(
+        &self,
+        feedback_message_id: MessageId,
+    ) -> impl Future<Output = anyhow::Result<Option<ChatId>>> + Send;
+    /// Last messages of the conversation with the user, oldest first.
+    fn get_feedback_history(
+        &self,
+        chat_id: ChatId,
+        limit: usize,
+    ) -> impl Future<Output = anyhow::Result<Vec<FeedbackMessage>>> + Send;
+    /// Returns the report id, or none if the user already reported the task for the same reason.
+    fn record_task_report(
+        &self,
+        uid: i64,
+        task_id: TaskId,
+        reason: TaskReportReason,
+    ) -> impl Future<Output = anyhow::Result<Option<i64>>> + Send;
+    fn set_task_report_message(
+        &self,
+        report_id: i64,
+        feedback_message_id: MessageId,
+    ) -> impl Future<Output = anyhow::Result<()>> + Send;
+    /// Finds the reported task by its notification in the feedback chat.
+    fn find_reported_task(
+        &self,
+        feedback_message_id: MessageId,
+    ) -> impl Future<Output = anyhow::Result<Option<TaskId>>> + Send;
+    /// Unresolved reports of the task counted by reason.
+    fn get_task_report_counts(
+        &self,
+        task_id: TaskId,
+    ) -> impl Future<Output = anyhow::Result<Vec<(TaskReportReason, i64)>>> + Send;
+    /// Tasks with unresolved reports and their report counts, most reported first.
+    fn get_reported_tasks(&self, limit: usize) -> impl Future<Output = anyhow::Result<Vec<(TaskId, i64)>>> + Send;
+    fn resolve_task_reports(&self, task_id: TaskId) -> impl Future<Output = anyhow::Result<()>> + Send;
+    /// Everything stored about the user and their private chat, rows as JSON grouped by the table.
+    fn export_user_data(
+        &self,
+        user_id: i64,
+    ) -> impl Future<Output = anyhow::Result<BTreeMap<String, serde_json::Value>>> + Send;
+    /// Deletes everything [`UserStateService::export_user_data`] returns.
+    fn forget_user(&self, user_id: i64) -> impl Future<Output = anyhow::Result<()>> + Send;
+    /// Writes buffered state to storage, called periodically and on shutdown.
+    /// Databases store every change right away and have nothing to do.
+    fn flush(&self) -> impl Future<Output = anyhow::Result<()>> + Send;
 }
 
-pub trait TaskInfoService: std::fmt::Debug + Sync + Send {
+pub trait TaskInfoService: std::fmt::Debug + Sync + Send + 'static {
+    /// Active tasks matching the filter, sorted by id.
     fn get_task_ids(&self, filter: Option<&Filter>) -> impl Future<Output = anyhow::Result<Vec<TaskId>>> + Send;
     fn collect_filter_info(&self) -> impl Future<Output = anyhow::Result<Vec<FilterInfo>>> + Send;
+    /// Random active tasks, up to `per_value` for every filter value, sorted by id. Tasks without filters are left out.
+    fn sample_task_ids(&self, per_value: usize) -> impl Future<Output = anyhow::Result<Vec<TaskId>>> + Send;
     fn get_task(&self, id: i64) -> impl Future<Output = anyhow::Result<Option<Task>>> + Send;
+    /// Tasks with the ids including inactive ones, unknown ids are skipped.
+    fn get_tasks(&self, ids: &[TaskId]) -> impl Future<Output = anyhow::Result<Vec<Task>>> + Send;
+    /// Filter values of the tasks without loading the whole tasks, unknown ids are skipped.
+    fn get_task_filters(
+        &self,
+        ids: &[TaskId],
+    ) -> impl Future<Output = anyhow::Result<HashMap<TaskId, Vec<FilterValue>>>> + Send;
+    /// Upserts tasks by hash and deactivates missing ones, returns updated and deactivated counts.
+    fn update_tasks(&self, tasks: &[Task]) -> impl Future<Output = anyhow::Result<(u64, u64)>> + Send;
+    /// Stops giving the task in new queues, task imports keep it deactivated.
+    /// Returns false for unknown tasks and tasks already deactivated by an admin.
+    fn deactivate_task(&self, id: TaskId, disabled_by: i64) -> impl Future<Output = anyhow::Result<bool>> + Send;
+    /// Id of the task with the hash from the task files, active or not.
+    fn find_task_by_hash(&self, hash: i64) -> impl Future<Output = anyhow::Result<Option<TaskId>>> + Send;
+    /// Sets the moderator note of the task, returns false for unknown tasks.
+    fn annotate_task(&self, id: TaskId, annotation: &str) -> impl Future<Output = anyhow::Result<bool>> + Send;
+    fn get_task_annotation(&self, id: TaskId) -> impl Future<Output = anyhow::Result<Option<String>>> + Send;
 }
@@ -1,84 +1,340 @@
 use std::{
-    collections::{hash_map::Entry, HashMap},
-    sync::Mutex,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
+    io::ErrorKind,
+    path::PathBuf,
+    sync::{Mutex, RwLock},
+    time::Duration,
 };
 
-use rand::seq::SliceRandom;
-use teloxide::types::ChatId;
-use time::OffsetDateTime;
+use anyhow::Context;
+use rand::{seq::SliceRandom, thread_rng};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use teloxide::types::{ChatId, MessageId};
+use time::{format_description::well_known::Rfc3339, Date, OffsetDateTime};
 
-use crate::model::{Task, TaskId};
+use crate::{
+    analytics::task_stats,
+    model::{FilterValue, Task, TaskId},
+};
 
 use super::{
-    bot_filter::{collect_filter_info, match_task, Filter, FilterInfo},
-    bot_services::{Answer, AnswerStat, TaskInfoService, UserData, UserInfo, UserStateService},
+    bot_filter::{collect_filter_info, Filter, FilterInfo},
+    bot_services::{
+        Answer, AnswerStat, BlitzResult, BotStats, Broadcast, BroadcastStatus, DailyStat, FeedbackMessage, GroupScore,
+        LeaderboardEntry, ProfileChange, QueuedTask, TaskAnswerCount, TaskInfoService, TaskQueue, TaskReportReason,
+        TaskStats, UserData, UserInfo, UserStateService, XpLeaderboardEntry,
+    },
 };
 
 #[derive(Debug)]
 pub struct LocalTasks {
+    tasks: RwLock<LocalTasksState>,
+}
+
+#[derive(Debug, Default)]
+struct LocalTasksState {
+    /// Deactivated tasks are kept, as they still might be in the queues
     tasks: Vec<Task>,
+    /// Tasks missing from the last update
+    inactive: HashSet<TaskId>,
+    /// Tasks deactivated by admins, updates keep them
+    disabled: HashMap<TaskId, i64>,
+    annotations: HashMap<TaskId, String>,
+}
+
+impl LocalTasksState {
+    fn active_tasks(&self) -> impl Iterator<Item = &Task> {
+        self.tasks
+            .iter()
+            .filter(|task| !self.inactive.contains(&task.id) && !self.disabled.contains_key(&task.id))
+    }
 }
 
 impl LocalTasks {
-    pub fn new(mut tasks: Vec<Task>) -> Self {
-        for task in &mut tasks {
+    pub fn new(tasks: Vec<Task>) -> Self {
+        let local_tasks = Self {
+            tasks: RwLock::default(),
+        };
+        local_tasks.replace_tasks(tasks);
+        local_tasks
+    }
+
+    fn replace_tasks(&self, tasks: Vec<Task>) -> (u64, u64) {
+        let mut state = self.tasks.write().unwrap();
+        let mut ids = HashSet::new();
+        for mut task in tasks {
             task.id = task.hash;
+            ids.insert(task.id);
+            state.inactive.remove(&task.id);
+            match state.tasks.iter_mut().find(|existing| existing.id == task.id) {
+                Some(existing) => *existing = task,
+                None => state.tasks.push(task),
+            }
+        }
+
+        let mut deactivated = 0;
+        for task_id in state.tasks.iter().map(|task| task.id).collect::<Vec<_>>() {
+            if !ids.contains(&task_id) && state.inactive.insert(task_id) {
+                deactivated += 1;
+            }
         }
-        Self { tasks }
+        (ids.len() as u64, deactivated)
     }
 }
 
 impl TaskInfoService for LocalTasks {
     async fn get_task_ids(&self, filter: Option<&Filter>) -> anyhow::Result<Vec<TaskId>> {
-        let mut rng = rand::thread_rng();
         let mut task_ids = self
             .tasks
-            .iter()
-            .filter(|task| match_task(&task.filters, filter.unwrap_or(&Filter::default())))
+            .read()
+            .unwrap()
+            .active_tasks()
+            .filter(|task| filter.unwrap_or(&Filter::default()).matches(task))
             .map(|task| task.id)
             .collect::<Vec<_>>();
-        task_ids.shuffle(&mut rng);
+        task_ids.sort();
         Ok(task_ids)
     }
 
     async fn collect_filter_info(&self) -> anyhow::Result<Vec<FilterInfo>> {
-        Ok(collect_filter_info(&self.tasks))
+        let state = self.tasks.read().unwrap();
+        let tasks = state.active_tasks().cloned().collect::<Vec<_>>();
+        Ok(collect_filter_info(&tasks))
+    }
+
+    async fn sample_task_ids(&self, per_value: usize) -> anyhow::Result<Vec<TaskId>> {
+        let state = self.tasks.read().unwrap();
+        let mut by_value: HashMap<(&str, &str), Vec<TaskId>> = HashMap::new();
+        for task in state.active_tasks() {
+            for filter in &task.filters {
+                by_value.entry((&filter.name, &filter.value)).or_default().push(task.id);
+            }
+        }
+
+        let mut sample = BTreeSet::new();
+        for ids in by_value.into_values() {
+            sample.extend(ids.choose_multiple(&mut thread_rng(), per_value));
+        }
+        Ok(sample.into_iter().collect())
     }
 
     async fn get_task(&self, id: i64) -> anyhow::Result<Option<Task>> {
-        Ok(self.tasks.iter().find(|task| task.id == id).cloned())
+        Ok(self.tasks.read().unwrap().tasks.iter().find(|task| task.id == id).cloned())
+    }
+
+    async fn get_tasks(&self, ids: &[TaskId]) -> anyhow::Result<Vec<Task>> {
+        let ids = ids.iter().collect::<HashSet<_>>();
+        let state = self.tasks.read().unwrap();
+        Ok(state.tasks.iter().filter(|task| ids.contains(&task.id)).cloned().collect())
+    }
+
+    async fn get_task_filters(&self, ids: &[TaskId]) -> anyhow::Result<HashMap<TaskId, Vec<FilterValue>>> {
+        let ids = ids.iter().collect::<HashSet<_>>();
+        let state = self.tasks.read().unwrap();
+        Ok(state
+            .tasks
+            .iter()
+            .filter(|task| ids.contains(&task.id))
+            .map(|task| (task.id, task.filters.clone()))
+            .collect())
+    }
+
+    async fn update_tasks(&self, tasks: &[Task]) -> anyhow::Result<(u64, u64)> {
+        Ok(self.replace_tasks(tasks.to_vec()))
+    }
+
+    async fn deactivate_task(&self, id: TaskId, disabled_by: i64) -> anyhow::Result<bool> {
+        let mut state = self.tasks.write().unwrap();
+        if !state.tasks.iter().any(|task| task.id == id) || state.disabled.contains_key(&id) {
+            return Ok(false);
+        }
+        state.disabled.insert(id, disabled_by);
+        Ok(true)
+    }
+
+    async fn find_task_by_hash(&self, hash: i64) -> anyhow::Result<Option<TaskId>> {
+        // Local task ids are the hashes, but the tasks are still looked up
+        let state = self.tasks.read().unwrap();
+        Ok(state.tasks.iter().find(|task| task.hash == hash).map(|task| task.id))
+    }
+
+    async fn annotate_task(&self, id: TaskId, annotation: &str) -> anyhow::Result<bool> {
+        let mut state = self.tasks.write().unwrap();
+        if !state.tasks.iter().any(|task| task.id == id) {
+            return Ok(false);
+        }
+        state.annotations.insert(id, annotation.to_owned());
+        Ok(true)
+    }
+
+    async fn get_task_annotation(&self, id: TaskId) -> anyhow::Result<Option<String>> {
+        Ok(self.tasks.read().unwrap().annotations.get(&id).cloned())
     }
 }
 
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 struct ChatState {
     user_data: UserData,
-    tasks: Vec<TaskId>,
+    tasks: VecDeque<TaskId>,
+    /// Answered and correct counts per user in group chats
+    group_scores: HashMap<i64, (i64, i64)>,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 struct UserState {
-    #[allow(dead_code)]
     user_info: UserInfo,
     answers: Vec<Answer>,
+    blitz_results: Vec<BlitzResult>,
+    public_profile: bool,
+    friends: BTreeSet<i64>,
+    #[serde(default)]
+    invite_token: Option<String>,
+    unlocked_lessons: BTreeSet<String>,
+    xp: i64,
+    /// Every XP award and its time, for weekly rankings
+    #[serde(default)]
+    xp_awards: Vec<(i64, OffsetDateTime)>,
+    achievements: Vec<(String, OffsetDateTime)>,
+    profile_changes: Vec<ProfileChange>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct TaskReport {
+    id: i64,
+    uid: i64,
+    task_id: TaskId,
+    reason: TaskReportReason,
+    feedback_message_id: Option<MessageId>,
+    resolved: bool,
 }
 
 #[derive(Debug, Default)]
 pub struct LocalUserStateService {
     state: Mutex<HashMap<i64, ChatState>>,
     user_state: Mutex<HashMap<i64, UserState>>,
+    broadcasts: Mutex<Vec<Broadcast>>,
+    blocked_chats: Mutex<HashMap<i64, OffsetDateTime>>,
+    /// Feedback conversations with the feedback chat message ids
+    feedback: Mutex<Vec<(FeedbackMessage, MessageId)>>,
+    task_reports: Mutex<Vec<TaskReport>>,
+    snapshot_file: Option<SnapshotFile>,
+}
+
+/// Everything [`LocalUserStateService`] keeps, as written to the snapshot file.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Snapshot {
+    state: HashMap<i64, ChatState>,
+    user_state: HashMap<i64, UserState>,
+    broadcasts: Vec<Broadcast>,
+    blocked_chats: HashMap<i64, OffsetDateTime>,
+    feedback: Vec<(FeedbackMessage, MessageId)>,
+    task_reports: Vec<TaskReport>,
+}
+
+#[derive(Debug)]
+struct SnapshotFile {
+    path: PathBuf,
+    /// Last written JSON, unchanged state is not written again. Held while writing, so flushes do not interleave.
+    written: tokio::sync::Mutex<String>,
+}
+
+impl LocalUserStateService {
+    /// State kept in a JSON file, loaded from it if it exists and written back on [`UserStateService::flush`].
+    pub fn with_snapshot(path: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let path = path.into();
+        let snapshot: Snapshot = match std::fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json)
+                .with_context(|| format!("Failed to parse state snapshot {}", path.display()))?,
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                log::info!("No state snapshot at {}, starting empty", path.display());
+                Snapshot::default()
+            }
+            Err(err) => return Err(err).with_context(|| format!("Failed to read state snapshot {}", path.display())),
+        };
+        log::info!(
+            "Loaded state of {} chats and {} users from {}",
+            snapshot.state.len(),
+            snapshot.user_state.len(),
+            path.display()
+        );
+
+        Ok(Self {
+            state: Mutex::new(snapshot.state),
+            user_state: Mutex::new(snapshot.user_state),
+            broadcasts: Mutex::new(snapshot.broadcasts),
+            blocked_chats: Mutex::new(snapshot.blocked_chats),
+            feedback: Mutex::new(snapshot.feedback),
+            task_reports: Mutex::new(snapshot.task_reports),
+            snapshot_file: Some(SnapshotFile {
+                path,
+                written: Default::default(),
+            }),
+        })
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            state: self.state.lock().unwrap().clone(),
+            user_state: self.user_state.lock().unwrap().clone(),
+            broadcasts: self.broadcasts.lock().unwrap().clone(),
+            blocked_chats: self.blocked_chats.lock().unwrap().clone(),
+            feedback: self.feedback.lock().unwrap().clone(),
+            task_reports: self.task_reports.lock().unwrap().clone(),
+        }
+    }
+
+    fn broadcast_chats(&self) -> BTreeSet<i64> {
+        let state = self.state.lock().unwrap();
+        let user_state = self.user_state.lock().unwrap();
+        let blocked_chats = self.blocked_chats.lock().unwrap();
+        state
+            .keys()
+            .chain(
+                user_state
+                    .iter()
+                    .filter(|(uid, user)| user.user_info.uid == **uid)
+                    .map(|(uid, _)| uid),
+            )
+            .filter(|chat_id| match blocked_chats.get(chat_id) {
+                Some(blocked_at) => user_state
+                    .get(chat_id)
+                    .is_some_and(|user| user.user_info.last_active_at > *blocked_at),
+                None => true,
+            })
+            .cloned()
+            .collect()
+    }
 }
 
 impl UserStateService for LocalUserStateService {
     async fn touch_user(&self, user: &UserInfo) -> anyhow::Result<bool> {
-        let mut state = self.state.lock().unwrap();
-        let entry = state.entry(user.uid);
-        let is_new = matches!(entry, Entry::Vacant(_));
+        let mut state = self.user_state.lock().unwrap();
+        let user_state = state.entry(user.uid).or_default();
+        // Answers might be recorded before the user is touched, so check the stored info instead of the entry
+        let is_new = user_state.user_info.uid != user.uid;
         if is_new {
             log::info!("New user: {:?}", user);
+            user_state.user_info = user.clone();
+            return Ok(true);
         }
-        entry.or_default();
-        Ok(is_new)
+
+        let changes = user_state.user_info.profile_changes(user, user.last_active_at);
+        user_state.profile_changes.extend(changes);
+        user_state.user_info = UserInfo {
+            created_at: user_state.user_info.created_at,
+            ..user.clone()
+        };
+        Ok(false)
+    }
+
+    async fn get_profile_changes(&self, user_id: i64, limit: usize) -> anyhow::Result<Vec<ProfileChange>> {
+        let state = self.user_state.lock().unwrap();
+        let changes = state
+            .get(&user_id)
+            .map(|user| user.profile_changes.as_slice())
+            .unwrap_or_default();
+        Ok(changes[changes.len().saturating_sub(limit)..].to_vec())
     }
 
     async fn get_state(&self, chat_id: ChatId) -> anyhow::Result<UserData> {
@@ -97,14 +353,29 @@ impl UserStateService for LocalUserStateService {
     async fn update_tasks(&self, chat_id: ChatId, tasks: &[TaskId]) -> anyhow::Result<()> {
         let mut state = self.state.lock().unwrap();
         let user_state = state.entry(chat_id.0).or_default();
-        user_state.tasks = tasks.to_vec();
+        user_state.tasks = tasks.iter().copied().collect();
         Ok(())
     }
 
     async fn take_next_task(&self, chat_id: ChatId) -> anyhow::Result<Option<TaskId>> {
         let mut state = self.state.lock().unwrap();
         let user_state = state.entry(chat_id.0).or_default();
-        Ok(user_state.tasks.pop())
+        Ok(user_state.tasks.pop_front())
+    }
+
+    async fn peek_tasks(&self, chat_id: ChatId, limit: usize) -> anyhow::Result<TaskQueue> {
+        let state = self.state.lock().unwrap();
+        let tasks = state.get(&chat_id.0).map(|user_state| &user_state.tasks);
+        Ok(TaskQueue {
+            remaining: tasks.map_or(0, VecDeque::len),
+            next: tasks
+                .into_iter()
+                .flatten()
+                .take(limit)
+                .enumerate()
+                .map(|(position, &task_id)| QueuedTask { position, task_id })
+                .collect(),
+        })
     }
 
     async fn record_anwer(&self, answer: Answer) -> anyhow::Result<()> {
@@ -114,21 +385,807 @@ impl UserStateService for LocalUserStateService {
         Ok(())
     }
 
+    async fn get_answers(&self, period: Duration) -> anyhow::Result<Vec<Answer>> {
+        let state = self.user_state.lock().unwrap();
+        let from = OffsetDateTime::now_utc() - period;
+        let mut answers = state
+            .values()
+            .flat_map(|user| user.answers.iter())
+            .filter(|answer| answer.answered_at > from)
+            .cloned()
+            .collect::<Vec<_>>();
+        answers.sort_by_key(|answer| answer.answered_at);
+        Ok(answers)
+    }
+
+    async fn get_task_stats(&self, period: Duration) -> anyhow::Result<Vec<TaskStats>> {
+        Ok(task_stats(&self.get_answers(period).await?))
+    }
+
     async fn get_answer_stat(&self, user_id: i64, period: std::time::Duration) -> anyhow::Result<AnswerStat> {
         let mut state = self.user_state.lock().unwrap();
         let user_state = state.entry(user_id).or_default();
         let from = OffsetDateTime::now_utc() - period;
-        let mut count = 0;
-        let mut correct = 0;
-        for answer in &user_state.answers {
-            if answer.answered_at > from {
-                count += 1;
+        // Gaps of a task share the time it was asked
+        let mut tasks: HashMap<(TaskId, OffsetDateTime), bool> = HashMap::new();
+        for answer in user_state.answers.iter().filter(|answer| answer.answered_at > from) {
+            *tasks.entry((answer.task_id, answer.asked_at)).or_insert(true) &= answer.correct;
+        }
+
+        Ok(AnswerStat {
+            count: tasks.len() as i64,
+            correct: tasks.values().filter(|correct| **correct).count() as i64,
+        })
+    }
+
+    async fn get_task_answer_counts(&self, user_id: i64) -> anyhow::Result<Vec<TaskAnswerCount>> {
+        let state = self.user_state.lock().unwrap();
+        let answers = state.get(&user_id).map(|user_state| user_state.answers.as_slice());
+        Ok(task_answer_counts(answers.unwrap_or_default()))
+    }
+
+    async fn get_global_task_answer_counts(&self) -> anyhow::Result<Vec<TaskAnswerCount>> {
+        let state = self.user_state.lock().unwrap();
+        Ok(task_answer_counts(
+            state.values().flat_map(|user_state| &user_state.answers),
+        ))
+    }
+
+    async fn get_task_answer_count(&self, task_id: TaskId) -> anyhow::Result<TaskAnswerCount> {
+        let state = self.user_state.lock().unwrap();
+        let answers = state
+            .values()
+            .flat_map(|user_state| &user_state.answers)
+            .filter(|answer| answer.task_id == task_id);
+        let mut count = TaskAnswerCount {
+            task_id,
+            answered: 0,
+            correct: 0,
+        };
+        for answer in answers {
+            count.answered += 1;
+            count.correct += answer.correct as i64;
+        }
+        Ok(count)
+    }
+
+    async fn count_answered_tasks(&self, user_id: i64) -> anyhow::Result<i64> {
+        let state = self.user_state.lock().unwrap();
+        let answers = state.get(&user_id).map(|user_state| user_state.answers.as_slice());
+        // Gaps of a task share the time it was asked
+        let tasks = answers
+            .unwrap_or_default()
+            .iter()
+            .map(|answer| (answer.task_id, answer.asked_at))
+            .collect::<HashSet<_>>();
+        Ok(tasks.len() as i64)
+    }
+
+    async fn get_answer_days(&self, user_id: i64, limit: usize) -> anyhow::Result<Vec<Date>> {
+        let state = self.user_state.lock().unwrap();
+        let days = state
+            .get(&user_id)
+            .map(|user_state| {
+                user_state
+                    .answers
+                    .iter()
+                    .map(|answer| answer.answered_at.date())
+                    .collect::<BTreeSet<_>>()
+            })
+            .unwrap_or_default();
+        Ok(days.into_iter().rev().take(limit).collect())
+    }
+
+    async fn add_xp(&self, user_id: i64, xp: i64) -> anyhow::Result<i64> {
+        let mut state = self.user_state.lock().unwrap();
+        let user_state = state.entry(user_id).or_default();
+        user_state.xp += xp;
+        user_state.xp_awards.push((xp, OffsetDateTime::now_utc()));
+        Ok(user_state.xp)
+    }
+
+    async fn get_xp(&self, user_id: i64) -> anyhow::Result<i64> {
+        let state = self.user_state.lock().unwrap();
+        Ok(state.get(&user_id).map(|user_state| user_state.xp).unwrap_or_default())
+    }
+
+    async fn award_achievement(&self, user_id: i64, achievement_id: &str) -> anyhow::Result<bool> {
+        let mut state = self.user_state.lock().unwrap();
+        let user_state = state.entry(user_id).or_default();
+        if user_state.achievements.iter().any(|(id, _)| id == achievement_id) {
+            return Ok(false);
+        }
+        user_state
+            .achievements
+            .push((achievement_id.to_owned(), OffsetDateTime::now_utc()));
+        Ok(true)
+    }
+
+    async fn get_achievements(&self, user_id: i64) -> anyhow::Result<Vec<(String, OffsetDateTime)>> {
+        let state = self.user_state.lock().unwrap();
+        Ok(state
+            .get(&user_id)
+            .map(|user_state| user_state.achievements.clone())
+            .unwrap_or_default())
+    }
+
+    async fn record_blitz_result(&self, result: BlitzResult) -> anyhow::Result<()> {
+        let mut state = self.user_state.lock().unwrap();
+        let user_state = state.entry(result.uid).or_default();
+        user_state.blitz_results.push(result);
+        Ok(())
+    }
+
+    async fn get_best_blitz_result(&self, user_id: i64, duration_secs: i32) -> anyhow::Result<Option<BlitzResult>> {
+        let mut state = self.user_state.lock().unwrap();
+        let user_state = state.entry(user_id).or_default();
+        let best = user_state
+            .blitz_results
+            .iter()
+            .filter(|result| result.duration_secs == duration_secs)
+            .max_by_key(|result| (result.correct, -(result.answered - result.correct)))
+            .cloned();
+        Ok(best)
+    }
+
+    async fn update_group_scores(&self, chat_id: ChatId, results: &[(i64, bool)]) -> anyhow::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let chat_state = state.entry(chat_id.0).or_default();
+        for (uid, correct) in results {
+            let (answered, correct_count) = chat_state.group_scores.entry(*uid).or_default();
+            *answered += 1;
+            if *correct {
+                *correct_count += 1;
+            }
+        }
+        Ok(())
+    }
+
+    async fn get_group_scoreboard(&self, chat_id: ChatId, limit: usize) -> anyhow::Result<Vec<GroupScore>> {
+        let state = self.state.lock().unwrap();
+        let user_state = self.user_state.lock().unwrap();
+        let mut scores = state
+            .get(&chat_id.0)
+            .map(|chat_state| {
+                chat_state
+                    .group_scores
+                    .iter()
+                    .map(|(uid, (answered, correct))| GroupScore {
+                        uid: *uid,
+                        full_name: user_state
+                            .get(uid)
+                            .map(|user| user.user_info.full_name.clone())
+                            .unwrap_or_default(),
+                        answered: *answered,
+                        correct: *correct,
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        scores.sort_by_key(|score| (-score.correct, score.answered, score.uid));
+        scores.truncate(limit);
+        Ok(scores)
+    }
+
+    async fn set_public_profile(&self, user_id: i64, public: bool) -> anyhow::Result<()> {
+        let mut state = self.user_state.lock().unwrap();
+        state.entry(user_id).or_default().public_profile = public;
+        Ok(())
+    }
+
+    async fn is_public_profile(&self, user_id: i64) -> anyhow::Result<bool> {
+        let state = self.user_state.lock().unwrap();
+        Ok(state.get(&user_id).is_some_and(|user| user.public_profile))
+    }
+
+    async fn add_friend(&self, user_id: i64, friend_id: i64) -> anyhow::Result<bool> {
+        let mut state = self.user_state.lock().unwrap();
+        let known = |uid| state.get(&uid).is_some_and(|user: &UserState| user.user_info.uid == uid);
+        if !known(user_id) || !known(friend_id) {
+            return Ok(false);
+        }
+
+        let added = state.entry(user_id).or_default().friends.insert(friend_id);
+        state.entry(friend_id).or_default().friends.insert(user_id);
+        Ok(added)
+    }
+
+    async fn get_friends(&self, user_id: i64) -> anyhow::Result<Vec<i64>> {
+        let state = self.user_state.lock().unwrap();
+        Ok(state
+            .get(&user_id)
+            .map(|user| user.friends.iter().cloned().collect())
+            .unwrap_or_default())
+    }
+
+    async fn get_invite_token(&self, user_id: i64, new_token: &str) -> anyhow::Result<String> {
+        let mut state = self.user_state.lock().unwrap();
+        let user = state.entry(user_id).or_default();
+        Ok(user.invite_token.get_or_insert_with(|| new_token.to_owned()).clone())
+    }
+
+    async fn find_invite_owner(&self, token: &str) -> anyhow::Result<Option<i64>> {
+        let state = self.user_state.lock().unwrap();
+        Ok(state
+            .iter()
+            .find(|(_, user)| user.invite_token.as_deref() == Some(token))
+            .map(|(uid, _)| *uid))
+    }
+
+    async fn get_unlocked_lessons(&self, user_id: i64) -> anyhow::Result<Vec<String>> {
+        let state = self.user_state.lock().unwrap();
+        Ok(state
+            .get(&user_id)
+            .map(|user| user.unlocked_lessons.iter().cloned().collect())
+            .unwrap_or_default())
+    }
+
+    async fn unlock_lesson(&self, user_id: i64, lesson_id: &str) -> anyhow::Result<bool> {
+        let mut state = self.user_state.lock().unwrap();
+        let user_state = state.entry(user_id).or_default();
+        Ok(user_state.unlocked_lessons.insert(lesson_id.to_owned()))
+    }
+
+    async fn get_leaderboard(
+        &self,
+        period: Duration,
+        user_ids: Option<&[i64]>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<LeaderboardEntry>> {
+        let state = self.user_state.lock().unwrap();
+        let from = OffsetDateTime::now_utc() - period;
+        let mut entries = state
+            .iter()
+            .filter(|(uid, user)| match user_ids {
+                Some(user_ids) => user_ids.contains(uid),
+                None => user.public_profile,
+            })
+            .map(|(uid, user)| {
+                // Gaps of a task share the time it was asked
+                let mut tasks: HashMap<(TaskId, OffsetDateTime), bool> = HashMap::new();
+                for answer in user.answers.iter().filter(|answer| answer.answered_at > from) {
+                    *tasks.entry((answer.task_id, answer.asked_at)).or_insert(true) &= answer.correct;
+                }
+                LeaderboardEntry {
+                    uid: *uid,
+                    full_name: user.user_info.full_name.clone(),
+                    answered: tasks.len() as i64,
+                    correct: tasks.values().filter(|correct| **correct).count() as i64,
+                }
+            })
+            .filter(|entry| entry.answered > 0)
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|entry| (-entry.correct, entry.answered, entry.uid));
+        entries.truncate(limit);
+        Ok(entries)
+    }
+
+    async fn get_xp_leaderboard(
+        &self,
+        period: Duration,
+        user_ids: Option<&[i64]>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<XpLeaderboardEntry>> {
+        let state = self.user_state.lock().unwrap();
+        let from = OffsetDateTime::now_utc() - period;
+        let mut entries = state
+            .iter()
+            .filter(|(uid, user)| match user_ids {
+                Some(user_ids) => user_ids.contains(uid),
+                None => user.public_profile,
+            })
+            .map(|(uid, user)| XpLeaderboardEntry {
+                uid: *uid,
+                full_name: user.user_info.full_name.clone(),
+                xp: user
+                    .xp_awards
+                    .iter()
+                    .filter(|(_, earned_at)| *earned_at > from)
+                    .map(|(xp, _)| xp)
+                    .sum(),
+            })
+            .filter(|entry| entry.xp > 0)
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|entry| (-entry.xp, entry.uid));
+        entries.truncate(limit);
+        Ok(entries)
+    }
+
+    async fn get_bot_stats(&self, period: Duration) -> anyhow::Result<BotStats> {
+        let state = self.user_state.lock().unwrap();
+        let from = OffsetDateTime::now_utc() - period;
+
+        let users = state.iter().filter(|(uid, user)| user.user_info.uid == **uid);
+        let mut daily = BTreeMap::new();
+        for (uid, user) in &*state {
+            for answer in user.answers.iter().filter(|answer| answer.answered_at > from) {
+                let (active_users, answered, correct) =
+                    daily.entry(answer.answered_at.date()).or_insert((HashSet::new(), 0, 0));
+                active_users.insert(*uid);
+                *answered += 1;
                 if answer.correct {
-                    correct += 1;
+                    *correct += 1;
                 }
             }
         }
 
-        Ok(AnswerStat { count, correct })
+        Ok(BotStats {
+            total_users: users.clone().count() as i64,
+            new_users: users.filter(|(_, user)| user.user_info.created_at > from).count() as i64,
+            daily: daily
+                .into_iter()
+                .map(|(day, (active_users, answered, correct))| DailyStat {
+                    day,
+                    active_users: active_users.len() as i64,
+                    answered,
+                    correct,
+                })
+                .collect(),
+        })
+    }
+
+    async fn find_user(&self, username: &str) -> anyhow::Result<Option<UserInfo>> {
+        let state = self.user_state.lock().unwrap();
+        Ok(state
+            .values()
+            .map(|user| &user.user_info)
+            .find(|user| user.username.as_deref().is_some_and(|name| name.eq_ignore_ascii_case(username)))
+            .cloned())
+    }
+
+    async fn create_broadcast(&self, author_uid: i64, text: &str) -> anyhow::Result<i64> {
+        let mut broadcasts = self.broadcasts.lock().unwrap();
+        let id = broadcasts.iter().map(|broadcast| broadcast.id).max().unwrap_or(0) + 1;
+        broadcasts.push(Broadcast {
+            id,
+            author_uid,
+            text: text.to_owned(),
+            status: BroadcastStatus::Draft,
+            last_chat_id: None,
+            sent: 0,
+            failed: 0,
+        });
+        Ok(id)
+    }
+
+    async fn get_broadcast(&self, id: i64) -> anyhow::Result<Option<Broadcast>> {
+        let broadcasts = self.broadcasts.lock().unwrap();
+        Ok(broadcasts.iter().find(|broadcast| broadcast.id == id).cloned())
+    }
+
+    async fn get_broadcasts(&self, status: BroadcastStatus) -> anyhow::Result<Vec<Broadcast>> {
+        let broadcasts = self.broadcasts.lock().unwrap();
+        Ok(broadcasts
+            .iter()
+            .filter(|broadcast| broadcast.status == status)
+            .cloned()
+            .collect())
+    }
+
+    async fn update_broadcast_status(
+        &self,
+        id: i64,
+        from: BroadcastStatus,
+        to: BroadcastStatus,
+    ) -> anyhow::Result<bool> {
+        let mut broadcasts = self.broadcasts.lock().unwrap();
+        match broadcasts.iter_mut().find(|broadcast| broadcast.id == id) {
+            Some(broadcast) if broadcast.status == from => {
+                broadcast.status = to;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn get_broadcast_chats(&self, after_chat_id: Option<i64>, limit: usize) -> anyhow::Result<Vec<ChatId>> {
+        Ok(self
+            .broadcast_chats()
+            .into_iter()
+            .filter(|chat_id| after_chat_id.is_none_or(|after| *chat_id > after))
+            .take(limit)
+            .map(ChatId)
+            .collect())
+    }
+
+    async fn count_broadcast_chats(&self) -> anyhow::Result<i64> {
+        Ok(self.broadcast_chats().len() as i64)
+    }
+
+    async fn record_broadcast_delivery(&self, id: i64, chat_id: ChatId, delivered: bool) -> anyhow::Result<()> {
+        let mut broadcasts = self.broadcasts.lock().unwrap();
+        if let Some(broadcast) = broadcasts.iter_mut().find(|broadcast| broadcast.id == id) {
+            broadcast.last_chat_id = Some(chat_id.0);
+            match delivered {
+                true => broadcast.sent += 1,
+                false => broadcast.failed += 1,
+            }
+        }
+        Ok(())
+    }
+
+    async fn record_blocked_chat(&self, chat_id: ChatId) -> anyhow::Result<()> {
+        let mut blocked_chats = self.blocked_chats.lock().unwrap();
+        blocked_chats.insert(chat_id.0, OffsetDateTime::now_utc());
+        Ok(())
+    }
+
+    async fn record_feedback(
+        &self,
+        chat_id: ChatId,
+        from_admin: bool,
+        text: &str,
+        feedback_message_id: MessageId,
+    ) -> anyhow::Result<()> {
+        let mut feedback = self.feedback.lock().unwrap();
+        feedback.push((
+            FeedbackMessage {
+                chat_id,
+                from_admin,
+                text: text.to_owned(),
+                created_at: OffsetDateTime::now_utc(),
+            },
+            feedback_message_id,
+        ));
+        Ok(())
+    }
+
+    async fn find_feedback_chat(&self, feedback_message_id: MessageId) -> anyhow::Result<Option<ChatId>> {
+        let feedback = self.feedback.lock().unwrap();
+        Ok(feedback
+            .iter()
+            .find(|(_, message_id)| *message_id == feedback_message_id)
+            .map(|(message, _)| message.chat_id))
+    }
+
+    async fn get_feedback_history(&self, chat_id: ChatId, limit: usize) -> anyhow::Result<Vec<FeedbackMessage>> {
+        let feedback = self.feedback.lock().unwrap();
+        let mut history = feedback
+            .iter()
+            .rev()
+            .filter(|(message, _)| message.chat_id == chat_id)
+            .take(limit)
+            .map(|(message, _)| message.clone())
+            .collect::<Vec<_>>();
+        history.reverse();
+        Ok(history)
+    }
+
+    async fn record_task_report(
+        &self,
+        uid: i64,
+        task_id: TaskId,
+        reason: TaskReportReason,
+    ) -> anyhow::Result<Option<i64>> {
+        let mut reports = self.task_reports.lock().unwrap();
+        let duplicate = reports.iter().any(|report| {
+            !report.resolved && report.uid == uid && report.task_id == task_id && report.reason == reason
+        });
+        if duplicate {
+            return Ok(None);
+        }
+
+        let id = reports.len() as i64 + 1;
+        reports.push(TaskReport {
+            id,
+            uid,
+            task_id,
+            reason,
+            feedback_message_id: None,
+            resolved: false,
+        });
+        Ok(Some(id))
+    }
+
+    async fn set_task_report_message(&self, report_id: i64, feedback_message_id: MessageId) -> anyhow::Result<()> {
+        let mut reports = self.task_reports.lock().unwrap();
+        if let Some(report) = reports.iter_mut().find(|report| report.id == report_id) {
+            report.feedback_message_id = Some(feedback_message_id);
+        }
+        Ok(())
+    }
+
+    async fn find_reported_task(&self, feedback_message_id: MessageId) -> anyhow::Result<Option<TaskId>> {
+        let reports = self.task_reports.lock().unwrap();
+        Ok(reports
+            .iter()
+            .find(|report| report.feedback_message_id == Some(feedback_message_id))
+            .map(|report| report.task_id))
+    }
+
+    async fn get_task_report_counts(&self, task_id: TaskId) -> anyhow::Result<Vec<(TaskReportReason, i64)>> {
+        let reports = self.task_reports.lock().unwrap();
+        let mut counts: Vec<(TaskReportReason, i64)> = Vec::new();
+        for report in reports.iter().filter(|report| !report.resolved && report.task_id == task_id) {
+            match counts.iter_mut().find(|(reason, _)| *reason == report.reason) {
+                Some((_, count)) => *count += 1,
+                None => counts.push((report.reason, 1)),
+            }
+        }
+        counts.sort_by_key(|(reason, count)| (-count, reason.as_str()));
+        Ok(counts)
+    }
+
+    async fn get_reported_tasks(&self, limit: usize) -> anyhow::Result<Vec<(TaskId, i64)>> {
+        let reports = self.task_reports.lock().unwrap();
+        let mut counts: HashMap<TaskId, i64> = HashMap::new();
+        for report in reports.iter().filter(|report| !report.resolved) {
+            *counts.entry(report.task_id).or_default() += 1;
+        }
+        let mut counts = counts.into_iter().collect::<Vec<_>>();
+        counts.sort_by_key(|(task_id, count)| (-count, *task_id));
+        counts.truncate(limit);
+        Ok(counts)
+    }
+
+    async fn resolve_task_reports(&self, task_id: TaskId) -> anyhow::Result<()> {
+        let mut reports = self.task_reports.lock().unwrap();
+        for report in reports.iter_mut().filter(|report| report.task_id == task_id) {
+            report.resolved = true;
+        }
+        Ok(())
+    }
+    async fn export_user_data(&self, user_id: i64) -> anyhow::Result<BTreeMap<String, Value>> {
+        let mut data = BTreeMap::new();
+
+        {
+            let state = self.state.lock().unwrap();
+            if let Some(chat_state) = state.get(&user_id) {
+                data.insert(
+                    "user_state".to_owned(),
+                    json!([{ "chat_id": user_id, "filter": chat_state.user_data.filter }]),
+                );
+                data.insert("user_task".to_owned(), json!(chat_state.tasks));
+            }
+            let group_scores = state
+                .iter()
+                .filter_map(|(chat_id, chat_state)| {
+                    let (answered, correct) = chat_state.group_scores.get(&user_id)?;
+                    Some(json!({ "chat_id": chat_id, "answered": answered, "correct": correct }))
+                })
+                .collect::<Vec<_>>();
+            data.insert("group_score".to_owned(), Value::Array(group_scores));
+        }
+
+        {
+            let user_state = self.user_state.lock().unwrap();
+            if let Some(user) = user_state.get(&user_id) {
+                let info = &user.user_info;
+                data.insert(
+                    "user_info".to_owned(),
+                    json!([{
+                        "uid": user_id,
+                        "username": info.username,
+                        "full_name": info.full_name,
+                        "language_code": info.language_code,
+                        "is_premium": info.is_premium,
+                        "created_at": timestamp(info.created_at),
+                        "last_active_at": timestamp(info.last_active_at),
+                        "public_profile": user.public_profile,
+                        "xp": user.xp,
+                    }]),
+                );
+                let answers = user.answers.iter().map(|answer| {
+                    json!({
+                        "task_id": answer.task_id,
+                        "gap": answer.gap,
+                        "answer_index": answer.answer_index,
+                        "answer_text": answer.answer_text,
+                        "correct": answer.correct,
+                        "asked_at": timestamp(answer.asked_at),
+                        "answered_at": timestamp(answer.answered_at),
+                    })
+                });
+                data.insert("user_answer".to_owned(), Value::Array(answers.collect()));
+                let blitz_results = user.blitz_results.iter().map(|result| {
+                    json!({
+                        "duration_secs": result.duration_secs,
+                        "answered": result.answered,
+                        "correct": result.correct,
+                        "avg_response_ms": result.avg_response_ms,
+                        "finished_at": timestamp(result.finished_at),
+                    })
+                });
+                data.insert("blitz_result".to_owned(), Value::Array(blitz_results.collect()));
+                // Both directions, as in the user_friend rows of the database
+                let friends = user_state
+                    .iter()
+                    .flat_map(|(&uid, other)| other.friends.iter().map(move |&friend_uid| (uid, friend_uid)))
+                    .filter(|&(uid, friend_uid)| uid == user_id || friend_uid == user_id)
+                    .map(|(uid, friend_uid)| json!({ "uid": uid, "friend_uid": friend_uid }));
+                data.insert("user_friend".to_owned(), Value::Array(friends.collect()));
+                let invites = user.invite_token.iter().map(|token| json!({ "token": token }));
+                data.insert("friend_invite".to_owned(), Value::Array(invites.collect()));
+                data.insert("user_lesson".to_owned(), json!(user.unlocked_lessons));
+                let achievements = user
+                    .achievements
+                    .iter()
+                    .map(|(id, unlocked_at)| json!({ "achievement_id": id, "unlocked_at": timestamp(*unlocked_at) }));
+                data.insert("user_achievement".to_owned(), Value::Array(achievements.collect()));
+                let xp_awards = user
+                    .xp_awards
+                    .iter()
+                    .map(|(xp, earned_at)| json!({ "xp": xp, "earned_at": timestamp(*earned_at) }));
+                data.insert("user_xp".to_owned(), Value::Array(xp_awards.collect()));
+                let changes = user.profile_changes.iter().map(|change| {
+                    json!({
+                        "field": change.field,
+                        "old_value": change.old_value,
+                        "new_value": change.new_value,
+                        "changed_at": timestamp(change.changed_at),
+                    })
+                });
+                data.insert("user_profile_change".to_owned(), Value::Array(changes.collect()));
+            }
+        }
+
+        let broadcasts = self.broadcasts.lock().unwrap();
+        let broadcasts = broadcasts.iter().filter(|broadcast| broadcast.author_uid == user_id).map(
+            |broadcast| json!({ "id": broadcast.id, "text": broadcast.text, "status": broadcast.status.as_str() }),
+        );
+        data.insert("broadcast".to_owned(), Value::Array(broadcasts.collect()));
+
+        let feedback = self.feedback.lock().unwrap();
+        let feedback = feedback
+            .iter()
+            .filter(|(message, _)| message.chat_id.0 == user_id)
+            .map(|(message, _)| {
+                json!({
+                    "from_admin": message.from_admin,
+                    "text": message.text,
+                    "created_at": timestamp(message.created_at),
+                })
+            });
+        data.insert("feedback".to_owned(), Value::Array(feedback.collect()));
+
+        let reports = self.task_reports.lock().unwrap();
+        let reports = reports.iter().filter(|report| report.uid == user_id).map(|report| {
+            json!({ "task_id": report.task_id, "reason": report.reason.as_str(), "resolved": report.resolved })
+        });
+        data.insert("task_report".to_owned(), Value::Array(reports.collect()));
+
+        if let Some(blocked_at) = self.blocked_chats.lock().unwrap().get(&user_id) {
+            data.insert(
+                "chat_blocked".to_owned(),
+                json!([{ "blocked_at": timestamp(*blocked_at) }]),
+            );
+        }
+
+        Ok(data)
+    }
+
+    async fn forget_user(&self, user_id: i64) -> anyhow::Result<()> {
+        {
+            let mut state = self.state.lock().unwrap();
+            state.remove(&user_id);
+            for chat_state in state.values_mut() {
+                chat_state.group_scores.remove(&user_id);
+            }
+        }
+        {
+            let mut user_state = self.user_state.lock().unwrap();
+            user_state.remove(&user_id);
+            for user in user_state.values_mut() {
+                user.friends.remove(&user_id);
+            }
+        }
+        self.broadcasts
+            .lock()
+            .unwrap()
+            .retain(|broadcast| broadcast.author_uid != user_id);
+        self.feedback
+            .lock()
+            .unwrap()
+            .retain(|(message, _)| message.chat_id.0 != user_id);
+        self.task_reports.lock().unwrap().retain(|report| report.uid != user_id);
+        self.blocked_chats.lock().unwrap().remove(&user_id);
+        Ok(())
+    }
+
+    async fn flush(&self) -> anyhow::Result<()> {
+        let Some(file) = &self.snapshot_file else {
+            return Ok(());
+        };
+        let json = serde_json::to_string(&self.snapshot())?;
+        let mut written = file.written.lock().await;
+        if *written == json {
+            return Ok(());
+        }
+
+        // Renaming a complete file keeps the old snapshot if the bot dies while writing
+        let tmp_path = file.path.with_extension("tmp");
+        tokio::fs::write(&tmp_path, &json).await?;
+        tokio::fs::rename(&tmp_path, &file.path).await?;
+        log::debug!("State snapshot written to {}", file.path.display());
+        *written = json;
+        Ok(())
+    }
+}
+
+/// Counts ordered by task id.
+fn task_answer_counts<'a>(answers: impl IntoIterator<Item = &'a Answer>) -> Vec<TaskAnswerCount> {
+    let mut counts: BTreeMap<TaskId, TaskAnswerCount> = BTreeMap::new();
+    for answer in answers {
+        let count = counts.entry(answer.task_id).or_insert(TaskAnswerCount {
+            task_id: answer.task_id,
+            answered: 0,
+            correct: 0,
+        });
+        count.answered += 1;
+        count.correct += answer.correct as i64;
+    }
+    counts.into_values().collect()
+}
+
+fn timestamp(time: OffsetDateTime) -> String {
+    time.format(&Rfc3339).unwrap_or_else(|_| time.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bot::conformance;
+
+    #[tokio::test]
+    async fn test_task_info() -> anyhow::Result<()> {
+        conformance::task_info(&LocalTasks::new(Vec::new())).await
+    }
+
+    #[tokio::test]
+    async fn test_user_state() -> anyhow::Result<()> {
+        conformance::user_state(&LocalUserStateService::default()).await
+    }
+
+    #[tokio::test]
+    async fn test_answer_stats() -> anyhow::Result<()> {
+        conformance::answer_stats(&LocalUserStateService::default()).await
+    }
+
+    #[tokio::test]
+    async fn test_xp_leaderboard() -> anyhow::Result<()> {
+        conformance::xp_leaderboard(&LocalUserStateService::default()).await
+    }
+
+    #[tokio::test]
+    async fn test_task_stats() -> anyhow::Result<()> {
+        conformance::task_stats(&LocalUserStateService::default()).await
+    }
+
+    #[tokio::test]
+    async fn test_multi_gap_answer_stat() -> anyhow::Result<()> {
+        conformance::multi_gap_answer_stat(&LocalUserStateService::default()).await
+    }
+
+    #[tokio::test]
+    async fn test_task_queue() -> anyhow::Result<()> {
+        conformance::task_queue(&LocalUserStateService::default()).await
+    }
+
+    #[tokio::test]
+    async fn test_snapshot() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join(format!("local_state_{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let service = LocalUserStateService::with_snapshot(&path)?;
+        conformance::answer_stats(&service).await?;
+        service.update_tasks(ChatId(1), &[3, 1, 2]).await?;
+        service.add_friend(1, 2).await?;
+        service.create_broadcast(1, "Hello").await?;
+        service.record_task_report(1, 10, TaskReportReason::Typo).await?;
+        service.flush().await?;
+
+        let restored = LocalUserStateService::with_snapshot(&path)?;
+        std::fs::remove_file(&path)?;
+        assert!(!restored.touch_user(&UserInfo::new(1, None, "User")).await?);
+        assert_eq!(
+            restored.get_answer_stat(1, Duration::from_secs(60 * 60)).await?.count,
+            1
+        );
+        assert_eq!(restored.take_next_task(ChatId(1)).await?, Some(3));
+        assert_eq!(restored.get_friends(2).await?, vec![1]);
+        assert_eq!(restored.get_broadcast(1).await?.unwrap().text, "Hello");
+        assert_eq!(restored.get_reported_tasks(10).await?, vec![(10, 1)]);
+
+        Ok(())
     }
 }
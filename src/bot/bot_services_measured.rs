@@ -0,0 +1,485 @@
+//! Service decorator recording call latency and a few business metrics, see [`crate::metrics`].
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::Duration,
+};
+
+use teloxide::types::{ChatId, MessageId};
+use time::{Date, OffsetDateTime};
+
+use crate::{
+    metrics::{measure, metrics},
+    model::{FilterValue, Task, TaskId},
+};
+
+use super::{
+    bot_filter::{Filter, FilterInfo},
+    bot_services::{
+        Answer, AnswerStat, BlitzResult, BotStats, Broadcast, BroadcastStatus, FeedbackMessage, GroupScore,
+        LeaderboardEntry, ProfileChange, TaskAnswerCount, TaskInfoService, TaskQueue, TaskReportReason, TaskStats,
+        UserData, UserInfo, UserStateService, XpLeaderboardEntry,
+    },
+};
+
+/// Wraps a [`UserStateService`] or a [`TaskInfoService`] to measure every call.
+#[derive(Debug)]
+pub struct Measured<S>(pub S);
+
+impl<S: UserStateService> UserStateService for Measured<S> {
+    async fn touch_user(&self, user: &UserInfo) -> anyhow::Result<bool> {
+        measure("user_state", "touch_user", self.0.touch_user(user)).await
+    }
+
+    async fn get_profile_changes(&self, user_id: i64, limit: usize) -> anyhow::Result<Vec<ProfileChange>> {
+        measure(
+            "user_state",
+            "get_profile_changes",
+            self.0.get_profile_changes(user_id, limit),
+        )
+        .await
+    }
+
+    async fn get_state(&self, chat_id: ChatId) -> anyhow::Result<UserData> {
+        measure("user_state", "get_state", self.0.get_state(chat_id)).await
+    }
+
+    async fn update_state(&self, chat_id: ChatId, update: UserData) -> anyhow::Result<()> {
+        measure("user_state", "update_state", self.0.update_state(chat_id, update)).await
+    }
+
+    async fn update_tasks(&self, chat_id: ChatId, tasks: &[TaskId]) -> anyhow::Result<()> {
+        measure("user_state", "update_tasks", self.0.update_tasks(chat_id, tasks)).await?;
+        metrics().queue_refill_size.observe(tasks.len() as f64);
+        Ok(())
+    }
+
+    async fn take_next_task(&self, chat_id: ChatId) -> anyhow::Result<Option<TaskId>> {
+        measure("user_state", "take_next_task", self.0.take_next_task(chat_id)).await
+    }
+
+    async fn peek_tasks(&self, chat_id: ChatId, limit: usize) -> anyhow::Result<TaskQueue> {
+        measure("user_state", "peek_tasks", self.0.peek_tasks(chat_id, limit)).await
+    }
+
+    async fn record_anwer(&self, answer: Answer) -> anyhow::Result<()> {
+        let result = if answer.correct { "correct" } else { "wrong" };
+        measure("user_state", "record_anwer", self.0.record_anwer(answer)).await?;
+        metrics().answers.with_label_values(&[result]).inc();
+        Ok(())
+    }
+
+    async fn get_answers(&self, period: Duration) -> anyhow::Result<Vec<Answer>> {
+        measure("user_state", "get_answers", self.0.get_answers(period)).await
+    }
+
+    async fn get_task_stats(&self, period: Duration) -> anyhow::Result<Vec<TaskStats>> {
+        measure("user_state", "get_task_stats", self.0.get_task_stats(period)).await
+    }
+
+    async fn get_answer_stat(&self, user_id: i64, period: Duration) -> anyhow::Result<AnswerStat> {
+        measure("user_state", "get_answer_stat", self.0.get_answer_stat(user_id, period)).await
+    }
+
+    async fn get_global_task_answer_counts(&self) -> anyhow::Result<Vec<TaskAnswerCount>> {
+        measure(
+            "user_state",
+            "get_global_task_answer_counts",
+            self.0.get_global_task_answer_counts(),
+        )
+        .await
+    }
+
+    async fn get_task_answer_counts(&self, user_id: i64) -> anyhow::Result<Vec<TaskAnswerCount>> {
+        measure(
+            "user_state",
+            "get_task_answer_counts",
+            self.0.get_task_answer_counts(user_id),
+        )
+        .await
+    }
+
+    async fn get_task_answer_count(&self, task_id: TaskId) -> anyhow::Result<TaskAnswerCount> {
+        measure(
+            "user_state",
+            "get_task_answer_count",
+            self.0.get_task_answer_count(task_id),
+        )
+        .await
+    }
+
+    async fn count_answered_tasks(&self, user_id: i64) -> anyhow::Result<i64> {
+        measure(
+            "user_state",
+            "count_answered_tasks",
+            self.0.count_answered_tasks(user_id),
+        )
+        .await
+    }
+
+    async fn get_answer_days(&self, user_id: i64, limit: usize) -> anyhow::Result<Vec<Date>> {
+        measure("user_state", "get_answer_days", self.0.get_answer_days(user_id, limit)).await
+    }
+
+    async fn add_xp(&self, user_id: i64, xp: i64) -> anyhow::Result<i64> {
+        measure("user_state", "add_xp", self.0.add_xp(user_id, xp)).await
+    }
+
+    async fn get_xp(&self, user_id: i64) -> anyhow::Result<i64> {
+        measure("user_state", "get_xp", self.0.get_xp(user_id)).await
+    }
+
+    async fn award_achievement(&self, user_id: i64, achievement_id: &str) -> anyhow::Result<bool> {
+        measure(
+            "user_state",
+            "award_achievement",
+            self.0.award_achievement(user_id, achievement_id),
+        )
+        .await
+    }
+
+    async fn get_achievements(&self, user_id: i64) -> anyhow::Result<Vec<(String, OffsetDateTime)>> {
+        measure("user_state", "get_achievements", self.0.get_achievements(user_id)).await
+    }
+
+    async fn record_blitz_result(&self, result: BlitzResult) -> anyhow::Result<()> {
+        measure("user_state", "record_blitz_result", self.0.record_blitz_result(result)).await
+    }
+
+    async fn get_best_blitz_result(&self, user_id: i64, duration_secs: i32) -> anyhow::Result<Option<BlitzResult>> {
+        measure(
+            "user_state",
+            "get_best_blitz_result",
+            self.0.get_best_blitz_result(user_id, duration_secs),
+        )
+        .await
+    }
+
+    async fn update_group_scores(&self, chat_id: ChatId, results: &[(i64, bool)]) -> anyhow::Result<()> {
+        measure(
+            "user_state",
+            "update_group_scores",
+            self.0.update_group_scores(chat_id, results),
+        )
+        .await
+    }
+
+    async fn get_group_scoreboard(&self, chat_id: ChatId, limit: usize) -> anyhow::Result<Vec<GroupScore>> {
+        measure(
+            "user_state",
+            "get_group_scoreboard",
+            self.0.get_group_scoreboard(chat_id, limit),
+        )
+        .await
+    }
+
+    async fn set_public_profile(&self, user_id: i64, public: bool) -> anyhow::Result<()> {
+        measure(
+            "user_state",
+            "set_public_profile",
+            self.0.set_public_profile(user_id, public),
+        )
+        .await
+    }
+
+    async fn is_public_profile(&self, user_id: i64) -> anyhow::Result<bool> {
+        measure("user_state", "is_public_profile", self.0.is_public_profile(user_id)).await
+    }
+
+    async fn add_friend(&self, user_id: i64, friend_id: i64) -> anyhow::Result<bool> {
+        measure("user_state", "add_friend", self.0.add_friend(user_id, friend_id)).await
+    }
+
+    async fn get_friends(&self, user_id: i64) -> anyhow::Result<Vec<i64>> {
+        measure("user_state", "get_friends", self.0.get_friends(user_id)).await
+    }
+
+    async fn get_invite_token(&self, user_id: i64, new_token: &str) -> anyhow::Result<String> {
+        measure(
+            "user_state",
+            "get_invite_token",
+            self.0.get_invite_token(user_id, new_token),
+        )
+        .await
+    }
+
+    async fn find_invite_owner(&self, token: &str) -> anyhow::Result<Option<i64>> {
+        measure("user_state", "find_invite_owner", self.0.find_invite_owner(token)).await
+    }
+
+    async fn get_unlocked_lessons(&self, user_id: i64) -> anyhow::Result<Vec<String>> {
+        measure(
+            "user_state",
+            "get_unlocked_lessons",
+            self.0.get_unlocked_lessons(user_id),
+        )
+        .await
+    }
+
+    async fn unlock_lesson(&self, user_id: i64, lesson_id: &str) -> anyhow::Result<bool> {
+        measure("user_state", "unlock_lesson", self.0.unlock_lesson(user_id, lesson_id)).await
+    }
+
+    async fn get_leaderboard(
+        &self,
+        period: Duration,
+        user_ids: Option<&[i64]>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<LeaderboardEntry>> {
+        measure(
+            "user_state",
+            "get_leaderboard",
+            self.0.get_leaderboard(period, user_ids, limit),
+        )
+        .await
+    }
+
+    async fn get_xp_leaderboard(
+        &self,
+        period: Duration,
+        user_ids: Option<&[i64]>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<XpLeaderboardEntry>> {
+        measure(
+            "user_state",
+            "get_xp_leaderboard",
+            self.0.get_xp_leaderboard(period, user_ids, limit),
+        )
+        .await
+    }
+
+    async fn get_bot_stats(&self, period: Duration) -> anyhow::Result<BotStats> {
+        measure("user_state", "get_bot_stats", self.0.get_bot_stats(period)).await
+    }
+
+    async fn find_user(&self, username: &str) -> anyhow::Result<Option<UserInfo>> {
+        measure("user_state", "find_user", self.0.find_user(username)).await
+    }
+
+    async fn create_broadcast(&self, author_uid: i64, text: &str) -> anyhow::Result<i64> {
+        measure(
+            "user_state",
+            "create_broadcast",
+            self.0.create_broadcast(author_uid, text),
+        )
+        .await
+    }
+
+    async fn get_broadcast(&self, id: i64) -> anyhow::Result<Option<Broadcast>> {
+        measure("user_state", "get_broadcast", self.0.get_broadcast(id)).await
+    }
+
+    async fn get_broadcasts(&self, status: BroadcastStatus) -> anyhow::Result<Vec<Broadcast>> {
+        measure("user_state", "get_broadcasts", self.0.get_broadcasts(status)).await
+    }
+
+    async fn update_broadcast_status(
+        &self,
+        id: i64,
+        from: BroadcastStatus,
+        to: BroadcastStatus,
+    ) -> anyhow::Result<bool> {
+        measure(
+            "user_state",
+            "update_broadcast_status",
+            self.0.update_broadcast_status(id, from, to),
+        )
+        .await
+    }
+
+    async fn get_broadcast_chats(&self, after_chat_id: Option<i64>, limit: usize) -> anyhow::Result<Vec<ChatId>> {
+        measure(
+            "user_state",
+            "get_broadcast_chats",
+            self.0.get_broadcast_chats(after_chat_id, limit),
+        )
+        .await
+    }
+
+    async fn count_broadcast_chats(&self) -> anyhow::Result<i64> {
+        measure("user_state", "count_broadcast_chats", self.0.count_broadcast_chats()).await
+    }
+
+    async fn record_broadcast_delivery(&self, id: i64, chat_id: ChatId, delivered: bool) -> anyhow::Result<()> {
+        measure(
+            "user_state",
+            "record_broadcast_delivery",
+            self.0.record_broadcast_delivery(id, chat_id, delivered),
+        )
+        .await
+    }
+
+    async fn record_blocked_chat(&self, chat_id: ChatId) -> anyhow::Result<()> {
+        measure("user_state", "record_blocked_chat", self.0.record_blocked_chat(chat_id)).await
+    }
+
+    async fn record_feedback(
+        &self,
+        chat_id: ChatId,
+        from_admin: bool,
+        text: &str,
+        feedback_message_id: MessageId,
+    ) -> anyhow::Result<()> {
+        measure(
+            "user_state",
+            "record_feedback",
+            self.0.record_feedback(chat_id, from_admin, text, feedback_message_id),
+        )
+        .await
+    }
+
+    async fn find_feedback_chat(&self, feedback_message_id: MessageId) -> anyhow::Result<Option<ChatId>> {
+        measure(
+            "user_state",
+            "find_feedback_chat",
+            self.0.find_feedback_chat(feedback_message_id),
+        )
+        .await
+    }
+
+    async fn get_feedback_history(&self, chat_id: ChatId, limit: usize) -> anyhow::Result<Vec<FeedbackMessage>> {
+        measure(
+            "user_state",
+            "get_feedback_history",
+            self.0.get_feedback_history(chat_id, limit),
+        )
+        .await
+    }
+
+    async fn record_task_report(
+        &self,
+        uid: i64,
+        task_id: TaskId,
+        reason: TaskReportReason,
+    ) -> anyhow::Result<Option<i64>> {
+        measure(
+            "user_state",
+            "record_task_report",
+            self.0.record_task_report(uid, task_id, reason),
+        )
+        .await
+    }
+
+    async fn set_task_report_message(&self, report_id: i64, feedback_message_id: MessageId) -> anyhow::Result<()> {
+        measure(
+            "user_state",
+            "set_task_report_message",
+            self.0.set_task_report_message(report_id, feedback_message_id),
+        )
+        .await
+    }
+
+    async fn find_reported_task(&self, feedback_message_id: MessageId) -> anyhow::Result<Option<TaskId>> {
+        measure(
+            "user_state",
+            "find_reported_task",
+            self.0.find_reported_task(feedback_message_id),
+        )
+        .await
+    }
+
+    async fn get_task_report_counts(&self, task_id: TaskId) -> anyhow::Result<Vec<(TaskReportReason, i64)>> {
+        measure(
+            "user_state",
+            "get_task_report_counts",
+            self.0.get_task_report_counts(task_id),
+        )
+        .await
+    }
+
+    async fn get_reported_tasks(&self, limit: usize) -> anyhow::Result<Vec<(TaskId, i64)>> {
+        measure("user_state", "get_reported_tasks", self.0.get_reported_tasks(limit)).await
+    }
+
+    async fn resolve_task_reports(&self, task_id: TaskId) -> anyhow::Result<()> {
+        measure(
+            "user_state",
+            "resolve_task_reports",
+            self.0.resolve_task_reports(task_id),
+        )
+        .await
+    }
+
+    async fn export_user_data(&self, user_id: i64) -> anyhow::Result<BTreeMap<String, serde_json::Value>> {
+        measure("user_state", "export_user_data", self.0.export_user_data(user_id)).await
+    }
+
+    async fn forget_user(&self, user_id: i64) -> anyhow::Result<()> {
+        measure("user_state", "forget_user", self.0.forget_user(user_id)).await
+    }
+
+    async fn flush(&self) -> anyhow::Result<()> {
+        measure("user_state", "flush", self.0.flush()).await
+    }
+}
+
+impl<S: TaskInfoService> TaskInfoService for Measured<S> {
+    async fn get_task_ids(&self, filter: Option<&Filter>) -> anyhow::Result<Vec<TaskId>> {
+        measure("task_info", "get_task_ids", self.0.get_task_ids(filter)).await
+    }
+
+    async fn collect_filter_info(&self) -> anyhow::Result<Vec<FilterInfo>> {
+        measure("task_info", "collect_filter_info", self.0.collect_filter_info()).await
+    }
+
+    async fn sample_task_ids(&self, per_value: usize) -> anyhow::Result<Vec<TaskId>> {
+        measure("task_info", "sample_task_ids", self.0.sample_task_ids(per_value)).await
+    }
+
+    async fn get_task(&self, id: i64) -> anyhow::Result<Option<Task>> {
+        measure("task_info", "get_task", self.0.get_task(id)).await
+    }
+
+    async fn get_tasks(&self, ids: &[TaskId]) -> anyhow::Result<Vec<Task>> {
+        measure("task_info", "get_tasks", self.0.get_tasks(ids)).await
+    }
+
+    async fn get_task_filters(&self, ids: &[TaskId]) -> anyhow::Result<HashMap<TaskId, Vec<FilterValue>>> {
+        measure("task_info", "get_task_filters", self.0.get_task_filters(ids)).await
+    }
+
+    async fn update_tasks(&self, tasks: &[Task]) -> anyhow::Result<(u64, u64)> {
+        measure("task_info", "update_tasks", self.0.update_tasks(tasks)).await
+    }
+
+    async fn deactivate_task(&self, id: TaskId, disabled_by: i64) -> anyhow::Result<bool> {
+        measure("task_info", "deactivate_task", self.0.deactivate_task(id, disabled_by)).await
+    }
+
+    async fn find_task_by_hash(&self, hash: i64) -> anyhow::Result<Option<TaskId>> {
+        measure("task_info", "find_task_by_hash", self.0.find_task_by_hash(hash)).await
+    }
+
+    async fn annotate_task(&self, id: TaskId, annotation: &str) -> anyhow::Result<bool> {
+        measure("task_info", "annotate_task", self.0.annotate_task(id, annotation)).await
+    }
+
+    async fn get_task_annotation(&self, id: TaskId) -> anyhow::Result<Option<String>> {
+        measure("task_info", "get_task_annotation", self.0.get_task_annotation(id)).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bot::{
+        bot_services_in_mem::{LocalTasks, LocalUserStateService},
+        conformance,
+    };
+
+    #[tokio::test]
+    async fn test_conformance() -> anyhow::Result<()> {
+        conformance::task_info(&Measured(LocalTasks::new(Vec::new()))).await?;
+        conformance::user_state(&Measured(LocalUserStateService::default())).await?;
+        conformance::task_queue(&Measured(LocalUserStateService::default())).await?;
+
+        let refills = metrics().queue_refill_size.get_sample_count();
+        let correct = metrics().answers.with_label_values(&["correct"]).get();
+        let service = Measured(LocalUserStateService::default());
+        service.update_tasks(ChatId(1), &[1, 2, 3]).await?;
+        conformance::answer_stats(&service).await?;
+        assert!(metrics().queue_refill_size.get_sample_count() > refills);
+        assert!(metrics().answers.with_label_values(&["correct"]).get() > correct);
+        Ok(())
+    }
+}
@@ -0,0 +1,216 @@
+use std::time::Duration;
+
+use teloxide::{
+    payloads::EditMessageTextSetters,
+    payloads::SendMessageSetters,
+    requests::Requester,
+    types::{CallbackQuery, ChatId, InlineKeyboardButton, InlineKeyboardMarkup},
+    ApiError, Bot, RequestError,
+};
+
+use crate::metrics::SendMeasured;
+
+use super::{
+    bot_core::{encode_command, BotContext},
+    bot_services::{BroadcastStatus, TaskInfoService, UserStateService},
+    proto,
+};
+
+const BROADCAST_BATCH_SIZE: usize = 100;
+/// Telegram allows about 30 messages per second to different chats
+const BROADCAST_DELAY: Duration = Duration::from_millis(40);
+
+impl<T: TaskInfoService, U: UserStateService> BotContext<T, U> {
+    /// Saves a draft and shows it to the admin with the confirmation buttons.
+    pub(super) async fn handle_broadcast(
+        &self,
+        bot: &Bot,
+        command_text: Option<&str>,
+        chat_id: ChatId,
+        uid: i64,
+    ) -> anyhow::Result<()> {
+        let Some(text) = command_text.map(str::trim).filter(|text| !text.is_empty()) else {
+            bot.send_message(chat_id, "Write the announcement after the command: /broadcast <text>")
+                .send_measured()
+                .await?;
+            return Ok(());
+        };
+
+        let broadcast_id = self.user_data.create_broadcast(uid, text).await?;
+        let chats = self.user_data.count_broadcast_chats().await?;
+
+        bot.send_message(chat_id, text).send_measured().await?;
+        bot.send_message(
+            chat_id,
+            format!("Broadcast #{broadcast_id}: the message above will be sent to {chats} chats."),
+        )
+        .reply_markup(broadcast_markup(broadcast_id, true))
+        .send_measured()
+        .await?;
+        Ok(())
+    }
+
+    pub(super) async fn handle_broadcast_action(
+        &self,
+        bot: &Bot,
+        query: &CallbackQuery,
+        action: &proto::BroadcastAction,
+        message: &teloxide::types::Message,
+    ) -> anyhow::Result<()> {
+        if !self.is_admin(query.from.id.0 as i64) {
+            return Ok(());
+        }
+
+        let broadcast_id = action.broadcast_id;
+        let (text, markup) = if action.confirm {
+            let started = self
+                .user_data
+                .update_broadcast_status(broadcast_id, BroadcastStatus::Draft, BroadcastStatus::Sending)
+                .await?;
+            if started {
+                self.spawn_broadcast(bot, broadcast_id);
+                (
+                    format!("Broadcast #{broadcast_id} is being sent, I will report when it is done."),
+                    broadcast_markup(broadcast_id, false),
+                )
+            } else {
+                (
+                    format!("Broadcast #{broadcast_id} was already sent or cancelled."),
+                    InlineKeyboardMarkup::default(),
+                )
+            }
+        } else {
+            let mut cancelled = false;
+            for from in [BroadcastStatus::Draft, BroadcastStatus::Sending] {
+                cancelled = cancelled
+                    || self
+                        .user_data
+                        .update_broadcast_status(broadcast_id, from, BroadcastStatus::Cancelled)
+                        .await?;
+            }
+            let text = match cancelled {
+                true => format!("Broadcast #{broadcast_id} cancelled."),
+                false => format!("Broadcast #{broadcast_id} was already finished."),
+            };
+            (text, InlineKeyboardMarkup::default())
+        };
+
+        bot.edit_message_text(message.chat.id, message.id, text)
+            .reply_markup(markup)
+            .send_measured()
+            .await?;
+        Ok(())
+    }
+
+    /// Continues broadcasts interrupted by a restart.
+    pub(super) async fn resume_broadcasts(&self, bot: &Bot) -> anyhow::Result<()> {
+        for broadcast in self.user_data.get_broadcasts(BroadcastStatus::Sending).await? {
+            log::info!(
+                "Resuming broadcast #{} after chat {:?}",
+                broadcast.id,
+                broadcast.last_chat_id
+            );
+            self.spawn_broadcast(bot, broadcast.id);
+        }
+        Ok(())
+    }
+
+    fn spawn_broadcast(&self, bot: &Bot, broadcast_id: i64) {
+        let context = self.clone();
+        let bot = bot.clone();
+        tokio::spawn(async move {
+            if let Err(err) = context.run_broadcast(&bot, broadcast_id).await {
+                log::error!("Broadcast #{broadcast_id} failed: {err}");
+            }
+        });
+    }
+
+    async fn run_broadcast(&self, bot: &Bot, broadcast_id: i64) -> anyhow::Result<()> {
+        loop {
+            // Progress is re-read for every batch, so cancellation is noticed
+            let Some(broadcast) = self.user_data.get_broadcast(broadcast_id).await? else {
+                return Ok(());
+            };
+            if broadcast.status != BroadcastStatus::Sending {
+                return Ok(());
+            }
+
+            let chats = self
+                .user_data
+                .get_broadcast_chats(broadcast.last_chat_id, BROADCAST_BATCH_SIZE)
+                .await?;
+
+            if chats.is_empty() {
+                self.user_data
+                    .update_broadcast_status(broadcast_id, BroadcastStatus::Sending, BroadcastStatus::Done)
+                    .await?;
+                log::info!(
+                    "Broadcast #{broadcast_id} done, sent {}, failed {}",
+                    broadcast.sent,
+                    broadcast.failed
+                );
+                bot.send_message(
+                    ChatId(broadcast.author_uid),
+                    format!(
+                        "Broadcast #{broadcast_id} done: delivered to {sent} chats, failed for {failed}.",
+                        sent = broadcast.sent,
+                        failed = broadcast.failed
+                    ),
+                )
+                .send_measured()
+                .await?;
+                return Ok(());
+            }
+
+            for chat_id in chats {
+                let delivered = self.deliver_broadcast(bot, chat_id, &broadcast.text).await?;
+                self.user_data
+                    .record_broadcast_delivery(broadcast_id, chat_id, delivered)
+                    .await?;
+                tokio::time::sleep(BROADCAST_DELAY).await;
+            }
+        }
+    }
+
+    async fn deliver_broadcast(&self, bot: &Bot, chat_id: ChatId, text: &str) -> anyhow::Result<bool> {
+        loop {
+            match bot.send_message(chat_id, text).send_measured().await {
+                Ok(_) => return Ok(true),
+                Err(RequestError::RetryAfter(delay)) => {
+                    log::warn!("#{chat_id} broadcast is throttled for {delay:?}");
+                    tokio::time::sleep(delay).await;
+                }
+                Err(RequestError::Api(
+                    ApiError::BotBlocked
+                    | ApiError::UserDeactivated
+                    | ApiError::ChatNotFound
+                    | ApiError::BotKicked
+                    | ApiError::BotKickedFromSupergroup
+                    | ApiError::CantInitiateConversation,
+                )) => {
+                    log::info!("#{chat_id} blocked the bot, skipping it in broadcasts");
+                    self.user_data.record_blocked_chat(chat_id).await?;
+                    return Ok(false);
+                }
+                Err(err) => {
+                    log::warn!("#{chat_id} failed to deliver broadcast: {err}");
+                    return Ok(false);
+                }
+            }
+        }
+    }
+}
+
+fn broadcast_markup(broadcast_id: i64, with_confirm: bool) -> InlineKeyboardMarkup {
+    let button = |text: &str, confirm: bool| {
+        let command = proto::command::Command::BroadcastAction(proto::BroadcastAction { broadcast_id, confirm });
+        InlineKeyboardButton::callback(text, encode_command(command))
+    };
+
+    let mut buttons = Vec::new();
+    if with_confirm {
+        buttons.push(button("📣 Send", true));
+    }
+    buttons.push(button("Cancel", false));
+    InlineKeyboardMarkup::new([buttons])
+}
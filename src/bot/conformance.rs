@@ -0,0 +1,398 @@
+//! Behaviour every service implementation must share, run from the tests of each backend.
+
+use std::{collections::HashMap, time::Duration};
+
+use teloxide::types::ChatId;
+use time::OffsetDateTime;
+
+use crate::model::{FilterValue, Task, TaskId};
+
+use super::{
+    bot_filter::{parse_filter, FilterInfo},
+    bot_services::{Answer, QueuedTask, TaskInfoService, TaskQueue, TaskStats, UserData, UserInfo, UserStateService},
+};
+
+fn task(hash: i64, filters: &[(&str, &str)]) -> Task {
+    Task {
+        id: 0,
+        hash,
+        task: format!("task{hash}"),
+        masked_task: format!("task{hash}"),
+        correct: format!("correct{hash}"),
+        base: format!("base{hash}"),
+        info: Vec::new(),
+        hints: Vec::new(),
+        filters: filters
+            .iter()
+            .map(|(name, value)| FilterValue {
+                name: (*name).into(),
+                value: (*value).into(),
+            })
+            .collect(),
+        wrong_answers: Vec::new(),
+        gaps: Vec::new(),
+    }
+}
+
+/// Hashes of the tasks, ids differ between backends.
+async fn hashes(service: &impl TaskInfoService, ids: &[TaskId]) -> anyhow::Result<Vec<i64>> {
+    let tasks = service
+        .get_tasks(ids)
+        .await?
+        .into_iter()
+        .map(|task| (task.id, task.hash))
+        .collect::<HashMap<_, _>>();
+    Ok(ids.iter().filter_map(|id| tasks.get(id).copied()).collect())
+}
+
+pub async fn task_info(service: &impl TaskInfoService) -> anyhow::Result<()> {
+    let tasks = [
+        task(10, &[("case", "dative"), ("theme", "shopping")]),
+        task(20, &[("case", "locative"), ("theme", "shopping")]),
+        task(30, &[("case", "dative"), ("theme", "travel")]),
+    ];
+    assert_eq!(service.update_tasks(&tasks).await?, (3, 0));
+
+    // Ids come sorted, the bot orders the queue itself
+    let ids = service.get_task_ids(None).await?;
+    assert!(ids.windows(2).all(|pair| pair[0] < pair[1]), "{ids:?}");
+    assert_eq!(hashes(service, &ids).await?, vec![10, 20, 30]);
+
+    for &id in &ids {
+        let task = service.get_task(id).await?.unwrap();
+        assert_eq!(task.id, id);
+        assert_eq!(task.task, format!("task{}", task.hash));
+    }
+    let unknown_id = ids.iter().max().unwrap() + 1000;
+    assert!(service.get_task(unknown_id).await?.is_none());
+    assert_eq!(service.get_tasks(&[unknown_id, ids[1]]).await?.len(), 1);
+
+    let filters = service.get_task_filters(&[unknown_id, ids[1]]).await?;
+    let mut second = filters[&ids[1]].iter().map(|filter| filter.value.as_str()).collect::<Vec<_>>();
+    second.sort();
+    assert_eq!((filters.len(), second), (1, vec!["locative", "shopping"]));
+
+    let filtered = service.get_task_ids(Some(&parse_filter("dative"))).await?;
+    assert_eq!(hashes(service, &filtered).await?, vec![10, 30]);
+    let filtered = service.get_task_ids(Some(&parse_filter("dative, locative; shopping"))).await?;
+    assert_eq!(hashes(service, &filtered).await?, vec![10, 20]);
+    assert!(service.get_task_ids(Some(&parse_filter("genitive"))).await?.is_empty());
+
+    // Every filter value gets a task, locative and travel have one task each
+    let sample = service.sample_task_ids(1).await?;
+    assert!(sample.windows(2).all(|pair| pair[0] < pair[1]), "{sample:?}");
+    assert!(sample.contains(&ids[1]) && sample.contains(&ids[2]), "{sample:?}");
+    assert!(sample.len() <= 3, "{sample:?}");
+    assert_eq!(service.sample_task_ids(5).await?, ids);
+
+    assert_eq!(
+        service.collect_filter_info().await?,
+        vec![
+            FilterInfo {
+                name: "case".into(),
+                possible_values: vec!["dative".into(), "locative".into()],
+            },
+            FilterInfo {
+                name: "theme".into(),
+                possible_values: vec!["shopping".into(), "travel".into()],
+            },
+        ]
+    );
+
+    // Missing tasks are deactivated, but still can be answered from the queues
+    let tasks = [tasks[0].clone(), task(40, &[("case", "genitive")])];
+    assert_eq!(service.update_tasks(&tasks).await?, (2, 2));
+    let active = service.get_task_ids(None).await?;
+    assert_eq!(hashes(service, &active).await?, vec![10, 40]);
+    assert_eq!(service.get_task(ids[1]).await?.unwrap().hash, 20);
+
+    assert_eq!(service.find_task_by_hash(40).await?, Some(active[1]));
+    assert_eq!(service.find_task_by_hash(20).await?, Some(ids[1]));
+    assert_eq!(service.find_task_by_hash(50).await?, None);
+    assert!(service.deactivate_task(active[1], 1).await?);
+    assert!(service.deactivate_task(active[0], 1).await?);
+    assert!(!service.deactivate_task(active[0], 1).await?);
+    assert!(!service.deactivate_task(unknown_id, 1).await?);
+    assert!(service.get_task_ids(None).await?.is_empty());
+
+    // Imports bring back tasks missing from the files, but not the deactivated ones
+    let tasks = [tasks[0].clone(), tasks[1].clone(), task(20, &[("case", "locative")])];
+    service.update_tasks(&tasks).await?;
+    assert_eq!(hashes(service, &service.get_task_ids(None).await?).await?, vec![20]);
+    assert_eq!(hashes(service, &service.sample_task_ids(5).await?).await?, vec![20]);
+    assert!(!service.deactivate_task(active[0], 1).await?);
+
+    assert_eq!(service.get_task_annotation(ids[0]).await?, None);
+    assert!(service.annotate_task(ids[0], "Checked").await?);
+    assert!(!service.annotate_task(unknown_id, "Unknown").await?);
+    assert_eq!(service.get_task_annotation(ids[0]).await?.as_deref(), Some("Checked"));
+
+    Ok(())
+}
+
+pub async fn user_state(service: &impl UserStateService) -> anyhow::Result<()> {
+    let chat_id = ChatId(1);
+
+    assert!(service.touch_user(&UserInfo::new(1, Some("first"), "First")).await?);
+    assert!(!service.touch_user(&UserInfo::new(1, Some("first"), "First")).await?);
+    service.touch_user(&UserInfo::new(2, None, "Second")).await?;
+
+    assert_eq!(service.get_state(chat_id).await?.filter, None);
+    service
+        .update_state(
+            chat_id,
+            UserData {
+                filter: Some("dative".into()),
+            },
+        )
+        .await?;
+    assert_eq!(service.get_state(chat_id).await?.filter.as_deref(), Some("dative"));
+    assert_eq!(service.get_state(ChatId(2)).await?.filter, None);
+
+    // The first token stays, so shared links keep working
+    assert_eq!(service.get_invite_token(1, "token1").await?, "token1");
+    assert_eq!(service.get_invite_token(1, "other").await?, "token1");
+    assert_eq!(service.get_invite_token(2, "token2").await?, "token2");
+    assert_eq!(service.find_invite_owner("token2").await?, Some(2));
+    assert_eq!(service.find_invite_owner("other").await?, None);
+
+    Ok(())
+}
+
+pub async fn answer_stats(service: &impl UserStateService) -> anyhow::Result<()> {
+    let now = OffsetDateTime::now_utc();
+    let hour = Duration::from_secs(60 * 60);
+    let answer = |uid: i64, task_id: TaskId, correct: bool, ago: Duration| Answer {
+        uid,
+        task_id,
+        gap: 0,
+        answer_index: 0,
+        answer_text: None,
+        correct,
+        asked_at: now - ago - Duration::from_secs(10),
+        answered_at: now - ago,
+    };
+
+    for uid in [1, 2] {
+        service.touch_user(&UserInfo::new(uid, None, "User")).await?;
+    }
+    service.record_anwer(answer(1, 10, true, hour * 48)).await?;
+    service.record_anwer(answer(1, 10, false, hour * 2)).await?;
+    service.record_anwer(answer(1, 20, true, Duration::from_secs(60))).await?;
+    service.record_anwer(answer(2, 10, true, hour / 2)).await?;
+
+    let stat = service.get_answer_stat(1, hour).await?;
+    assert_eq!((stat.count, stat.correct), (1, 1));
+    let stat = service.get_answer_stat(1, hour * 24).await?;
+    assert_eq!((stat.count, stat.correct), (2, 1));
+    let stat = service.get_answer_stat(1, hour * 72).await?;
+    assert_eq!((stat.count, stat.correct), (3, 2));
+    assert_eq!(service.get_answer_stat(3, hour).await?.count, 0);
+
+    // Oldest first
+    let answers = service.get_answers(hour * 24).await?;
+    assert_eq!(
+        answers.iter().map(|answer| (answer.uid, answer.task_id)).collect::<Vec<_>>(),
+        vec![(1, 10), (2, 10), (1, 20)]
+    );
+
+    let counts = service.get_global_task_answer_counts().await?;
+    assert_eq!(
+        counts
+            .iter()
+            .map(|count| (count.task_id, count.answered, count.correct))
+            .collect::<Vec<_>>(),
+        vec![(10, 3, 2), (20, 1, 1)]
+    );
+    let counts = service.get_task_answer_counts(1).await?;
+    assert_eq!(
+        counts
+            .iter()
+            .map(|count| (count.task_id, count.answered, count.correct))
+            .collect::<Vec<_>>(),
+        vec![(10, 2, 1), (20, 1, 1)]
+    );
+    let counts = service.get_task_answer_counts(2).await?;
+    assert_eq!(counts.len(), 1);
+    assert_eq!((counts[0].answered, counts[0].correct), (1, 1));
+    assert!(service.get_task_answer_counts(3).await?.is_empty());
+
+    Ok(())
+}
+
+pub async fn task_stats(service: &impl UserStateService) -> anyhow::Result<()> {
+    let now = OffsetDateTime::now_utc();
+    let answer = |task_id: TaskId, answer_text: Option<&str>, response_secs: u64, ago: Duration| Answer {
+        uid: 1,
+        task_id,
+        gap: 0,
+        answer_index: 0,
+        answer_text: answer_text.map(str::to_owned),
+        correct: answer_text == Some("correct"),
+        asked_at: now - ago - Duration::from_secs(response_secs),
+        answered_at: now - ago,
+    };
+
+    let minute = Duration::from_secs(60);
+    service.touch_user(&UserInfo::new(1, None, "User")).await?;
+    service.record_anwer(answer(10, Some("correct"), 2, minute)).await?;
+    service.record_anwer(answer(10, Some("a"), 4, minute)).await?;
+    service.record_anwer(answer(10, Some("b"), 8, minute)).await?;
+    service.record_anwer(answer(10, Some("a"), 6, minute)).await?;
+    // Answers recorded before the chosen text was stored have no distractor
+    service.record_anwer(answer(20, None, 3, minute)).await?;
+    // Later gaps share the time the task was asked, they don't count for the response time
+    service
+        .record_anwer(Answer {
+            gap: 1,
+            answered_at: now - minute + Duration::from_secs(27),
+            ..answer(20, Some("correct"), 3, minute)
+        })
+        .await?;
+    service.record_anwer(answer(30, Some("a"), 3, minute * 120)).await?;
+
+    let stats = service.get_task_stats(minute * 60).await?;
+    assert_eq!(
+        stats,
+        vec![
+            TaskStats {
+                task_id: 10,
+                answered: 4,
+                correct: 1,
+                median_response: Duration::from_secs(5),
+                distractors: vec![("a".to_owned(), 2), ("b".to_owned(), 1)],
+            },
+            TaskStats {
+                task_id: 20,
+                answered: 2,
+                correct: 1,
+                median_response: Duration::from_secs(3),
+                distractors: Vec::new(),
+            },
+        ]
+    );
+
+    Ok(())
+}
+
+/// Gaps of a multi-gap task are recorded separately but counted as one answered task, in rankings too.
+pub async fn multi_gap_answer_stat(service: &impl UserStateService) -> anyhow::Result<()> {
+    let now = OffsetDateTime::now_utc();
+    let asked_at = now - Duration::from_secs(60);
+    let answer = |task_id: TaskId, gap: i32, correct: bool| Answer {
+        uid: 1,
+        task_id,
+        gap,
+        answer_index: 0,
+        answer_text: None,
+        correct,
+        asked_at,
+        answered_at: now - Duration::from_secs(10 - gap as u64),
+    };
+
+    service.touch_user(&UserInfo::new(1, None, "User")).await?;
+    for gap in 0..3 {
+        service.record_anwer(answer(10, gap, true)).await?;
+    }
+    service.record_anwer(answer(20, 0, true)).await?;
+    service.record_anwer(answer(20, 1, false)).await?;
+    service.record_anwer(answer(30, 0, true)).await?;
+
+    let stat = service.get_answer_stat(1, Duration::from_secs(60 * 60)).await?;
+    assert_eq!((stat.count, stat.correct), (3, 2));
+    assert_eq!(service.count_answered_tasks(1).await?, 3);
+    assert_eq!(service.count_answered_tasks(2).await?, 0);
+
+    // Rankings count tasks as well, so they agree with the user's own result
+    let entries = service.get_leaderboard(Duration::from_secs(60 * 60), Some(&[1]), 10).await?;
+    assert_eq!(
+        entries.iter().map(|entry| (entry.answered, entry.correct)).collect::<Vec<_>>(),
+        vec![(3, 2)]
+    );
+
+    Ok(())
+}
+
+/// Weekly XP counts only recent awards, the total keeps all of them.
+pub async fn xp_leaderboard(service: &impl UserStateService) -> anyhow::Result<()> {
+    let week = Duration::from_secs(60 * 60 * 24 * 7);
+    for uid in [1, 2, 3] {
+        service.touch_user(&UserInfo::new(uid, None, &format!("User{uid}"))).await?;
+    }
+    service.set_public_profile(1, true).await?;
+    service.set_public_profile(2, true).await?;
+    assert_eq!(service.add_xp(1, 10).await?, 10);
+    assert_eq!(service.add_xp(1, 5).await?, 15);
+    service.add_xp(2, 20).await?;
+    service.add_xp(3, 50).await?;
+
+    let top = service.get_xp_leaderboard(week, None, 10).await?;
+    assert_eq!(
+        top.iter().map(|entry| (entry.uid, entry.xp)).collect::<Vec<_>>(),
+        vec![(2, 20), (1, 15)]
+    );
+    assert_eq!(top[1].full_name, "User1");
+
+    let friends = service.get_xp_leaderboard(week, Some(&[1, 3]), 10).await?;
+    assert_eq!(
+        friends.iter().map(|entry| (entry.uid, entry.xp)).collect::<Vec<_>>(),
+        vec![(3, 50), (1, 15)]
+    );
+    assert_eq!(service.get_xp_leaderboard(week, None, 1).await?.len(), 1);
+    assert!(service.get_xp_leaderboard(Duration::ZERO, None, 10).await?.is_empty());
+
+    Ok(())
+}
+
+pub async fn task_queue(service: &impl UserStateService) -> anyhow::Result<()> {
+    let chat_id = ChatId(1);
+    let other_chat_id = ChatId(2);
+
+    assert_eq!(service.take_next_task(chat_id).await?, None);
+    assert_eq!(
+        service.peek_tasks(chat_id, 5).await?,
+        TaskQueue {
+            remaining: 0,
+            next: Vec::new()
+        }
+    );
+
+    service.update_tasks(chat_id, &[3, 1, 3, 2]).await?;
+    service.update_tasks(other_chat_id, &[7]).await?;
+
+    let queue = service.peek_tasks(chat_id, 2).await?;
+    assert_eq!(queue.remaining, 4);
+    assert_eq!(
+        queue.next,
+        vec![
+            QueuedTask {
+                position: 0,
+                task_id: 3
+            },
+            QueuedTask {
+                position: 1,
+                task_id: 1
+            },
+        ]
+    );
+    // Peeking does not take tasks
+    assert_eq!(service.peek_tasks(chat_id, 10).await?.next.len(), 4);
+
+    // Duplicates are taken one at a time, in the given order
+    assert_eq!(service.take_next_task(chat_id).await?, Some(3));
+    assert_eq!(service.take_next_task(chat_id).await?, Some(1));
+    assert_eq!(service.peek_tasks(chat_id, 0).await?.remaining, 2);
+    assert_eq!(service.take_next_task(chat_id).await?, Some(3));
+    assert_eq!(service.take_next_task(chat_id).await?, Some(2));
+    assert_eq!(service.take_next_task(chat_id).await?, None);
+
+    assert_eq!(service.peek_tasks(other_chat_id, 1).await?.remaining, 1);
+
+    // Updating replaces the queue
+    service.update_tasks(other_chat_id, &[8, 9]).await?;
+    assert_eq!(service.take_next_task(other_chat_id).await?, Some(8));
+    service.update_tasks(other_chat_id, &[]).await?;
+    assert_eq!(service.take_next_task(other_chat_id).await?, None);
+
+    Ok(())
+}
@@ -0,0 +1,283 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use rand::{seq::SliceRandom, thread_rng, Rng};
+use teloxide::types::ChatId;
+
+use crate::model::{FilterValue, TaskId};
+
+use super::{
+    bot_core::BotContext,
+    bot_services::{TaskAnswerCount, TaskInfoService, UserStateService},
+};
+
+/// Learners with fewer answers get a random queue, their accuracy says little yet
+const MIN_LEARNER_ANSWERS: i64 = 10;
+/// Pseudo-answers with the average accuracy added to every task and category, so few answers don't dominate
+const PRIOR_ANSWERS: f64 = 5.0;
+/// Expected success this far from the target makes a task about e times less likely to come early
+const TARGET_WIDTH: f64 = 0.15;
+const DEFAULT_ACCURACY: f64 = 0.75;
+/// Task difficulty changes slowly while computing it scans all answers
+const TASK_DIFFICULTY_REFRESH: Duration = Duration::from_secs(60 * 60);
+
+/// Task difficulty with the time it was computed, shared by all queue refills.
+pub(super) type TaskDifficultyCache = Arc<Mutex<Option<(Instant, Arc<TaskDifficulty>)>>>;
+
+/// Smoothed accuracy of every task over all learners.
+#[derive(Debug)]
+pub(super) struct TaskDifficulty {
+    mean_accuracy: f64,
+    task_accuracy: HashMap<TaskId, f64>,
+}
+
+impl TaskDifficulty {
+    pub(super) fn new(global: &[TaskAnswerCount]) -> Self {
+        let answered: i64 = global.iter().map(|count| count.answered).sum();
+        let correct: i64 = global.iter().map(|count| count.correct).sum();
+        let mean_accuracy = match answered {
+            0 => DEFAULT_ACCURACY,
+            answered => correct as f64 / answered as f64,
+        };
+
+        let task_accuracy = global
+            .iter()
+            .map(|count| {
+                let accuracy = smoothed(count.correct as f64, count.answered as f64, mean_accuracy);
+                (count.task_id, accuracy)
+            })
+            .collect();
+
+        TaskDifficulty {
+            mean_accuracy,
+            task_accuracy,
+        }
+    }
+
+    fn accuracy(&self, task_id: TaskId) -> f64 {
+        self.task_accuracy.get(&task_id).copied().unwrap_or(self.mean_accuracy)
+    }
+}
+
+/// Predicts how likely a learner answers a task correctly.
+///
+/// Task difficulty comes from the accuracy of all learners on it. The learner's skill is the difference
+/// in log-odds between their accuracy and the accuracy expected from the difficulty of the same tasks,
+/// computed for every filter value (category) the learner has answered.
+#[derive(Debug)]
+pub(super) struct DifficultyModel<'a> {
+    difficulty: &'a TaskDifficulty,
+    /// Learner skill over all answers, used for categories the learner has not met
+    skill: f64,
+    category_skill: HashMap<(String, String), f64>,
+}
+
+impl<'a> DifficultyModel<'a> {
+    /// `filters` must contain the tasks answered by the learner, to know their categories.
+    pub(super) fn new(
+        difficulty: &'a TaskDifficulty,
+        learner: &[TaskAnswerCount],
+        filters: &HashMap<TaskId, Vec<FilterValue>>,
+    ) -> Self {
+        let mut model = DifficultyModel {
+            difficulty,
+            skill: 0.0,
+            category_skill: HashMap::new(),
+        };
+
+        // Correct, expected correct and answered counts of the learner
+        let mut total = (0.0, 0.0, 0.0);
+        let mut categories: HashMap<(String, String), (f64, f64, f64)> = HashMap::new();
+        for count in learner {
+            let expected = difficulty.accuracy(count.task_id) * count.answered as f64;
+            let filters = filters.get(&count.task_id).map(Vec::as_slice).unwrap_or_default();
+            let category_keys = filters.iter().map(|filter| (filter.name.clone(), filter.value.clone()));
+            add_counts(&mut total, count, expected);
+            for key in category_keys {
+                add_counts(categories.entry(key).or_default(), count, expected);
+            }
+        }
+
+        model.skill = skill(total);
+        model.category_skill = categories.into_iter().map(|(key, sums)| (key, skill(sums))).collect();
+        model
+    }
+
+    pub(super) fn expected_success(&self, task_id: TaskId, filters: &[FilterValue]) -> f64 {
+        let skills = filters
+            .iter()
+            .filter_map(|filter| self.category_skill.get(&(filter.name.clone(), filter.value.clone())).copied())
+            .collect::<Vec<_>>();
+        let skill = match skills.len() {
+            0 => self.skill,
+            len => skills.iter().sum::<f64>() / len as f64,
+        };
+        sigmoid(logit(self.difficulty.accuracy(task_id)) + skill)
+    }
+}
+
+fn add_counts(sums: &mut (f64, f64, f64), count: &TaskAnswerCount, expected: f64) {
+    sums.0 += count.correct as f64;
+    sums.1 += expected;
+    sums.2 += count.answered as f64;
+}
+
+/// Log-odds of the learner accuracy over the accuracy expected for the answered tasks.
+fn skill((correct, expected, answered): (f64, f64, f64)) -> f64 {
+    if answered == 0.0 {
+        return 0.0;
+    }
+    let expected_accuracy = expected / answered;
+    logit(smoothed(correct, answered, expected_accuracy)) - logit(expected_accuracy)
+}
+
+fn smoothed(correct: f64, answered: f64, prior: f64) -> f64 {
+    (correct + PRIOR_ANSWERS * prior) / (answered + PRIOR_ANSWERS)
+}
+
+fn logit(p: f64) -> f64 {
+    let p = p.clamp(0.01, 0.99);
+    (p / (1.0 - p)).ln()
+}
+
+fn sigmoid(x: f64) -> f64 {
+    1.0 / (1.0 + (-x).exp())
+}
+
+/// Random order where tasks with the expected success close to the target tend to come first.
+pub(super) fn order_by_target(expected: Vec<(TaskId, f64)>, target: f64, rng: &mut impl Rng) -> Vec<TaskId> {
+    // Weighted sampling without replacement: sorting by u^(1/w) picks heavier items earlier
+    let mut keyed = expected
+        .into_iter()
+        .map(|(task_id, success)| {
+            let weight = (-((success - target) / TARGET_WIDTH).powi(2)).exp().max(1e-9);
+            let key = rng.gen::<f64>().powf(1.0 / weight);
+            (task_id, key)
+        })
+        .collect::<Vec<_>>();
+    keyed.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+    keyed.into_iter().map(|(task_id, _)| task_id).collect()
+}
+
+impl<T: TaskInfoService, U: UserStateService> BotContext<T, U> {
+    /// Orders a new task queue for the learner, falls back to a random order for new learners.
+    pub(super) async fn order_by_difficulty(
+        &self,
+        chat_id: ChatId,
+        mut task_ids: Vec<TaskId>,
+    ) -> anyhow::Result<Vec<TaskId>> {
+        let learner = self.user_data.get_task_answer_counts(chat_id.0).await?;
+        if learner.iter().map(|count| count.answered).sum::<i64>() < MIN_LEARNER_ANSWERS {
+            task_ids.shuffle(&mut thread_rng());
+            return Ok(task_ids);
+        }
+
+        let difficulty = self.task_difficulty().await?;
+        let mut ids = task_ids.clone();
+        ids.extend(learner.iter().map(|count| count.task_id));
+        ids.sort();
+        ids.dedup();
+        let filters = self.tasks.get_task_filters(&ids).await?;
+
+        let model = DifficultyModel::new(&difficulty, &learner, &filters);
+        let expected = task_ids
+            .into_iter()
+            .map(|task_id| {
+                let success = filters
+                    .get(&task_id)
+                    .map(|filters| model.expected_success(task_id, filters))
+                    .unwrap_or(self.target_success);
+                (task_id, success)
+            })
+            .collect();
+        Ok(order_by_target(expected, self.target_success, &mut thread_rng()))
+    }
+
+    /// Cached task difficulty, recomputed from all answers once it gets old.
+    async fn task_difficulty(&self) -> anyhow::Result<Arc<TaskDifficulty>> {
+        if let Some((computed_at, difficulty)) = self.task_difficulty.lock().unwrap().as_ref() {
+            if computed_at.elapsed() < TASK_DIFFICULTY_REFRESH {
+                return Ok(difficulty.clone());
+            }
+        }
+
+        // Concurrent refills may compute it twice, which is cheaper than holding a lock over the query
+        let global = self.user_data.get_global_task_answer_counts().await?;
+        let difficulty = Arc::new(TaskDifficulty::new(&global));
+        *self.task_difficulty.lock().unwrap() = Some((Instant::now(), difficulty.clone()));
+        Ok(difficulty)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    fn task(id: TaskId, case: &str) -> (TaskId, Vec<FilterValue>) {
+        let filter = FilterValue {
+            name: "case".into(),
+            value: case.into(),
+        };
+        (id, vec![filter])
+    }
+
+    fn count(task_id: TaskId, answered: i64, correct: i64) -> TaskAnswerCount {
+        TaskAnswerCount {
+            task_id,
+            answered,
+            correct,
+        }
+    }
+
+    #[test]
+    fn test_expected_success() {
+        let tasks = [
+            task(1, "dativ"),
+            task(2, "dativ"),
+            task(3, "akuzativ"),
+            task(4, "akuzativ"),
+            task(5, "vokativ"),
+        ];
+        let global = [
+            count(1, 100, 90),
+            count(2, 100, 50),
+            count(3, 100, 80),
+            count(4, 100, 80),
+        ];
+        // Good at dativ, bad at akuzativ
+        let learner = [count(1, 10, 10), count(2, 10, 10), count(3, 10, 2)];
+        let difficulty = TaskDifficulty::new(&global);
+        let filters = tasks.iter().cloned().collect::<HashMap<_, _>>();
+        let model = DifficultyModel::new(&difficulty, &learner, &filters);
+
+        let success = tasks
+            .iter()
+            .map(|(task_id, filters)| model.expected_success(*task_id, filters))
+            .collect::<Vec<_>>();
+        assert!(success[0] > 0.9, "{success:?}");
+        assert!(success[1] > 0.5, "{success:?}");
+        assert!(success[1] < success[0], "{success:?}");
+        assert!(success[3] < 0.8, "{success:?}");
+        // Unknown category uses the overall skill, unknown task the average accuracy
+        assert!(success[4] > 0.0 && success[4] < 1.0, "{success:?}");
+    }
+
+    #[test]
+    fn test_order_by_target() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut first_near_target = 0;
+        for _ in 0..100 {
+            let order = order_by_target(vec![(1, 0.3), (2, 0.8), (3, 0.99)], 0.8, &mut rng);
+            assert_eq!(order.len(), 3);
+            if order[0] == 2 {
+                first_near_target += 1;
+            }
+        }
+        assert!(first_near_target > 80, "{first_near_target}");
+    }
+}
@@ -0,0 +1,110 @@
+use anyhow::Result;
+use teloxide::{payloads::SendMessageSetters, requests::Requester, Bot};
+
+use crate::metrics::SendMeasured;
+
+use super::{
+    bot_core::{BotContext, BotErrors},
+    bot_services::{TaskInfoService, UserStateService},
+};
+
+const FEEDBACK_HISTORY_SIZE: usize = 20;
+
+impl<T: TaskInfoService, U: UserStateService> BotContext<T, U> {
+    pub(super) async fn send_feedback(
+        &self,
+        bot: &Bot,
+        text: Option<&str>,
+        message: &teloxide::types::Message,
+    ) -> Result<()> {
+        let feedback_chat_id = self.feedback_chat_id.ok_or(BotErrors::NoFeedbackChatId)?;
+
+        let text = match text {
+            Some(text) => text,
+            None => {
+                bot.send_message(message.chat.id, "Пожалуйста, напишите текст, что хотите отправить. Можно ответить на сообщение бота, чтобы сослаться на него.").send_measured().await?;
+                return Ok(());
+            }
+        };
+
+        let username = message
+            .from()
+            .as_ref()
+            .map(|user| {
+                format!(
+                    "@{} ({})",
+                    user.username.as_deref().unwrap_or("unknown"),
+                    user.full_name()
+                )
+            })
+            .unwrap_or_default();
+
+        let reply = message
+            .reply_to_message()
+            .map(|r| r.text().unwrap_or_default())
+            .map(|r| format!("\n\nReply to:\n\n{}", r))
+            .unwrap_or_default();
+
+        let feedback_text = format!("Feedback from {username}:\n\n{text}{reply}\n\nReply to this message to answer");
+        let forwarded = bot.send_message(feedback_chat_id, feedback_text).send_measured().await?;
+        self.user_data
+            .record_feedback(message.chat.id, false, text, forwarded.id)
+            .await?;
+        bot.send_message(message.chat.id, "Спасибо за отзыв!").send_measured().await?;
+
+        Ok(())
+    }
+
+    /// Replies to feedback messages are delivered to their authors, `/history` as a reply shows the conversation.
+    /// Admin replies to task report notifications annotate the task.
+    pub(super) async fn handle_feedback_chat(&self, bot: &Bot, message: &teloxide::types::Message) -> Result<()> {
+        let (Some(text), Some(replied)) = (message.text(), message.reply_to_message()) else {
+            return Ok(());
+        };
+        if let Some(task_id) = self.user_data.find_reported_task(replied.id).await? {
+            return self.annotate_reported_task(bot, task_id, message).await;
+        }
+        let Some(user_chat_id) = self.user_data.find_feedback_chat(replied.id).await? else {
+            return Ok(());
+        };
+
+        if text.trim().starts_with("/history") {
+            let history = self.user_data.get_feedback_history(user_chat_id, FEEDBACK_HISTORY_SIZE).await?;
+            let mut history_text = format!("Conversation with #{user_chat_id}:");
+            for feedback in history {
+                history_text.push_str(&format!(
+                    "\n\n{author} {at}\n{text}",
+                    author = if feedback.from_admin { "🛠 Admin" } else { "👤 User" },
+                    at = feedback.created_at.date(),
+                    text = feedback.text,
+                ));
+            }
+            bot.send_message(message.chat.id, history_text)
+                .reply_to_message_id(message.id)
+                .send_measured()
+                .await?;
+            return Ok(());
+        }
+
+        let delivered = bot
+            .send_message(
+                user_chat_id,
+                format!("Ответ на ваш отзыв:\n\n{text}\n\nОтветить можно командой /feedback"),
+            )
+            .send_measured()
+            .await;
+
+        let status = match delivered {
+            Ok(_) => {
+                self.user_data.record_feedback(user_chat_id, true, text, message.id).await?;
+                "✅ Delivered".to_owned()
+            }
+            Err(err) => format!("❌ Not delivered: {err}"),
+        };
+        bot.send_message(message.chat.id, status)
+            .reply_to_message_id(message.id)
+            .send_measured()
+            .await?;
+        Ok(())
+    }
+}
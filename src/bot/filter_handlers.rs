@@ -1,12 +1,13 @@
 use indoc::indoc;
 use teloxide::{
     payloads::SendMessageSetters,
-    requests::{Request, Requester},
+    requests::Requester,
     types::{ChatId, ParseMode},
     Bot,
 };
 
-use crate::utils::escape_telegram_symbols;
+use crate::metrics::SendMeasured;
+use crate::utils::{escape_telegram_symbols, rus_numeric};
 
 use super::{
     bot_core::BotContext,
@@ -14,9 +15,6 @@ use super::{
     bot_services::{TaskInfoService, UserStateService},
 };
 
-#[derive(Debug, thiserror::Error)]
-pub(super) enum FilterErrors {}
-
 impl<T: TaskInfoService, U: UserStateService> BotContext<T, U> {
     pub(super) async fn handle_filter(
         &self,
@@ -30,12 +28,13 @@ impl<T: TaskInfoService, U: UserStateService> BotContext<T, U> {
         }
     }
 
-    async fn change_filter(&self, bot: &Bot, filter_text: &str, chat_id: ChatId) -> anyhow::Result<()> {
+    pub(super) async fn change_filter(&self, bot: &Bot, filter_text: &str, chat_id: ChatId) -> anyhow::Result<()> {
         if filter_text == "-" {
             let mut user_state = self.user_data.get_state(chat_id).await?;
             user_state.filter = None;
             self.user_data.update_state(chat_id, user_state).await?;
             self.user_data.update_tasks(chat_id, &[]).await?;
+            self.cancel_placement(chat_id);
 
             self.ask_next_task(bot, chat_id).await?;
             return Ok(());
@@ -52,12 +51,32 @@ impl<T: TaskInfoService, U: UserStateService> BotContext<T, U> {
             user_state.filter = Some(filter_text.into());
             self.user_data.update_state(chat_id, user_state).await?;
             self.user_data.update_tasks(chat_id, &[]).await?;
+            self.cancel_placement(chat_id);
 
             self.ask_next_task(bot, chat_id).await?;
         }
         Ok(())
     }
 
+    /// Tells how many tasks are left before the queue is built again.
+    pub(super) async fn handle_queue(&self, bot: &Bot, chat_id: ChatId) -> anyhow::Result<()> {
+        let queue = self.user_data.peek_tasks(chat_id, 0).await?;
+        let filter = self.user_data.get_state(chat_id).await?.filter;
+        let filter = match filter {
+            Some(filter) => format!(" по фильтру «{filter}»"),
+            None => "".to_owned(),
+        };
+        let text = match queue.remaining {
+            0 => format!("Очередь пуста, следующее задание{filter} подберу заново: /start"),
+            remaining => format!(
+                "Осталось {remaining} {tasks}{filter}, потом я подберу задания заново.",
+                tasks = rus_numeric(remaining, "задач", "задача", "задачи"),
+            ),
+        };
+        bot.send_message(chat_id, text).send_measured().await?;
+        Ok(())
+    }
+
     async fn handle_filter_help(&self, bot: &Bot, chat_id: ChatId) -> anyhow::Result<()> {
         let mut message = indoc! {r#"
         Фильтр позволяет выбрать задания по определенным критериям.
@@ -82,7 +101,7 @@ impl<T: TaskInfoService, U: UserStateService> BotContext<T, U> {
         let message = escape_telegram_symbols(&message, ".-*_()[]");
         bot.send_message(chat_id, message)
             .parse_mode(ParseMode::MarkdownV2)
-            .send()
+            .send_measured()
             .await?;
         Ok(())
     }
@@ -0,0 +1,196 @@
+use std::{
+    sync::{Arc, RwLock},
+    time::Instant,
+};
+
+use teloxide::{
+    payloads::SendMessageSetters,
+    requests::Requester,
+    types::{ChatId, InlineKeyboardButton, InlineKeyboardButtonKind, ParseMode},
+    Bot,
+};
+
+use crate::metrics::SendMeasured;
+use crate::{
+    model::{GrammarCard, Task, TaskId},
+    utils::escape_telegram_symbols,
+};
+
+use super::{
+    achievement_handlers::USED_HINT_TTL,
+    bot_core::{encode_command, parse_command, BotContext, BotErrors},
+    bot_services::{TaskInfoService, UserStateService},
+    proto::{self, command::Command},
+};
+
+pub(super) type GrammarCards = Arc<RwLock<Vec<GrammarCard>>>;
+
+const MARKDOWN_SYMBOLS: &str = "_*[]()~`>#+-=|{}.!\\";
+
+pub(super) fn grammar_button(task_id: TaskId) -> InlineKeyboardButton {
+    let command = Command::ShowGrammar(proto::ShowGrammar { task_id });
+    InlineKeyboardButton::callback("📖", encode_command(command))
+}
+
+impl<T: TaskInfoService, U: UserStateService> BotContext<T, U> {
+    /// Cards explaining the filter values of the task.
+    pub(super) fn grammar_cards_for(&self, task: &Task) -> Vec<GrammarCard> {
+        let cards = self.grammar_cards.read().unwrap();
+        cards
+            .iter()
+            .filter(|card| {
+                task.filters
+                    .iter()
+                    .any(|filter| filter.name == card.name && filter.value.eq_ignore_ascii_case(&card.value))
+            })
+            .cloned()
+            .collect()
+    }
+
+    pub(super) async fn handle_grammar(
+        &self,
+        bot: &Bot,
+        command_text: Option<&str>,
+        chat_id: ChatId,
+    ) -> anyhow::Result<()> {
+        let query = command_text.map(str::trim).unwrap_or_default().to_lowercase();
+        let (found, values) = {
+            let cards = self.grammar_cards.read().unwrap();
+            let found = cards
+                .iter()
+                .filter(|card| !query.is_empty() && card.value.to_lowercase() == query)
+                .cloned()
+                .collect::<Vec<_>>();
+            let values = cards.iter().map(|card| card.value.clone()).collect::<Vec<_>>();
+            (found, values)
+        };
+
+        if found.is_empty() {
+            let text = match (query.is_empty(), values.is_empty()) {
+                (_, true) => "Справочник пока пуст.".to_owned(),
+                (true, false) => format!(
+                    "Справка есть для: {}. Например, /grammar {}",
+                    values.join(", "),
+                    values[0]
+                ),
+                (false, false) => format!("Нет справки по «{query}». Справка есть для: {}", values.join(", ")),
+            };
+            bot.send_message(chat_id, text).send_measured().await?;
+            return Ok(());
+        }
+
+        for card in &found {
+            bot.send_message(chat_id, render_card(card))
+                .parse_mode(ParseMode::MarkdownV2)
+                .send_measured()
+                .await?;
+        }
+        Ok(())
+    }
+
+    pub(super) async fn handle_show_grammar(
+        &self,
+        bot: &Bot,
+        show: &proto::ShowGrammar,
+        message: &teloxide::types::Message,
+    ) -> anyhow::Result<()> {
+        let task = self.tasks.get_task(show.task_id).await?.ok_or(BotErrors::NoTaskFound)?;
+        let cards = self.grammar_cards_for(&task);
+        // Cards opened under answered questions don't change any reward
+        if awaits_answer(message) {
+            let mut used_hints = self.used_hints.lock().unwrap();
+            used_hints.retain(|_, opened_at| opened_at.elapsed() < USED_HINT_TTL);
+            used_hints.entry((message.chat.id, message.id)).or_insert_with(Instant::now);
+        }
+        if cards.is_empty() {
+            bot.send_message(message.chat.id, "Для этого задания справки пока нет.")
+                .reply_to_message_id(message.id)
+                .send_measured()
+                .await?;
+        }
+        for card in &cards {
+            bot.send_message(message.chat.id, render_card(card))
+                .parse_mode(ParseMode::MarkdownV2)
+                .reply_to_message_id(message.id)
+                .send_measured()
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+/// True while the question still has answer buttons, answered ones keep only the task buttons.
+fn awaits_answer(message: &teloxide::types::Message) -> bool {
+    message.reply_markup().is_some_and(|markup| {
+        markup.inline_keyboard.iter().flatten().any(|button| match &button.kind {
+            InlineKeyboardButtonKind::CallbackData(data) => {
+                parse_command(data).is_ok_and(|command| matches!(command.command, Some(Command::QuestionAnswer(_))))
+            }
+            _ => false,
+        })
+    })
+}
+
+fn render_card(card: &GrammarCard) -> String {
+    let escape = |text: &str| escape_telegram_symbols(text, MARKDOWN_SYMBOLS);
+
+    let mut text = format!("📖 *{}*\n", escape(&card.title));
+    for usage in &card.usage {
+        text.push_str(&format!("\n• {}", escape(usage)));
+    }
+    if !card.table.trim().is_empty() {
+        // Only ` and \ are escaped inside code blocks
+        text.push_str(&format!(
+            "\n\n```\n{}\n```",
+            escape_telegram_symbols(card.table.trim_end(), "`\\")
+        ));
+    }
+    if !card.examples.is_empty() {
+        text.push_str("\n\n*Примеры:*");
+        for example in &card.examples {
+            text.push_str(&format!("\n{}", escape(&example.text)));
+            if !example.translation.is_empty() {
+                text.push_str(&format!("\n_{}_", escape(&example.translation)));
+            }
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod test {
+    use crate::model::GrammarExample;
+
+    use super::*;
+
+    #[test]
+    fn test_render_card() {
+        let card = GrammarCard {
+            name: "Падеж".into(),
+            value: "locative".into(),
+            title: "Локатив (местный падеж)".into(),
+            usage: vec!["u, na — место: u gradu".into()],
+            table: "м. р.  u gradu\n".into(),
+            examples: vec![GrammarExample {
+                text: "Živim u Beogradu.".into(),
+                translation: "Я живу в Белграде.".into(),
+            }],
+        };
+
+        assert_eq!(
+            render_card(&card),
+            indoc::indoc! {r#"
+                📖 *Локатив \(местный падеж\)*
+
+                • u, na — место: u gradu
+
+                ```
+                м. р.  u gradu
+                ```
+
+                *Примеры:*
+                Živim u Beogradu\.
+                _Я живу в Белграде\._"#}
+        );
+    }
+}
@@ -0,0 +1,374 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use indoc::indoc;
+use teloxide::{
+    payloads::{AnswerCallbackQuerySetters, EditMessageReplyMarkupSetters, EditMessageTextSetters, SendMessageSetters},
+    requests::Requester,
+    types::{CallbackQuery, ChatId, InlineKeyboardMarkup, MessageEntity, MessageId, ParseMode},
+    Bot,
+};
+use time::OffsetDateTime;
+
+use crate::metrics::SendMeasured;
+use crate::{model::Task, utils::rus_numeric};
+
+use super::{
+    ask_next_task_handler::{build_message, buttons_markup, MessageData},
+    bot_core::{answer_texts, reveal_question, BotContext},
+    bot_services::{Answer, TaskInfoService, UserStateService},
+    proto,
+};
+
+const DEFAULT_QUESTION_SECONDS: u64 = 30;
+const MIN_QUESTION_SECONDS: u64 = 10;
+const MAX_QUESTION_SECONDS: u64 = 300;
+
+static GROUP_HELP_TEXT: &str = indoc! {"
+    Викторина для группы: каждый может ответить на вопрос один раз, а когда время выйдет, я покажу правильный ответ и кто угадал.
+
+    /quiz — начать викторину (30 секунд на вопрос)
+    /quiz 60 — начать викторину с 60 секундами на вопрос
+    /stop — остановить викторину после текущего вопроса
+    /scoreboard — таблица лидеров группы
+    "};
+
+#[derive(Debug)]
+pub(super) struct GroupQuiz {
+    question_secs: u64,
+    stopped: bool,
+    question: Option<GroupQuestion>,
+}
+
+#[derive(Debug)]
+struct GroupQuestion {
+    message_id: MessageId,
+    task: Task,
+    text: String,
+    entities: Option<Vec<MessageEntity>>,
+    answers: Vec<GroupAnswer>,
+}
+
+#[derive(Debug)]
+struct GroupAnswer {
+    uid: i64,
+    name: String,
+    correct: bool,
+}
+
+pub(super) type GroupQuizzes = Arc<Mutex<HashMap<ChatId, GroupQuiz>>>;
+
+impl<T: TaskInfoService, U: UserStateService> BotContext<T, U> {
+    pub(super) async fn handle_group_message(
+        &self,
+        bot: &Bot,
+        command: &str,
+        command_text: Option<&str>,
+        chat_id: ChatId,
+    ) -> anyhow::Result<()> {
+        match command {
+            "quiz" | "start" => self.start_group_quiz(bot, command_text, chat_id).await,
+            "stop" => self.stop_group_quiz(bot, chat_id).await,
+            "scoreboard" => self.show_group_scoreboard(bot, chat_id).await,
+            "help" => {
+                bot.send_message(chat_id, GROUP_HELP_TEXT).send_measured().await?;
+                Ok(())
+            }
+            // Regular conversation in the group is not addressed to the bot
+            _ => Ok(()),
+        }
+    }
+
+    async fn start_group_quiz(&self, bot: &Bot, command_text: Option<&str>, chat_id: ChatId) -> anyhow::Result<()> {
+        let question_secs = match command_text.map(str::trim) {
+            None | Some("") => DEFAULT_QUESTION_SECONDS,
+            Some(text) => match text.parse::<u64>() {
+                Ok(secs) if (MIN_QUESTION_SECONDS..=MAX_QUESTION_SECONDS).contains(&secs) => secs,
+                _ => {
+                    bot.send_message(
+                        chat_id,
+                        format!(
+                            "Укажите время на вопрос в секундах от {MIN_QUESTION_SECONDS} до {MAX_QUESTION_SECONDS}, например /quiz 30"
+                        ),
+                    )
+                    .send_measured()
+                    .await?;
+                    return Ok(());
+                }
+            },
+        };
+
+        let reply = {
+            let mut quizzes = self.group_quizzes.lock().unwrap();
+            match quizzes.get_mut(&chat_id) {
+                Some(quiz) if !quiz.stopped => Some("Викторина уже идёт, отвечайте на текущий вопрос!"),
+                Some(quiz) => {
+                    // Stopped quiz is still waiting for the last question, just let it go on
+                    quiz.stopped = false;
+                    quiz.question_secs = question_secs;
+                    Some("Хорошо, продолжаем викторину после текущего вопроса!")
+                }
+                None => {
+                    quizzes.insert(
+                        chat_id,
+                        GroupQuiz {
+                            question_secs,
+                            stopped: false,
+                            question: None,
+                        },
+                    );
+                    None
+                }
+            }
+        };
+
+        match reply {
+            Some(reply) => {
+                bot.send_message(chat_id, reply).send_measured().await?;
+                Ok(())
+            }
+            None => {
+                let context = self.clone();
+                let bot = bot.clone();
+                tokio::spawn(async move {
+                    if let Err(err) = context.run_group_quiz(&bot, chat_id).await {
+                        log::error!("#{chat_id} group quiz failed: {err}");
+                        context.group_quizzes.lock().unwrap().remove(&chat_id);
+                    }
+                });
+                Ok(())
+            }
+        }
+    }
+
+    async fn run_group_quiz(&self, bot: &Bot, chat_id: ChatId) -> anyhow::Result<()> {
+        loop {
+            let Some((message_id, question_secs)) = self.ask_group_question(bot, chat_id).await? else {
+                return Ok(());
+            };
+            tokio::time::sleep(Duration::from_secs(question_secs)).await;
+            if !self.reveal_group_question(bot, chat_id, message_id).await? {
+                return Ok(());
+            }
+        }
+    }
+
+    async fn stop_group_quiz(&self, bot: &Bot, chat_id: ChatId) -> anyhow::Result<()> {
+        let running = {
+            let mut quizzes = self.group_quizzes.lock().unwrap();
+            match quizzes.get_mut(&chat_id) {
+                Some(quiz) => {
+                    quiz.stopped = true;
+                    true
+                }
+                None => false,
+            }
+        };
+
+        let text = match running {
+            true => "Викторина остановится после текущего вопроса.",
+            false => "Викторина не запущена, начните её командой /quiz",
+        };
+        bot.send_message(chat_id, text).send_measured().await?;
+        Ok(())
+    }
+
+    async fn ask_group_question(&self, bot: &Bot, chat_id: ChatId) -> anyhow::Result<Option<(MessageId, u64)>> {
+        let question_secs = {
+            let quizzes = self.group_quizzes.lock().unwrap();
+            match quizzes.get(&chat_id) {
+                Some(quiz) => quiz.question_secs,
+                None => return Ok(None),
+            }
+        };
+
+        // Group queues have no multi-gap tasks, see `Filter::single_gap`
+        let task = self.take_next_task(chat_id).await?.task;
+
+        let MessageData { mut message, buttons } = build_message(&task, self.distractors)?;
+        message.push_str(&format!("\n\n_⏱ {question_secs} с на ответ_"));
+
+        let sent = bot
+            .send_message(chat_id, message)
+            .parse_mode(ParseMode::MarkdownV2)
+            .reply_markup(buttons_markup(buttons))
+            .send_measured()
+            .await?;
+
+        {
+            let mut quizzes = self.group_quizzes.lock().unwrap();
+            if let Some(quiz) = quizzes.get_mut(&chat_id) {
+                quiz.question = Some(GroupQuestion {
+                    message_id: sent.id,
+                    task,
+                    text: sent.text().unwrap_or_default().to_owned(),
+                    entities: sent.entities().map(|entities| entities.to_vec()),
+                    answers: Vec::new(),
+                });
+            }
+        }
+
+        Ok(Some((sent.id, question_secs)))
+    }
+
+    pub(super) async fn handle_group_answer(
+        &self,
+        bot: &Bot,
+        query: &CallbackQuery,
+        answer: &proto::QuestionAnswer,
+        message: &teloxide::types::Message,
+    ) -> anyhow::Result<()> {
+        let chat_id = message.chat.id;
+        let uid = query.from.id.0 as i64;
+
+        let (reply, accepted) = {
+            let mut quizzes = self.group_quizzes.lock().unwrap();
+            let question = quizzes
+                .get_mut(&chat_id)
+                .and_then(|quiz| quiz.question.as_mut())
+                .filter(|question| question.message_id == message.id);
+
+            match question {
+                None => ("Этот вопрос уже закрыт", false),
+                Some(question) if question.answers.iter().any(|answer| answer.uid == uid) => {
+                    ("Вы уже ответили на этот вопрос", false)
+                }
+                Some(question) => {
+                    question.answers.push(GroupAnswer {
+                        uid,
+                        name: query.from.full_name(),
+                        correct: answer.is_correct,
+                    });
+                    ("Ответ принят! Результаты — когда выйдет время", true)
+                }
+            }
+        };
+
+        bot.answer_callback_query(query.id.clone()).text(reply).send_measured().await?;
+
+        if accepted {
+            let (answer_text, _) = answer_texts(message, answer.index)?;
+            let asked_at = OffsetDateTime::from_unix_timestamp(answer.time_asked_ts / 1000)?
+                + Duration::from_millis((answer.time_asked_ts % 1000) as u64);
+            self.user_data
+                .record_anwer(Answer {
+                    uid,
+                    task_id: answer.task_id,
+                    gap: answer.gap,
+                    answer_index: answer.index,
+                    answer_text: Some(answer_text),
+                    correct: answer.is_correct,
+                    asked_at,
+                    answered_at: OffsetDateTime::now_utc(),
+                })
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Closes the question and returns whether the quiz goes on.
+    async fn reveal_group_question(&self, bot: &Bot, chat_id: ChatId, message_id: MessageId) -> anyhow::Result<bool> {
+        let (question, continue_quiz) = {
+            let mut quizzes = self.group_quizzes.lock().unwrap();
+            let Some(quiz) = quizzes.get_mut(&chat_id) else {
+                return Ok(false);
+            };
+            let question = match quiz.question.take() {
+                Some(question) if question.message_id == message_id => question,
+                other => {
+                    quiz.question = other;
+                    return Ok(false);
+                }
+            };
+
+            let continue_quiz = !quiz.stopped && !question.answers.is_empty();
+            if !continue_quiz {
+                quizzes.remove(&chat_id);
+            }
+            (question, continue_quiz)
+        };
+
+        let (mut text, entities) = reveal_question(&question.text, question.entities.as_deref());
+        text.push_str("\n\n✅ ");
+        text.push_str(&question.task.correct);
+        text.push_str("\n📝 ");
+        text.push_str(&question.task.task);
+        text.push_str("\n\n");
+
+        let winners = question
+            .answers
+            .iter()
+            .filter(|answer| answer.correct)
+            .map(|answer| answer.name.as_str())
+            .collect::<Vec<_>>();
+        text.push_str(&match (winners.len(), question.answers.len()) {
+            (_, 0) => "Никто не ответил 😴".to_owned(),
+            (0, _) => "Никто не ответил правильно 🥺".to_owned(),
+            (_, answered) => format!(
+                "Правильно ответили {} из {answered}: {}",
+                winners.len(),
+                winners.join(", ")
+            ),
+        });
+
+        bot.edit_message_reply_markup(chat_id, message_id)
+            .reply_markup(InlineKeyboardMarkup::default())
+            .send_measured()
+            .await?;
+
+        let mut call = bot.edit_message_text(chat_id, message_id, text);
+        if let Some(entities) = entities {
+            call = call.entities(entities);
+        }
+        call.send_measured().await?;
+
+        let results = question
+            .answers
+            .iter()
+            .map(|answer| (answer.uid, answer.correct))
+            .collect::<Vec<_>>();
+        self.user_data.update_group_scores(chat_id, &results).await?;
+
+        if !continue_quiz {
+            bot.send_message(
+                chat_id,
+                "Викторина окончена. Таблица лидеров — /scoreboard, продолжить — /quiz",
+            )
+            .send_measured()
+            .await?;
+        }
+        Ok(continue_quiz)
+    }
+
+    async fn show_group_scoreboard(&self, bot: &Bot, chat_id: ChatId) -> anyhow::Result<()> {
+        let scores = self.user_data.get_group_scoreboard(chat_id, 10).await?;
+        if scores.is_empty() {
+            bot.send_message(
+                chat_id,
+                "В этой группе ещё никто не отвечал. Начните викторину командой /quiz",
+            )
+            .send_measured()
+            .await?;
+            return Ok(());
+        }
+
+        let mut text = "🏆 Таблица лидеров группы:\n".to_owned();
+        for (place, score) in scores.iter().enumerate() {
+            text.push_str(&format!(
+                "\n{place}. {name} — {correct} из {answered} {tasks}",
+                place = place + 1,
+                name = score.full_name,
+                correct = score.correct,
+                answered = score.answered,
+                tasks = rus_numeric(score.answered as usize, "задач", "задачи", "задач"),
+            ));
+        }
+
+        bot.send_message(chat_id, text).send_measured().await?;
+        Ok(())
+    }
+}
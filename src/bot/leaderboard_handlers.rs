@@ -0,0 +1,234 @@
+use std::time::Duration;
+
+use indoc::indoc;
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use teloxide::{requests::Requester, types::ChatId, Bot};
+
+use crate::metrics::SendMeasured;
+use crate::utils::rus_numeric;
+
+use super::{
+    bot_core::BotContext,
+    bot_services::{LeaderboardEntry, TaskInfoService, UserStateService, XpLeaderboardEntry},
+};
+
+const LEADERBOARD_PERIOD: Duration = Duration::from_secs(60 * 60 * 24 * 7);
+const LEADERBOARD_SIZE: usize = 10;
+const FRIEND_INVITE_PREFIX: &str = "friend_";
+/// Random, so friendships need a link the user shared rather than a guessable id
+const INVITE_TOKEN_LENGTH: usize = 16;
+
+static TOP_HELP_TEXT: &str = indoc! {"
+    /top — рейтинг недели по правильным ответам
+    /top xp — рейтинг недели по опыту
+    /top friends — рейтинг среди друзей
+    /top on — показывать меня в общем рейтинге
+    /top off — скрыть меня из общего рейтинга
+    /invite — ссылка, чтобы добавить друга
+    "};
+
+impl<T: TaskInfoService, U: UserStateService> BotContext<T, U> {
+    pub(super) async fn handle_top(
+        &self,
+        bot: &Bot,
+        command_text: Option<&str>,
+        chat_id: ChatId,
+        uid: i64,
+    ) -> anyhow::Result<()> {
+        match command_text.map(str::trim) {
+            None | Some("") => self.show_global_top(bot, chat_id, uid).await,
+            Some("friends") => self.show_friends_top(bot, chat_id, uid).await,
+            Some("xp") => self.show_xp_top(bot, chat_id, uid).await,
+            Some("on") => {
+                self.user_data.set_public_profile(uid, true).await?;
+                bot.send_message(chat_id, "Теперь вы участвуете в общем рейтинге. Скрыться — /top off")
+                    .send_measured()
+                    .await?;
+                Ok(())
+            }
+            Some("off") => {
+                self.user_data.set_public_profile(uid, false).await?;
+                bot.send_message(
+                    chat_id,
+                    "Вы скрыты из общего рейтинга. Друзья по-прежнему видят ваше имя и число правильных ответов за неделю в /top friends.",
+                )
+                .send_measured()
+                .await?;
+                Ok(())
+            }
+            Some(_) => {
+                bot.send_message(chat_id, TOP_HELP_TEXT).send_measured().await?;
+                Ok(())
+            }
+        }
+    }
+
+    async fn show_global_top(&self, bot: &Bot, chat_id: ChatId, uid: i64) -> anyhow::Result<()> {
+        let entries = self
+            .user_data
+            .get_leaderboard(LEADERBOARD_PERIOD, None, LEADERBOARD_SIZE)
+            .await?;
+
+        let mut text = "🏆 Рейтинг недели по правильным ответам:\n".to_owned();
+        text.push_str(&format_leaderboard(&entries, uid));
+
+        if !entries.iter().any(|entry| entry.uid == uid) {
+            let stat = self.user_data.get_answer_stat(uid, LEADERBOARD_PERIOD).await?;
+            text.push_str(&format!(
+                "\n\nВаш результат за неделю: {correct} правильно из {count}.",
+                correct = stat.correct,
+                count = stat.count
+            ));
+            if !self.user_data.is_public_profile(uid).await? {
+                text.push_str("\nВы не участвуете в общем рейтинге, включить — /top on");
+            }
+        }
+
+        text.push_str("\n\nРейтинг по опыту — /top xp, рейтинг друзей — /top friends");
+        bot.send_message(chat_id, text).send_measured().await?;
+        Ok(())
+    }
+
+    async fn show_xp_top(&self, bot: &Bot, chat_id: ChatId, uid: i64) -> anyhow::Result<()> {
+        let entries = self
+            .user_data
+            .get_xp_leaderboard(LEADERBOARD_PERIOD, None, LEADERBOARD_SIZE)
+            .await?;
+
+        let mut text = "⭐ Рейтинг недели по опыту:\n".to_owned();
+        text.push_str(&format_xp_leaderboard(&entries, uid));
+
+        if !entries.iter().any(|entry| entry.uid == uid) {
+            let xp = self
+                .user_data
+                .get_xp_leaderboard(LEADERBOARD_PERIOD, Some(&[uid]), 1)
+                .await?
+                .first()
+                .map(|entry| entry.xp)
+                .unwrap_or_default();
+            text.push_str(&format!("\n\nВаш опыт за неделю: {xp} XP."));
+            if !self.user_data.is_public_profile(uid).await? {
+                text.push_str("\nВы не участвуете в общем рейтинге, включить — /top on");
+            }
+        }
+
+        bot.send_message(chat_id, text).send_measured().await?;
+        Ok(())
+    }
+
+    async fn show_friends_top(&self, bot: &Bot, chat_id: ChatId, uid: i64) -> anyhow::Result<()> {
+        let mut uids = self.user_data.get_friends(uid).await?;
+        if uids.is_empty() {
+            bot.send_message(chat_id, "У вас пока нет друзей в боте. Отправьте им ссылку из /invite")
+                .send_measured()
+                .await?;
+            return Ok(());
+        }
+        uids.push(uid);
+
+        let entries = self
+            .user_data
+            .get_leaderboard(LEADERBOARD_PERIOD, Some(&uids), uids.len())
+            .await?;
+
+        let mut text = "👥 Рейтинг друзей за неделю:\n".to_owned();
+        text.push_str(&format_leaderboard(&entries, uid));
+        bot.send_message(chat_id, text).send_measured().await?;
+        Ok(())
+    }
+
+    pub(super) async fn send_invite(&self, bot: &Bot, chat_id: ChatId, uid: i64) -> anyhow::Result<()> {
+        let me = bot.get_me().send_measured().await?;
+        let token = self.user_data.get_invite_token(uid, &new_invite_token()).await?;
+        bot.send_message(
+            chat_id,
+            format!(
+                "Отправьте эту ссылку другу, чтобы соревноваться в рейтинге друзей (/top friends). Кто откроет её, станет вашим другом и увидит ваше имя и число правильных ответов за неделю:\n\nhttps://t.me/{bot_name}?start={FRIEND_INVITE_PREFIX}{token}",
+                bot_name = me.username(),
+            ),
+        )
+        .send_measured()
+        .await?;
+        Ok(())
+    }
+
+    /// Handles `/start friend_<token>` from an invite link, other payloads and unknown tokens are ignored.
+    pub(super) async fn accept_invite(
+        &self,
+        bot: &Bot,
+        payload: Option<&str>,
+        chat_id: ChatId,
+        uid: i64,
+        full_name: &str,
+    ) -> anyhow::Result<()> {
+        let Some(token) = payload.and_then(|payload| payload.trim().strip_prefix(FRIEND_INVITE_PREFIX)) else {
+            return Ok(());
+        };
+        let Some(friend_uid) = self.user_data.find_invite_owner(token).await? else {
+            log::info!("#{chat_id} opened an unknown invite");
+            return Ok(());
+        };
+
+        if friend_uid != uid && self.user_data.add_friend(uid, friend_uid).await? {
+            bot.send_message(chat_id, "Вы добавлены в друзья! Сравнивайте успехи в /top friends")
+                .send_measured()
+                .await?;
+            if let Err(err) = bot
+                .send_message(
+                    ChatId(friend_uid),
+                    format!("{full_name} теперь в ваших друзьях! 👋 /top friends"),
+                )
+                .send_measured()
+                .await
+            {
+                log::warn!("#{chat_id} could not notify friend {friend_uid}: {err}");
+            }
+        }
+        Ok(())
+    }
+}
+
+fn new_invite_token() -> String {
+    thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(INVITE_TOKEN_LENGTH)
+        .map(char::from)
+        .collect()
+}
+
+fn format_xp_leaderboard(entries: &[XpLeaderboardEntry], uid: i64) -> String {
+    if entries.is_empty() {
+        return "\nПока никто не получал опыт на этой неделе.".to_owned();
+    }
+
+    let mut text = String::new();
+    for (place, entry) in entries.iter().enumerate() {
+        text.push_str(&format!(
+            "\n{place}. {name} — {xp} XP{me}",
+            place = place + 1,
+            name = entry.full_name,
+            xp = entry.xp,
+            me = if entry.uid == uid { " 👈" } else { "" },
+        ));
+    }
+    text
+}
+
+fn format_leaderboard(entries: &[LeaderboardEntry], uid: i64) -> String {
+    if entries.is_empty() {
+        return "\nПока никто не отвечал на этой неделе.".to_owned();
+    }
+
+    let mut text = String::new();
+    for (place, entry) in entries.iter().enumerate() {
+        text.push_str(&format!(
+            "\n{place}. {name} — {correct} {answers}{me}",
+            place = place + 1,
+            name = entry.full_name,
+            correct = entry.correct,
+            answers = rus_numeric(entry.correct as usize, "правильных", "правильный", "правильных"),
+            me = if entry.uid == uid { " 👈" } else { "" },
+        ));
+    }
+    text
+}
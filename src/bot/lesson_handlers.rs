@@ -0,0 +1,304 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, RwLock},
+};
+
+use teloxide::{
+    payloads::SendMessageSetters,
+    requests::Requester,
+    types::{ChatId, InlineKeyboardButton, InlineKeyboardMarkup},
+    Bot,
+};
+
+use crate::metrics::SendMeasured;
+use crate::model::{read_curriculum, Curriculum, Lesson, TaskId};
+
+use super::{
+    bot_core::{encode_command, BotContext},
+    bot_filter::parse_filter,
+    bot_services::{TaskInfoService, UserStateService},
+    proto::{self, command::Command},
+};
+
+pub(super) type CurriculumState = Arc<RwLock<Arc<Lessons>>>;
+
+/// Curriculum with the tasks of every lesson, lessons are defined by filters so they are looked up on load.
+#[derive(Debug, Default)]
+pub(super) struct Lessons {
+    curriculum: Curriculum,
+    /// Active task ids in curriculum order
+    task_ids: Vec<HashSet<TaskId>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum LessonStatus {
+    Locked,
+    Open,
+    Passed,
+}
+
+#[derive(Debug)]
+struct LessonProgress {
+    lesson: Lesson,
+    answered: i64,
+    correct: i64,
+    status: LessonStatus,
+}
+
+impl<T: TaskInfoService, U: UserStateService> BotContext<T, U> {
+    /// Reads the curriculum and finds the lesson tasks, on start and after the tasks are reloaded.
+    pub(super) async fn load_curriculum(&self) -> anyhow::Result<()> {
+        let curriculum = read_curriculum(&self.data_dir)?;
+        let mut task_ids = Vec::new();
+        for lesson in &curriculum.lessons {
+            let ids = self.tasks.get_task_ids(Some(&parse_filter(&lesson.filter_text()))).await?;
+            task_ids.push(ids.into_iter().collect());
+        }
+        *self.curriculum.write().unwrap() = Arc::new(Lessons { curriculum, task_ids });
+        Ok(())
+    }
+
+    pub(super) async fn handle_lessons(&self, bot: &Bot, chat_id: ChatId, uid: i64) -> anyhow::Result<()> {
+        let (progress, _) = self.lesson_progress(uid).await?;
+        if progress.is_empty() {
+            bot.send_message(chat_id, "Уроки пока не готовы, а задания доступны всегда: /start")
+                .send_measured()
+                .await?;
+            return Ok(());
+        }
+
+        let current_filter = self.user_data.get_state(chat_id).await?.filter;
+        let (unlock_threshold, min_answers) = {
+            let lessons = self.curriculum.read().unwrap();
+            (lessons.curriculum.unlock_threshold, lessons.curriculum.min_answers)
+        };
+
+        let mut text = "📚 Уроки\n".to_owned();
+        let mut buttons = Vec::new();
+        for (index, lesson) in progress.iter().enumerate() {
+            let number = index + 1;
+            let mark = match lesson.status {
+                LessonStatus::Locked => "🔒",
+                LessonStatus::Open => "▶️",
+                LessonStatus::Passed => "✅",
+            };
+            let current = match current_filter.as_deref() == Some(lesson.lesson.filter_text().as_str()) {
+                true => " 👈",
+                false => "",
+            };
+            text.push_str(&format!("\n{mark} {number}. {}{current}", lesson.lesson.title));
+            if lesson.status != LessonStatus::Locked && lesson.answered > 0 {
+                text.push_str(&format!(" — {} из {}", lesson.correct, lesson.answered));
+            }
+
+            if lesson.status != LessonStatus::Locked {
+                let command = Command::StartLesson(proto::StartLesson {
+                    lesson_id: lesson.lesson.id.clone(),
+                });
+                buttons.push(vec![InlineKeyboardButton::callback(
+                    format!("{number}. {}", lesson.lesson.title),
+                    encode_command(command),
+                )]);
+            }
+        }
+        text.push_str(&format!(
+            "\n\nСледующий урок открывается, когда в текущем верно {}% из не менее {min_answers} ответов.",
+            (unlock_threshold * 100.0).round()
+        ));
+
+        bot.send_message(chat_id, text)
+            .reply_markup(InlineKeyboardMarkup::new(buttons))
+            .send_measured()
+            .await?;
+        Ok(())
+    }
+
+    /// Switches the learner's filter to the lesson tasks.
+    pub(super) async fn handle_start_lesson(
+        &self,
+        bot: &Bot,
+        chat_id: ChatId,
+        uid: i64,
+        start: &proto::StartLesson,
+    ) -> anyhow::Result<()> {
+        let (progress, _) = self.lesson_progress(uid).await?;
+        let Some(lesson) = progress.into_iter().find(|lesson| lesson.lesson.id == start.lesson_id) else {
+            bot.send_message(chat_id, "Такого урока больше нет, посмотрите список: /lessons")
+                .send_measured()
+                .await?;
+            return Ok(());
+        };
+        if lesson.status == LessonStatus::Locked {
+            bot.send_message(chat_id, "Этот урок ещё закрыт, сначала пройдите предыдущий: /lessons")
+                .send_measured()
+                .await?;
+            return Ok(());
+        }
+
+        bot.send_message(chat_id, format!("📚 Урок «{}»", lesson.lesson.title))
+            .send_measured()
+            .await?;
+        self.change_filter(bot, &lesson.lesson.filter_text(), chat_id).await
+    }
+
+    /// Unlocks lessons the learner has reached with the answer and tells about them.
+    pub(super) async fn check_lesson_unlocks(
+        &self,
+        bot: &Bot,
+        chat_id: ChatId,
+        uid: i64,
+        task_id: TaskId,
+    ) -> anyhow::Result<()> {
+        // Only passing a lesson unlocks the next one, answers to other tasks change nothing
+        let next_lessons = {
+            let lessons = self.curriculum.read().unwrap();
+            lessons
+                .task_ids
+                .iter()
+                .zip(lessons.curriculum.lessons.iter().skip(1))
+                .filter(|(task_ids, _)| task_ids.contains(&task_id))
+                .map(|(_, next)| next.id.clone())
+                .collect::<Vec<_>>()
+        };
+        if next_lessons.is_empty() {
+            return Ok(());
+        }
+        let unlocked = self.user_data.get_unlocked_lessons(uid).await?;
+        if next_lessons.iter().all(|lesson_id| unlocked.contains(lesson_id)) {
+            return Ok(());
+        }
+
+        let (_, unlocked) = self.lesson_progress(uid).await?;
+        for lesson in unlocked {
+            bot.send_message(
+                chat_id,
+                format!("🔓 Открыт новый урок «{}»! Перейти к нему: /lessons", lesson.title),
+            )
+            .send_measured()
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Progress in curriculum order and the lessons unlocked by this call.
+    async fn lesson_progress(&self, uid: i64) -> anyhow::Result<(Vec<LessonProgress>, Vec<Lesson>)> {
+        let lessons = self.curriculum.read().unwrap().clone();
+        if lessons.curriculum.lessons.is_empty() {
+            return Ok((Vec::new(), Vec::new()));
+        }
+
+        let counts = self
+            .user_data
+            .get_task_answer_counts(uid)
+            .await?
+            .into_iter()
+            .map(|count| (count.task_id, (count.answered, count.correct)))
+            .collect::<HashMap<_, _>>();
+
+        let lesson_counts = lessons
+            .task_ids
+            .iter()
+            .map(|task_ids| {
+                task_ids
+                    .iter()
+                    .filter_map(|task_id| counts.get(task_id))
+                    .fold((0, 0), |(answered, correct), count| {
+                        (answered + count.0, correct + count.1)
+                    })
+            })
+            .collect::<Vec<_>>();
+
+        let unlocked = self.user_data.get_unlocked_lessons(uid).await?.into_iter().collect();
+        let statuses = lesson_statuses(&lessons.curriculum, &lesson_counts, &unlocked);
+
+        let mut newly_unlocked = Vec::new();
+        let mut progress = Vec::new();
+        for (index, ((lesson, (answered, correct)), status)) in
+            lessons.curriculum.lessons.iter().zip(lesson_counts).zip(statuses).enumerate()
+        {
+            // The first lesson is open from the start, it is not an unlock
+            if index > 0
+                && status != LessonStatus::Locked
+                && !unlocked.contains(&lesson.id)
+                && self.user_data.unlock_lesson(uid, &lesson.id).await?
+            {
+                newly_unlocked.push(lesson.clone());
+            }
+            progress.push(LessonProgress {
+                lesson: lesson.clone(),
+                answered,
+                correct,
+                status,
+            });
+        }
+        Ok((progress, newly_unlocked))
+    }
+}
+
+/// The first lesson is always open, the next one opens when the previous is passed.
+/// Unlocked lessons stay open, even if the accuracy drops later.
+fn lesson_statuses(curriculum: &Curriculum, counts: &[(i64, i64)], unlocked: &HashSet<String>) -> Vec<LessonStatus> {
+    let mut previous_passed = true;
+    curriculum
+        .lessons
+        .iter()
+        .zip(counts)
+        .map(|(lesson, &(answered, correct))| {
+            let open = previous_passed || unlocked.contains(&lesson.id);
+            let passed = open
+                && answered >= curriculum.min_answers
+                && correct as f64 >= curriculum.unlock_threshold * answered as f64;
+            previous_passed = passed;
+            match (open, passed) {
+                (false, _) => LessonStatus::Locked,
+                (true, false) => LessonStatus::Open,
+                (true, true) => LessonStatus::Passed,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn curriculum() -> Curriculum {
+        let lesson = |id: &str| Lesson {
+            id: id.into(),
+            title: id.into(),
+            themes: Vec::new(),
+            filter: Some(id.into()),
+        };
+        Curriculum {
+            unlock_threshold: 0.8,
+            min_answers: 10,
+            lessons: vec![lesson("locative"), lesson("accusative"), lesson("dative")],
+        }
+    }
+
+    #[test]
+    fn test_lesson_statuses() {
+        let curriculum = curriculum();
+        let no_unlocks = HashSet::new();
+
+        assert_eq!(
+            lesson_statuses(&curriculum, &[(0, 0), (0, 0), (0, 0)], &no_unlocks),
+            vec![LessonStatus::Open, LessonStatus::Locked, LessonStatus::Locked]
+        );
+        // Not enough answers yet, even if all are correct
+        assert_eq!(
+            lesson_statuses(&curriculum, &[(9, 9), (0, 0), (0, 0)], &no_unlocks),
+            vec![LessonStatus::Open, LessonStatus::Locked, LessonStatus::Locked]
+        );
+        assert_eq!(
+            lesson_statuses(&curriculum, &[(10, 8), (20, 15), (0, 0)], &no_unlocks),
+            vec![LessonStatus::Passed, LessonStatus::Open, LessonStatus::Locked]
+        );
+        // Lessons unlocked before stay open
+        let unlocked = HashSet::from(["accusative".to_owned(), "dative".to_owned()]);
+        assert_eq!(
+            lesson_statuses(&curriculum, &[(10, 5), (20, 5), (0, 0)], &unlocked),
+            vec![LessonStatus::Open, LessonStatus::Open, LessonStatus::Open]
+        );
+    }
+}
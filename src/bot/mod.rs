@@ -1,11 +1,27 @@
-pub use crate::bot::bot_core::{setup_and_run_bot, BotConfig};
+pub use crate::bot::bot_core::{setup_and_run_bot, BotConfig, UpdatesMode};
 
+mod achievement_handlers;
+mod admin_handlers;
 mod ask_next_task_handler;
+mod blitz_handlers;
 mod bot_core;
 pub mod bot_filter;
 pub mod bot_services;
 pub mod bot_services_in_mem;
+pub mod bot_services_measured;
+mod broadcast_handlers;
+#[cfg(test)]
+pub mod conformance;
+mod difficulty;
+mod feedback_handlers;
 mod filter_handlers;
+mod grammar_handlers;
+mod group_quiz_handlers;
+mod leaderboard_handlers;
+mod lesson_handlers;
+mod placement_handlers;
+mod privacy_handlers;
+mod report_handlers;
 
 pub mod proto {
     include!(concat!(env!("OUT_DIR"), "/bot.proto.rs"));
@@ -0,0 +1,354 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+use rand::{seq::SliceRandom, thread_rng, Rng};
+use teloxide::{
+    payloads::SendMessageSetters,
+    requests::Requester,
+    types::{ChatId, InlineKeyboardButton, InlineKeyboardMarkup},
+    Bot,
+};
+
+use crate::metrics::SendMeasured;
+use crate::model::{FilterValue, Task, TaskId};
+
+use super::{
+    bot_core::{encode_command, BotContext},
+    bot_services::{TaskInfoService, UserStateService},
+    proto::{self, command::Command},
+};
+
+/// More than the answers the difficulty model needs to trust the learner accuracy
+const PLACEMENT_SIZE: usize = 12;
+/// Values of this filter are suggested to practice after the test
+const SUGGESTED_FILTER_NAME: &str = "Падеж";
+const MASTERY_THRESHOLD: f64 = 0.7;
+const MAX_SUGGESTED_VALUES: usize = 3;
+/// Callback data is limited to 64 bytes after base64, the suggested filter must fit with the command framing
+const MAX_FILTER_BYTES: usize = 40;
+
+#[derive(Debug, Clone)]
+pub(super) struct PlacementSession {
+    tasks: HashSet<TaskId>,
+    /// Answered tasks, correct when every gap is
+    answers: Vec<(TaskId, bool)>,
+}
+
+pub(super) type PlacementSessions = Arc<Mutex<HashMap<ChatId, PlacementSession>>>;
+
+#[derive(Debug, PartialEq)]
+pub(super) enum PlacementAnswer {
+    NotInPlacement,
+    Accepted,
+    Finished(Vec<(TaskId, bool)>),
+}
+
+/// Button offered to new users on `/start`.
+pub(super) fn placement_button() -> InlineKeyboardButton {
+    let command = Command::StartPlacement(proto::StartPlacement {});
+    InlineKeyboardButton::callback("🎯 Пройти тест", encode_command(command))
+}
+
+impl<T: TaskInfoService, U: UserStateService> BotContext<T, U> {
+    pub(super) async fn offer_placement(&self, bot: &Bot, chat_id: ChatId) -> anyhow::Result<()> {
+        bot.send_message(
+            chat_id,
+            format!(
+                "Хотите начать с короткого теста из {PLACEMENT_SIZE} заданий? Он покажет, какие падежи стоит потренировать, и подберёт задания под ваш уровень. Пройти его можно в любой момент командой /placement."
+            ),
+        )
+        .reply_markup(InlineKeyboardMarkup::new([[placement_button()]]))
+        .send_measured()
+        .await?;
+        Ok(())
+    }
+
+    /// Replaces the queue with a sample covering all filter values, the answers are recorded as usual,
+    /// so the next queues are already ordered by the learner's level.
+    pub(super) async fn handle_placement(&self, bot: &Bot, chat_id: ChatId) -> anyhow::Result<()> {
+        // Enough candidates of every value to fill the test even with a single value
+        let candidates = self.tasks.sample_task_ids(PLACEMENT_SIZE).await?;
+        let mut candidates = self.tasks.get_task_filters(&candidates).await?.into_iter().collect::<Vec<_>>();
+        candidates.sort_by_key(|(task_id, _)| *task_id);
+        let sample = placement_sample(&candidates, PLACEMENT_SIZE, &mut thread_rng());
+        if sample.is_empty() {
+            bot.send_message(chat_id, "Сейчас нет заданий для теста, попробуйте позже.")
+                .send_measured()
+                .await?;
+            return Ok(());
+        }
+
+        self.placement_sessions.lock().unwrap().insert(
+            chat_id,
+            PlacementSession {
+                tasks: sample.iter().copied().collect(),
+                answers: Vec::new(),
+            },
+        );
+        self.user_data.update_tasks(chat_id, &sample).await?;
+
+        bot.send_message(
+            chat_id,
+            format!(
+                "🎯 Тест из {} заданий. Отвечайте без подсказок, как получится!",
+                sample.len()
+            ),
+        )
+        .send_measured()
+        .await?;
+        self.ask_next_task(bot, chat_id).await
+    }
+
+    /// Stops the running placement test, returns false if there was none.
+    pub(super) fn cancel_placement(&self, chat_id: ChatId) -> bool {
+        self.placement_sessions.lock().unwrap().remove(&chat_id).is_some()
+    }
+
+    /// Counts an answered task towards the running placement test, if the task is from it.
+    pub(super) fn placement_answer(
+        &self,
+        chat_id: ChatId,
+        task_id: TaskId,
+        last_gap: bool,
+        task_correct: bool,
+    ) -> PlacementAnswer {
+        let mut sessions = self.placement_sessions.lock().unwrap();
+        let Some(session) = sessions.get_mut(&chat_id) else {
+            return PlacementAnswer::NotInPlacement;
+        };
+        if !session.tasks.contains(&task_id) {
+            return PlacementAnswer::NotInPlacement;
+        }
+
+        // The task is counted after its last gap
+        if !last_gap {
+            return PlacementAnswer::Accepted;
+        }
+        session.answers.push((task_id, task_correct));
+        if session.answers.len() < session.tasks.len() {
+            return PlacementAnswer::Accepted;
+        }
+
+        let answers = std::mem::take(&mut session.answers);
+        sessions.remove(&chat_id);
+        PlacementAnswer::Finished(answers)
+    }
+
+    pub(super) async fn finish_placement(
+        &self,
+        bot: &Bot,
+        chat_id: ChatId,
+        answers: &[(TaskId, bool)],
+    ) -> anyhow::Result<()> {
+        let task_ids = answers.iter().map(|(task_id, _)| *task_id).collect::<Vec<_>>();
+        let tasks = self.tasks.get_tasks(&task_ids).await?;
+        let mastery = estimate_mastery(answers, &tasks);
+
+        let correct = answers.iter().filter(|(_, correct)| *correct).count();
+        let mut text = format!("🎯 Тест завершён: {correct} правильно из {}.\n", answers.len());
+        for (name, values) in &mastery {
+            text.push_str(&format!("\n{name}:\n"));
+            for (value, (correct, answered)) in values {
+                text.push_str(&format!(
+                    "{mark} {value} — {correct} из {answered}\n",
+                    mark = if mastery_of(*correct, *answered) >= MASTERY_THRESHOLD {
+                        "✅"
+                    } else {
+                        "📚"
+                    },
+                ));
+            }
+        }
+
+        let mut markup = InlineKeyboardMarkup::default();
+        if let Some(filter) = suggest_filter(&mastery) {
+            text.push_str(&format!("\nСтоит потренировать: {filter}"));
+            let command = Command::ApplyFilter(proto::ApplyFilter { filter });
+            markup = InlineKeyboardMarkup::new([[InlineKeyboardButton::callback(
+                "Тренировать слабые падежи",
+                encode_command(command),
+            )]]);
+        }
+        bot.send_message(chat_id, text).reply_markup(markup).send_measured().await?;
+
+        // The next queue is built with the placement answers taken into account
+        self.user_data.update_tasks(chat_id, &[]).await?;
+        self.ask_next_task(bot, chat_id).await
+    }
+}
+
+/// Picks tasks one by one, each time the task covering the least covered filter values,
+/// so every case and theme gets into the sample before any of them repeats.
+fn placement_sample(tasks: &[(TaskId, Vec<FilterValue>)], size: usize, rng: &mut impl Rng) -> Vec<TaskId> {
+    let mut candidates = tasks.iter().collect::<Vec<_>>();
+    candidates.shuffle(rng);
+
+    let mut coverage: HashMap<(&str, &str), usize> = HashMap::new();
+    let mut sample = Vec::new();
+    while sample.len() < size && !candidates.is_empty() {
+        let score = |filters: &[FilterValue]| -> f64 {
+            filters
+                .iter()
+                .map(|filter| {
+                    let covered = coverage.get(&(filter.name.as_str(), filter.value.as_str())).copied();
+                    1.0 / (1 + covered.unwrap_or_default()) as f64
+                })
+                .sum()
+        };
+        let Some((best, _)) = candidates
+            .iter()
+            .enumerate()
+            .map(|(index, (_, filters))| (index, score(filters)))
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        else {
+            break;
+        };
+
+        let (task_id, filters) = candidates.swap_remove(best);
+        for filter in filters {
+            *coverage.entry((&filter.name, &filter.value)).or_default() += 1;
+        }
+        sample.push(*task_id);
+    }
+    sample
+}
+
+/// Correct and total answers per filter value, grouped by the filter name.
+fn estimate_mastery(answers: &[(TaskId, bool)], tasks: &[Task]) -> BTreeMap<String, BTreeMap<String, (usize, usize)>> {
+    let tasks = tasks.iter().map(|task| (task.id, task)).collect::<HashMap<_, _>>();
+    let mut mastery: BTreeMap<String, BTreeMap<String, (usize, usize)>> = BTreeMap::new();
+    for (task_id, correct) in answers {
+        let Some(task) = tasks.get(task_id) else {
+            continue;
+        };
+        for filter in &task.filters {
+            let counts = mastery
+                .entry(filter.name.clone())
+                .or_default()
+                .entry(filter.value.clone())
+                .or_default();
+            counts.0 += *correct as usize;
+            counts.1 += 1;
+        }
+    }
+    mastery
+}
+
+fn mastery_of(correct: usize, answered: usize) -> f64 {
+    match answered {
+        0 => 0.0,
+        answered => correct as f64 / answered as f64,
+    }
+}
+
+/// Weakest values of the suggested filter below the mastery threshold, as a `/filter` text.
+fn suggest_filter(mastery: &BTreeMap<String, BTreeMap<String, (usize, usize)>>) -> Option<String> {
+    let mut weak = mastery
+        .get(SUGGESTED_FILTER_NAME)?
+        .iter()
+        .map(|(value, (correct, answered))| (value, mastery_of(*correct, *answered)))
+        .filter(|(_, mastery)| *mastery < MASTERY_THRESHOLD)
+        .collect::<Vec<_>>();
+    weak.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+
+    let mut filter = String::new();
+    for (value, _) in weak.into_iter().take(MAX_SUGGESTED_VALUES) {
+        let separator = if filter.is_empty() { "" } else { ", " };
+        if filter.len() + separator.len() + value.len() > MAX_FILTER_BYTES {
+            break;
+        }
+        filter.push_str(separator);
+        filter.push_str(value);
+    }
+    (!filter.is_empty()).then_some(filter)
+}
+
+#[cfg(test)]
+mod test {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    fn task(id: TaskId, case: &str, theme: &str) -> Task {
+        let filter = |name: &str, value: &str| FilterValue {
+            name: name.into(),
+            value: value.into(),
+        };
+        Task {
+            id,
+            hash: id,
+            task: String::new(),
+            masked_task: String::new(),
+            correct: String::new(),
+            base: String::new(),
+            info: Vec::new(),
+            hints: Vec::new(),
+            filters: vec![filter("Падеж", case), filter("Тема", theme)],
+            wrong_answers: Vec::new(),
+            gaps: Vec::new(),
+        }
+    }
+
+    fn tasks() -> Vec<Task> {
+        let mut tasks = Vec::new();
+        for (case_index, case) in ["dative", "locative", "genitive"].into_iter().enumerate() {
+            for (theme_index, theme) in ["Shopping", "Travel"].into_iter().enumerate() {
+                for copy in 0..5 {
+                    tasks.push(task(
+                        (case_index * 100 + theme_index * 10 + copy) as TaskId,
+                        case,
+                        theme,
+                    ));
+                }
+            }
+        }
+        tasks
+    }
+
+    #[test]
+    fn test_placement_sample() {
+        let tasks = tasks();
+        let candidates = tasks.iter().map(|task| (task.id, task.filters.clone())).collect::<Vec<_>>();
+        let sample = placement_sample(&candidates, 6, &mut StdRng::seed_from_u64(1));
+        assert_eq!(sample.len(), 6);
+
+        let sampled = tasks.iter().filter(|task| sample.contains(&task.id)).collect::<Vec<_>>();
+        for case in ["dative", "locative", "genitive"] {
+            let count = sampled.iter().filter(|task| task.filters[0].value == case).count();
+            assert_eq!(count, 2, "{case}");
+        }
+        for theme in ["Shopping", "Travel"] {
+            let count = sampled.iter().filter(|task| task.filters[1].value == theme).count();
+            assert_eq!(count, 3, "{theme}");
+        }
+
+        assert_eq!(
+            placement_sample(&candidates[..2], 6, &mut StdRng::seed_from_u64(1)).len(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_mastery_and_suggested_filter() {
+        let tasks = tasks();
+        let answers = [
+            (0, true),
+            (1, true),
+            (100, false),
+            (101, true),
+            (200, false),
+            (210, false),
+        ];
+        let mastery = estimate_mastery(&answers, &tasks);
+
+        assert_eq!(mastery["Падеж"]["dative"], (2, 2));
+        assert_eq!(mastery["Падеж"]["locative"], (1, 2));
+        assert_eq!(mastery["Тема"]["Travel"], (0, 1));
+        assert_eq!(suggest_filter(&mastery).as_deref(), Some("genitive, locative"));
+
+        let mastery = estimate_mastery(&answers[..2], &tasks);
+        assert_eq!(suggest_filter(&mastery), None);
+    }
+}
@@ -0,0 +1,75 @@
+use teloxide::{
+    payloads::{EditMessageTextSetters, SendDocumentSetters, SendMessageSetters},
+    requests::Requester,
+    types::{ChatId, InlineKeyboardButton, InlineKeyboardMarkup, InputFile},
+    Bot,
+};
+
+use crate::metrics::SendMeasured;
+
+use super::{
+    bot_core::{encode_command, BotContext},
+    bot_services::{TaskInfoService, UserStateService},
+    proto::{self, command::Command},
+};
+
+fn forget_markup() -> InlineKeyboardMarkup {
+    let button = |text: &str, confirm: bool| {
+        let command = Command::ForgetMe(proto::ForgetMe { confirm });
+        InlineKeyboardButton::callback(text, encode_command(command))
+    };
+    InlineKeyboardMarkup::new([[button("Удалить всё", true), button("Отмена", false)]])
+}
+
+impl<T: TaskInfoService, U: UserStateService> BotContext<T, U> {
+    /// Sends everything stored about the user as a JSON file.
+    pub(super) async fn handle_export(&self, bot: &Bot, chat_id: ChatId, uid: i64) -> anyhow::Result<()> {
+        let data = self.user_data.export_user_data(uid).await?;
+        let json = serde_json::to_string_pretty(&data)?;
+        bot.send_document(chat_id, InputFile::memory(json.into_bytes()).file_name("my_data.json"))
+            .caption("Все данные, которые бот хранит о вас. Удалить их можно командой /forget.")
+            .send_measured()
+            .await?;
+        Ok(())
+    }
+
+    pub(super) async fn handle_forget(&self, bot: &Bot, chat_id: ChatId) -> anyhow::Result<()> {
+        bot.send_message(
+            chat_id,
+            "Удалить все ваши ответы, прогресс, достижения и настройки? Это нельзя отменить. Скачать данные перед удалением можно командой /export.",
+        )
+        .reply_markup(forget_markup())
+        .send_measured()
+        .await?;
+        Ok(())
+    }
+
+    pub(super) async fn handle_forget_me(
+        &self,
+        bot: &Bot,
+        uid: i64,
+        forget: &proto::ForgetMe,
+        message: &teloxide::types::Message,
+    ) -> anyhow::Result<()> {
+        let chat_id = message.chat.id;
+        let text = if forget.confirm {
+            self.user_data.forget_user(uid).await?;
+            self.blitz_sessions.lock().unwrap().remove(&chat_id);
+            self.placement_sessions.lock().unwrap().remove(&chat_id);
+            self.used_hints
+                .lock()
+                .unwrap()
+                .retain(|(hint_chat_id, _), _| *hint_chat_id != chat_id);
+            log::info!("#{chat_id} user {uid} data deleted");
+            "Готово, все ваши данные удалены. Чтобы начать заново, отправьте /start."
+        } else {
+            "Хорошо, ничего не удалено."
+        };
+
+        bot.edit_message_text(chat_id, message.id, text)
+            .reply_markup(InlineKeyboardMarkup::default())
+            .send_measured()
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,207 @@
+use anyhow::Result;
+use teloxide::{
+    payloads::{AnswerCallbackQuerySetters, EditMessageTextSetters, SendMessageSetters},
+    requests::Requester,
+    types::{CallbackQuery, InlineKeyboardButton, InlineKeyboardMarkup},
+    Bot,
+};
+
+use crate::metrics::SendMeasured;
+use crate::model::TaskId;
+
+use super::{
+    bot_core::{encode_command, BotContext, BotErrors},
+    bot_services::{TaskInfoService, TaskReportReason, UserStateService},
+    proto::{self, command::Command, ReportReason},
+};
+
+const REPORT_REASONS: [(ReportReason, &str); 4] = [
+    (ReportReason::WrongAnswer, "Неверный правильный ответ"),
+    (ReportReason::BadDistractor, "Неудачный неверный вариант"),
+    (ReportReason::BadTranslation, "Ошибка в переводе"),
+    (ReportReason::Typo, "Опечатка"),
+];
+
+/// Button shown under every question, it stays after the answer as errors are often noticed then.
+pub(super) fn report_button(task_id: TaskId) -> InlineKeyboardButton {
+    let command = Command::ReportTask(proto::ReportTask {
+        task_id,
+        reason: ReportReason::Unspecified.into(),
+    });
+    InlineKeyboardButton::callback("⚠️ Ошибка в задании", encode_command(command))
+}
+
+impl<T: TaskInfoService, U: UserStateService> BotContext<T, U> {
+    pub(super) async fn handle_report_task(
+        &self,
+        bot: &Bot,
+        query: &CallbackQuery,
+        report: &proto::ReportTask,
+        message: &teloxide::types::Message,
+    ) -> Result<()> {
+        let reason = match report.reason() {
+            ReportReason::Unspecified => {
+                let buttons = REPORT_REASONS.iter().map(|(reason, text)| {
+                    let command = Command::ReportTask(proto::ReportTask {
+                        task_id: report.task_id,
+                        reason: (*reason).into(),
+                    });
+                    vec![InlineKeyboardButton::callback(*text, encode_command(command))]
+                });
+                bot.send_message(message.chat.id, "Что не так с заданием?")
+                    .reply_to_message_id(message.id)
+                    .reply_markup(InlineKeyboardMarkup::new(buttons))
+                    .send_measured()
+                    .await?;
+                return Ok(());
+            }
+            ReportReason::WrongAnswer => TaskReportReason::WrongAnswer,
+            ReportReason::BadDistractor => TaskReportReason::BadDistractor,
+            ReportReason::BadTranslation => TaskReportReason::BadTranslation,
+            ReportReason::Typo => TaskReportReason::Typo,
+        };
+
+        let uid = query.from.id.0 as i64;
+        let report_id = self.user_data.record_task_report(uid, report.task_id, reason).await?;
+        let reply = match report_id {
+            Some(_) => "Спасибо! Мы проверим задание.",
+            None => "Вы уже сообщили об этой ошибке, спасибо!",
+        };
+        bot.edit_message_text(message.chat.id, message.id, reply)
+            .reply_markup(InlineKeyboardMarkup::default())
+            .send_measured()
+            .await?;
+
+        if let Some(report_id) = report_id {
+            self.notify_task_report(bot, query, report.task_id, reason, report_id).await?;
+        }
+        Ok(())
+    }
+
+    async fn notify_task_report(
+        &self,
+        bot: &Bot,
+        query: &CallbackQuery,
+        task_id: TaskId,
+        reason: TaskReportReason,
+        report_id: i64,
+    ) -> Result<()> {
+        let Some(feedback_chat_id) = self.feedback_chat_id else {
+            log::warn!("No feedback chat to notify about report on task {task_id}");
+            return Ok(());
+        };
+
+        let task = self.tasks.get_task(task_id).await?.ok_or(BotErrors::NoTaskFound)?;
+        let counts = self.user_data.get_task_report_counts(task_id).await?;
+        let annotation = self.tasks.get_task_annotation(task_id).await?;
+
+        let mut text = format!(
+            "⚠️ Report: {reason} from @{username} ({name})\n\nTask #{task_id}: {task}\nCorrect: {correct}\nOpen reports: {counts}",
+            reason = reason_name(reason),
+            username = query.from.username.as_deref().unwrap_or("unknown"),
+            name = query.from.full_name(),
+            task = task.task,
+            correct = task.correct,
+            counts = counts
+                .iter()
+                .map(|(reason, count)| format!("{} {count}", reason_name(*reason)))
+                .collect::<Vec<_>>()
+                .join(", "),
+        );
+        if let Some(annotation) = annotation {
+            text.push_str(&format!("\nNote: {annotation}"));
+        }
+        text.push_str("\n\nReply to this message to annotate the task.");
+
+        let deactivate = Command::DeactivateTask(proto::DeactivateTask { task_id });
+        let notification = bot
+            .send_message(feedback_chat_id, text)
+            .reply_markup(InlineKeyboardMarkup::new([[InlineKeyboardButton::callback(
+                "🚫 Deactivate task",
+                encode_command(deactivate),
+            )]]))
+            .send_measured()
+            .await?;
+        self.user_data.set_task_report_message(report_id, notification.id).await?;
+        Ok(())
+    }
+
+    /// Deactivation from a report notification, allowed to admins only. Answers the callback itself
+    /// to show an alert to everyone else. The deactivation survives task reloads and deploys.
+    pub(super) async fn handle_deactivate_task(
+        &self,
+        bot: &Bot,
+        query: &CallbackQuery,
+        deactivate: &proto::DeactivateTask,
+        message: &teloxide::types::Message,
+    ) -> Result<()> {
+        if Some(message.chat.id) != self.feedback_chat_id {
+            bot.answer_callback_query(query.id.clone()).send_measured().await?;
+            return Ok(());
+        }
+        let admin_uid = query.from.id.0 as i64;
+        if !self.is_admin(admin_uid) {
+            bot.answer_callback_query(query.id.clone())
+                .text("Only admins can deactivate tasks")
+                .show_alert(true)
+                .send_measured()
+                .await?;
+            return Ok(());
+        }
+        bot.answer_callback_query(query.id.clone()).send_measured().await?;
+
+        let task_id = deactivate.task_id;
+        let deactivated = self.tasks.deactivate_task(task_id, admin_uid).await?;
+        self.user_data.resolve_task_reports(task_id).await?;
+        log::info!("Task {task_id} deactivated from report by {}", query.from.id);
+
+        let result = match deactivated {
+            true => "🚫 Deactivated, task reloads keep it off, by",
+            false => "🚫 Was already deactivated, reports resolved by",
+        };
+        let text = format!(
+            "{}\n\n{result} {}",
+            message.text().unwrap_or_default(),
+            query.from.full_name()
+        );
+        bot.edit_message_text(message.chat.id, message.id, text)
+            .reply_markup(InlineKeyboardMarkup::default())
+            .send_measured()
+            .await?;
+        Ok(())
+    }
+
+    /// Saves an admin reply to a report notification as the task note and resolves its reports.
+    pub(super) async fn annotate_reported_task(
+        &self,
+        bot: &Bot,
+        task_id: TaskId,
+        message: &teloxide::types::Message,
+    ) -> Result<()> {
+        if !message.from().is_some_and(|user| self.is_admin(user.id.0 as i64)) {
+            return Ok(());
+        }
+        let annotation = message.text().unwrap_or_default().trim();
+        let reply = match self.tasks.annotate_task(task_id, annotation).await? {
+            true => {
+                self.user_data.resolve_task_reports(task_id).await?;
+                format!("📝 Note saved for task #{task_id}, its reports are resolved")
+            }
+            false => format!("Task #{task_id} not found"),
+        };
+        bot.send_message(message.chat.id, reply)
+            .reply_to_message_id(message.id)
+            .send_measured()
+            .await?;
+        Ok(())
+    }
+}
+
+fn reason_name(reason: TaskReportReason) -> &'static str {
+    match reason {
+        TaskReportReason::WrongAnswer => "wrong answer",
+        TaskReportReason::BadDistractor => "bad distractor",
+        TaskReportReason::BadTranslation => "bad translation",
+        TaskReportReason::Typo => "typo",
+    }
+}
@@ -0,0 +1,85 @@
+use clap::{Parser, Subcommand};
+
+/// Telegram bot for learning Serbian cases.
+#[derive(Debug, Parser)]
+#[command(version)]
+pub struct Cli {
+    /// TOML config file, `config.toml` is used if it exists
+    #[arg(long, global = true, env = "CONFIG_FILE")]
+    pub config: Option<String>,
+    /// Runs the bot if omitted
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run the bot, tasks are synced by `import-tasks` unless `--import-tasks` is given
+    Run {
+        /// Keep everything in memory or in `local_state_file` instead of the database
+        #[arg(long)]
+        local: bool,
+        /// Sync tasks from the data directory to the database before starting
+        #[arg(long, conflicts_with = "local")]
+        import_tasks: bool,
+    },
+    /// Apply database migrations and exit
+    Migrate,
+    /// Sync tasks from the data directory to the database and exit
+    ImportTasks,
+    /// Check task files, grammar cards and the curriculum in the data directory
+    Lint,
+    /// Print users and daily activity
+    Stats {
+        #[arg(long, default_value_t = 7, value_parser = clap::value_parser!(u64).range(1..))]
+        days: u64,
+    },
+    /// Write per-task answer statistics as CSV
+    Export {
+        #[arg(long, default_value_t = 30, value_parser = clap::value_parser!(u64).range(1..))]
+        days: u64,
+        /// Written to stdout if omitted
+        #[arg(long, short)]
+        output: Option<String>,
+    },
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigAction {
+    /// Validate the configuration and print it with secrets hidden
+    Check,
+}
+
+#[cfg(test)]
+mod test {
+    use clap::CommandFactory;
+
+    use super::*;
+
+    #[test]
+    fn test_cli() {
+        Cli::command().debug_assert();
+
+        let cli = Cli::parse_from(["bot", "run", "--local"]);
+        assert!(matches!(
+            cli.command,
+            Some(Command::Run {
+                local: true,
+                import_tasks: false
+            })
+        ));
+        let cli = Cli::parse_from(["bot", "run", "--import-tasks"]);
+        assert!(matches!(cli.command, Some(Command::Run { import_tasks: true, .. })));
+        assert!(Cli::try_parse_from(["bot", "run", "--local", "--import-tasks"]).is_err());
+        let cli = Cli::parse_from(["bot", "--config", "bot.toml", "export", "--days", "7"]);
+        assert_eq!(cli.config.as_deref(), Some("bot.toml"));
+        assert!(matches!(cli.command, Some(Command::Export { days: 7, output: None })));
+        assert!(Cli::try_parse_from(["bot", "stats", "--days", "0"]).is_err());
+        assert!(Cli::parse_from(["bot"]).command.is_none());
+    }
+}
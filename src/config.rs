@@ -0,0 +1,328 @@
+//! Bot configuration: a TOML file with environment variables on top.
+
+use std::{env, fmt::Display, net::SocketAddr, path::PathBuf, str::FromStr, time::Duration};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use teloxide::types::ChatId;
+use url::Url;
+
+use crate::bot::{BotConfig, UpdatesMode};
+
+/// Used when `CONFIG_FILE` is not set, the file is optional.
+pub const DEFAULT_CONFIG_FILE: &str = "config.toml";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub telegram_bot_token: Option<String>,
+    /// `postgres://…` or `sqlite:…`, not needed in the local mode
+    pub database_url: Option<String>,
+    /// Root certificate for the Postgres SSL connection
+    pub pg_cert: Option<PathBuf>,
+    /// Directory with tasks, grammar cards and the curriculum
+    pub data_dir: String,
+    /// Where the local mode keeps its state, in memory only if not set
+    pub local_state_file: Option<PathBuf>,
+    pub feedback_chat_id: Option<i64>,
+    /// Users allowed to run `/admin` commands
+    pub admin_ids: Vec<i64>,
+    /// Expected success rate new task queues are ordered around, between 0 and 1
+    pub target_success_rate: f64,
+    /// Progress message after every that many answers, 0 turns it off
+    pub stats_every: u64,
+    /// Pause between an answer and the next task
+    pub answer_delay_ms: u64,
+    /// Wrong answers shown next to the correct one
+    pub distractors: usize,
+    /// Updates are polled if not set
+    pub webhook: Option<WebhookConfig>,
+    /// Address of the metrics and health endpoints, not served if not set
+    pub metrics_listen: Option<SocketAddr>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookConfig {
+    /// Public HTTPS address Telegram sends updates to
+    pub url: Url,
+    /// Local address the webhook server listens on
+    pub listen: SocketAddr,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            telegram_bot_token: None,
+            database_url: None,
+            pg_cert: None,
+            data_dir: "data".into(),
+            local_state_file: None,
+            feedback_chat_id: None,
+            admin_ids: Vec::new(),
+            target_success_rate: 0.8,
+            stats_every: 5,
+            answer_delay_ms: 1000,
+            distractors: 3,
+            webhook: None,
+            metrics_listen: None,
+        }
+    }
+}
+
+/// Parses the variable if it is set, the error names the variable.
+fn env_value<T: FromStr>(name: &str) -> Result<Option<T>>
+where
+    T::Err: Display,
+{
+    match env::var(name) {
+        Ok(value) => value
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|err| anyhow::format_err!("{name}={value:?} is invalid: {err}")),
+        Err(_) => Ok(None),
+    }
+}
+
+impl Config {
+    /// Reads the given file or [`DEFAULT_CONFIG_FILE`] if it exists, applies environment overrides and validates.
+    pub fn load(path: Option<&str>) -> Result<Self> {
+        let mut config = match path {
+            Some(path) => Self::read(path)?,
+            None if PathBuf::from(DEFAULT_CONFIG_FILE).exists() => Self::read(DEFAULT_CONFIG_FILE)?,
+            None => Self::default(),
+        };
+        config.apply_env()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn read(path: &str) -> Result<Self> {
+        let text = std::fs::read_to_string(path).with_context(|| format!("Failed to read config file {path}"))?;
+        toml::from_str(&text).with_context(|| format!("Failed to parse config file {path}"))
+    }
+
+    /// Environment variables win over the file, names are the upper case keys.
+    fn apply_env(&mut self) -> Result<()> {
+        if let Some(token) = env_value("TELEGRAM_BOT_TOKEN")? {
+            self.telegram_bot_token = Some(token);
+        }
+        if let Some(url) = env_value("DATABASE_URL")? {
+            self.database_url = Some(url);
+        }
+        if let Some(path) = env_value("PG_CERT")? {
+            self.pg_cert = Some(path);
+        }
+        if let Some(data_dir) = env_value("DATA_DIR")? {
+            self.data_dir = data_dir;
+        }
+        if let Some(path) = env_value("LOCAL_STATE_FILE")? {
+            self.local_state_file = Some(path);
+        }
+        if let Some(chat_id) = env_value("FEEDBACK_CHAT_ID")? {
+            self.feedback_chat_id = Some(chat_id);
+        }
+        if let Ok(admin_ids) = env::var("ADMIN_IDS") {
+            self.admin_ids = admin_ids
+                .split(',')
+                .map(str::trim)
+                .filter(|uid| !uid.is_empty())
+                .map(|uid| uid.parse().with_context(|| format!("ADMIN_IDS has a bad user id {uid:?}")))
+                .collect::<Result<_>>()?;
+        }
+        if let Some(rate) = env_value("TARGET_SUCCESS_RATE")? {
+            self.target_success_rate = rate;
+        }
+        if let Some(every) = env_value("STATS_EVERY")? {
+            self.stats_every = every;
+        }
+        if let Some(delay) = env_value("ANSWER_DELAY_MS")? {
+            self.answer_delay_ms = delay;
+        }
+        if let Some(distractors) = env_value("DISTRACTORS")? {
+            self.distractors = distractors;
+        }
+        match (env_value("WEBHOOK_URL")?, env_value("WEBHOOK_LISTEN")?) {
+            (Some(url), Some(listen)) => self.webhook = Some(WebhookConfig { url, listen }),
+            (None, None) => {}
+            _ => anyhow::bail!("WEBHOOK_URL and WEBHOOK_LISTEN must be set together"),
+        }
+        if let Some(listen) = env_value("METRICS_LISTEN")? {
+            self.metrics_listen = Some(listen);
+        }
+        Ok(())
+    }
+
+    /// Checks all values at once, so every problem is reported on the first start.
+    fn validate(&self) -> Result<()> {
+        let mut problems = Vec::new();
+        if let Some(url) = &self.database_url {
+            if !["postgres:", "postgresql:", "sqlite:"]
+                .iter()
+                .any(|scheme| url.starts_with(scheme))
+            {
+                problems.push("database_url should start with postgres:, postgresql: or sqlite:".to_owned());
+            }
+        }
+        if let Some(pg_cert) = &self.pg_cert {
+            if !pg_cert.is_file() {
+                problems.push(format!("pg_cert {} is not a file", pg_cert.display()));
+            }
+        }
+        if !PathBuf::from(&self.data_dir).is_dir() {
+            problems.push(format!("data_dir {} is not a directory", self.data_dir));
+        }
+        if !(0.0..=1.0).contains(&self.target_success_rate) {
+            problems.push(format!(
+                "target_success_rate should be between 0 and 1, got {}",
+                self.target_success_rate
+            ));
+        }
+        if !(1..=7).contains(&self.distractors) {
+            problems.push(format!("distractors should be from 1 to 7, got {}", self.distractors));
+        }
+        if let Some(webhook) = &self.webhook {
+            if webhook.url.scheme() != "https" {
+                problems.push(format!(
+                    "webhook url should be an https:// address, got {}",
+                    webhook.url
+                ));
+            }
+        }
+        if self.feedback_chat_id.is_none() {
+            log::warn!("No feedback_chat_id, feedback will throw errors");
+        }
+        if self.admin_ids.is_empty() {
+            log::warn!("No admin_ids, admin commands are disabled");
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            anyhow::bail!("Invalid configuration:\n  {}", problems.join("\n  "))
+        }
+    }
+
+    /// Settings the bot itself needs, the other commands run without a token.
+    pub fn bot_config(&self) -> Result<BotConfig> {
+        let token = self
+            .telegram_bot_token
+            .clone()
+            .filter(|token| !token.is_empty())
+            .context("telegram_bot_token is required to run the bot")?;
+        Ok(BotConfig {
+            token,
+            feedback_chat_id: self.feedback_chat_id.map(ChatId),
+            admin_ids: self.admin_ids.clone(),
+            data_dir: self.data_dir.clone(),
+            target_success: self.target_success_rate,
+            stats_every: self.stats_every,
+            answer_delay: Duration::from_millis(self.answer_delay_ms),
+            distractors: self.distractors,
+            updates: match &self.webhook {
+                Some(webhook) => UpdatesMode::Webhook {
+                    url: webhook.url.clone(),
+                    listen: webhook.listen,
+                },
+                None => UpdatesMode::Polling,
+            },
+        })
+    }
+
+    pub fn database_url(&self) -> Result<&str> {
+        self.database_url
+            .as_deref()
+            .context("database_url is required unless running in the local mode")
+    }
+
+    /// Human readable summary for `config check`, secrets are hidden.
+    pub fn summary(&self) -> String {
+        let hidden = |value: &Option<String>| match value {
+            Some(_) => "<set>".to_owned(),
+            None => "<not set>".to_owned(),
+        };
+        let database = match &self.database_url {
+            Some(url) if url.starts_with("sqlite:") => url.clone(),
+            url => hidden(url),
+        };
+        let updates = match &self.webhook {
+            Some(webhook) => format!("webhook {} on {}", webhook.url, webhook.listen),
+            None => "polling".to_owned(),
+        };
+        format!(
+            indoc::indoc! {"
+                telegram_bot_token: {}
+                database_url: {}
+                pg_cert: {:?}
+                data_dir: {}
+                local_state_file: {:?}
+                feedback_chat_id: {:?}
+                admin_ids: {:?}
+                target_success_rate: {}
+                stats_every: {}
+                answer_delay_ms: {}
+                distractors: {}
+                updates: {}
+                metrics_listen: {:?}"},
+            hidden(&self.telegram_bot_token),
+            database,
+            self.pg_cert,
+            self.data_dir,
+            self.local_state_file,
+            self.feedback_chat_id,
+            self.admin_ids,
+            self.target_success_rate,
+            self.stats_every,
+            self.answer_delay_ms,
+            self.distractors,
+            updates,
+            self.metrics_listen,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_validate() -> Result<()> {
+        let config: Config = toml::from_str(indoc::indoc! {r#"
+            telegram_bot_token = "secret-token"
+            database_url = "sqlite:bot.db"
+            data_dir = "data"
+            admin_ids = [1, 2]
+            distractors = 2
+
+            metrics_listen = "0.0.0.0:9090"
+
+            [webhook]
+            url = "https://example.com/bot"
+            listen = "0.0.0.0:8080"
+        "#})?;
+        config.validate()?;
+        assert_eq!(config.admin_ids, vec![1, 2]);
+        assert_eq!(config.stats_every, 5);
+        let bot_config = config.bot_config()?;
+        assert_eq!(bot_config.distractors, 2);
+        assert!(matches!(bot_config.updates, UpdatesMode::Webhook { .. }));
+        assert_eq!(config.metrics_listen, Some("0.0.0.0:9090".parse()?));
+        assert!(!config.summary().contains("secret-token"));
+
+        assert!(toml::from_str::<Config>("unknown_key = 1").is_err());
+
+        let config = Config {
+            telegram_bot_token: None,
+            target_success_rate: 1.5,
+            distractors: 0,
+            ..Config::default()
+        };
+        assert!(config.bot_config().is_err());
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("target_success_rate"), "{err}");
+        assert!(err.contains("distractors"), "{err}");
+
+        Ok(())
+    }
+}
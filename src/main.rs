@@ -1,92 +1,222 @@
-use std::{env, path::Path};
+use std::{path::Path, str::FromStr, time::Duration};
 
 use anyhow::{Context, Result};
 use bot::{
+    bot_services::{TaskInfoService, UserStateService},
     bot_services_in_mem::{LocalTasks, LocalUserStateService},
-    BotConfig,
 };
+use clap::Parser;
+use cli::{Cli, Command, ConfigAction};
+use config::Config;
+use model::Task;
+use service::sqlite_task_info_service::SqliteTaskInfoService;
+use service::sqlite_user_state::SqliteUserService;
 use service::task_info_service::PgTaskInfoService;
 use service::user_state::PgUserService;
-use sqlx::postgres::{PgConnectOptions, PgPool};
-use teloxide::types::ChatId;
+use sqlx::{
+    postgres::{PgConnectOptions, PgPool},
+    sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePool},
+};
 
+mod analytics;
 mod bot;
+mod cli;
+mod config;
+mod metrics;
 mod model;
 mod service;
 #[cfg(test)]
 mod test_db;
 mod utils;
 
+const DAY: Duration = Duration::from_secs(60 * 60 * 24);
+
+/// Runs the body with `$tasks` and `$users` services of the configured database, migrated on connect.
+macro_rules! with_database {
+    ($config:expr, |$tasks:ident, $users:ident| $body:expr) => {
+        match Database::connect($config).await? {
+            Database::Postgres(pool) => {
+                let $tasks = PgTaskInfoService::new(pool.clone());
+                let $users = PgUserService::new(pool);
+                $body
+            }
+            Database::Sqlite(pool) => {
+                let $tasks = SqliteTaskInfoService::new(pool.clone());
+                let $users = SqliteUserService::new(pool);
+                $body
+            }
+        }
+    };
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     if dotenv::from_filename(".env").is_err() {
-        println!("No .env file found, working without it");
+        // Not on stdout, it is the output of `export`
+        eprintln!("No .env file found, working without it");
     }
     pretty_env_logger::init_timed();
 
-    log::info!("Connecting to database...");
-    let connection_url = env::var("DATABASE_URL").context("No DATABASE_URL environment")?;
+    let cli = Cli::parse();
+    let config = Config::load(cli.config.as_deref())?;
 
-    let pgcert = Path::new(".pgcert");
-    let connect_options = if pgcert.exists() {
-        log::info!("Found .pgcert file, using it for SSL connection");
-        let pgcert = std::fs::read(pgcert)?;
-        connection_url
-            .parse::<PgConnectOptions>()?
-            .ssl_root_cert_from_pem(pgcert)
-            .ssl_mode(sqlx::postgres::PgSslMode::VerifyCa)
-    } else {
-        connection_url.parse::<PgConnectOptions>()?
+    let default_command = Command::Run {
+        local: false,
+        import_tasks: false,
     };
+    match cli.command.unwrap_or(default_command) {
+        Command::Run {
+            local,
+            import_tasks: import,
+        } => {
+            serve_metrics(&config);
+            if local {
+                run_local(&config).await
+            } else {
+                with_database!(&config, |tasks, users| {
+                    // Deployments sync tasks with the import-tasks job, so restarts don't touch them
+                    if import {
+                        import_tasks(&config, &tasks).await?;
+                    }
+                    bot::setup_and_run_bot(config.bot_config()?, tasks, users).await
+                })
+            }
+        }
+        Command::Migrate => with_database!(&config, |_tasks, _users| {
+            log::info!("Database is migrated");
+            Ok(())
+        }),
+        Command::ImportTasks => with_database!(&config, |tasks, _users| import_tasks(&config, &tasks).await),
+        Command::Lint => lint(&config),
+        Command::Stats { days } => with_database!(&config, |_tasks, users| {
+            let stats = users.get_bot_stats(DAY * days as u32).await?;
+            println!("{}", analytics::bot_stats_report(&stats, days));
+            Ok(())
+        }),
+        Command::Export { days, output } => with_database!(&config, |tasks, users| {
+            export_task_stats(&tasks, &users, days, output.as_deref()).await
+        }),
+        Command::Config {
+            action: ConfigAction::Check,
+        } => {
+            config.bot_config()?;
+            println!("Configuration is valid:\n{}", config.summary());
+            Ok(())
+        }
+    }
+}
 
-    let pool = PgPool::connect_with(connect_options).await?;
-    sqlx::migrate!().run(&pool).await?;
-
-    let data_dir = env::var("DATA_DIR").unwrap_or("data".to_owned());
+/// Starts the endpoints before connecting and importing tasks, so `/healthz` doesn't depend on the startup
+/// work. `/readyz` turns ready only when the dispatcher runs.
+fn serve_metrics(config: &Config) {
+    if let Some(listen) = config.metrics_listen {
+        tokio::spawn(async move {
+            if let Err(err) = metrics::serve(listen).await {
+                log::error!("Metrics server failed: {err:#}");
+            }
+        });
+    }
+}
 
-    log::info!("Reading tasks from {data_dir}...");
-    let task_groups = model::scan_data_directory(&data_dir)?;
+fn read_tasks(config: &Config) -> Result<Vec<Task>> {
+    log::info!("Reading tasks from {}...", config.data_dir);
+    let task_groups = model::scan_data_directory(&config.data_dir)?;
     let tasks = task_groups
         .into_iter()
         .flat_map(|task_group| task_group.tasks.into_iter())
         .collect::<Vec<_>>();
+    log::info!("Got {} tasks", tasks.len());
+    Ok(tasks)
+}
 
-    let token = env::var("TELEGRAM_BOT_TOKEN").context("No TELEGRAM_BOT_TOKEN environment")?;
-    let feedback_chat_id = env::var("FEEDBACK_CHAT_ID")
-        .ok()
-        .and_then(|chat_id| chat_id.parse::<i64>().ok())
-        .map(ChatId);
+async fn import_tasks(config: &Config, task_info_service: &impl TaskInfoService) -> Result<()> {
+    let tasks = read_tasks(config)?;
+    let (updated, deactivated) = task_info_service.update_tasks(&tasks).await.context("Failed to update tasks")?;
+    log::info!("Imported {updated} tasks, deactivated {deactivated} tasks");
+    Ok(())
+}
 
-    if feedback_chat_id.is_none() {
-        log::warn!("No FEEDBACK_CHAT_ID environment, feedback will throw errors");
-    }
+async fn run_local(config: &Config) -> Result<()> {
+    let tasks = read_tasks(config)?;
+    // Without a state file everything is lost on restart
+    let user_state = match &config.local_state_file {
+        Some(path) => LocalUserStateService::with_snapshot(path)?,
+        None => {
+            log::warn!("No local_state_file, user state is kept in memory only");
+            LocalUserStateService::default()
+        }
+    };
+    bot::setup_and_run_bot(config.bot_config()?, LocalTasks::new(tasks), user_state).await
+}
 
-    log::info!("Got {} tasks, starting bot.", tasks.len());
-    let args: Vec<String> = env::args().collect();
-    if args.get(1) == Some(&"local".to_owned()) {
-        bot::setup_and_run_bot(
-            BotConfig {
-                token,
-                feedback_chat_id,
-            },
-            LocalTasks::new(tasks),
-            LocalUserStateService::default(),
-        )
-        .await?;
-    } else {
-        let task_info_service = PgTaskInfoService::new(pool.clone());
-        task_info_service.update_tasks(&tasks).await.context("Failed to update tasks")?;
-
-        bot::setup_and_run_bot(
-            BotConfig {
-                token,
-                feedback_chat_id,
-            },
-            task_info_service,
-            PgUserService::new(pool.clone()),
-        )
-        .await?;
+fn lint(config: &Config) -> Result<()> {
+    let problems = model::lint_data_directory(&config.data_dir)?;
+    for problem in &problems {
+        println!("{problem}");
+    }
+    if !problems.is_empty() {
+        anyhow::bail!("Found {} problems in {}", problems.len(), config.data_dir);
     }
+    println!("No problems found in {}", config.data_dir);
+    Ok(())
+}
 
+async fn export_task_stats(
+    task_info_service: &impl TaskInfoService,
+    user_state: &impl UserStateService,
+    days: u64,
+    output: Option<&str>,
+) -> Result<()> {
+    let stats = user_state.get_task_stats(DAY * days as u32).await?;
+    let task_ids = stats.iter().map(|task_stats| task_stats.task_id).collect::<Vec<_>>();
+    let tasks = task_info_service.get_tasks(&task_ids).await?;
+
+    let csv = analytics::to_csv(&stats, &tasks);
+    match output {
+        Some(path) => {
+            std::fs::write(path, csv).with_context(|| format!("Failed to write {path}"))?;
+            log::info!("Statistics of {} tasks written to {path}", stats.len());
+        }
+        None => print!("{csv}"),
+    }
     Ok(())
 }
+
+enum Database {
+    Postgres(PgPool),
+    Sqlite(SqlitePool),
+}
+
+impl Database {
+    /// Picks the backend by the `database_url` scheme and applies its migrations.
+    async fn connect(config: &Config) -> Result<Self> {
+        let connection_url = config.database_url()?;
+        if connection_url.starts_with("sqlite:") {
+            log::info!("Opening SQLite database...");
+            let connect_options = SqliteConnectOptions::from_str(connection_url)?
+                .create_if_missing(true)
+                .journal_mode(SqliteJournalMode::Wal);
+            let pool = SqlitePool::connect_with(connect_options).await?;
+            sqlx::migrate!("./migrations_sqlite").run(&pool).await?;
+            Ok(Database::Sqlite(pool))
+        } else {
+            log::info!("Connecting to database...");
+            let pool = PgPool::connect_with(pg_connect_options(connection_url, config.pg_cert.as_deref())?).await?;
+            sqlx::migrate!().run(&pool).await?;
+            Ok(Database::Postgres(pool))
+        }
+    }
+}
+
+fn pg_connect_options(connection_url: &str, pg_cert: Option<&Path>) -> Result<PgConnectOptions> {
+    if let Some(pg_cert) = pg_cert {
+        log::info!("Using {} for SSL connection", pg_cert.display());
+        let pgcert = std::fs::read(pg_cert)?;
+        Ok(connection_url
+            .parse::<PgConnectOptions>()?
+            .ssl_root_cert_from_pem(pgcert)
+            .ssl_mode(sqlx::postgres::PgSslMode::VerifyCa))
+    } else {
+        Ok(connection_url.parse::<PgConnectOptions>()?)
+    }
+}
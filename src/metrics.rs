@@ -0,0 +1,207 @@
+//! Prometheus metrics and the HTTP endpoint serving them together with the Kubernetes probes.
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex, OnceLock,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use axum::{http::StatusCode, routing::get, Router};
+use prometheus::{
+    exponential_buckets, Encoder, Histogram, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+use teloxide::requests::{HasPayload, Output, Payload, Request};
+
+/// Users are counted as active for a day after their last update.
+const ACTIVE_PERIOD: Duration = Duration::from_secs(60 * 60 * 24);
+
+pub struct Metrics {
+    registry: Registry,
+    pub updates: IntCounterVec,
+    pub answers: IntCounterVec,
+    pub handler_errors: IntCounterVec,
+    pub telegram_requests: HistogramVec,
+    pub service_calls: HistogramVec,
+    pub queue_refill_size: Histogram,
+    active_users: IntGauge,
+    /// Last update time of every user, pruned to the active ones when the gauge is scraped
+    last_seen: Mutex<HashMap<i64, Instant>>,
+    ready: AtomicBool,
+}
+
+pub fn metrics() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(|| Metrics::new().expect("Metrics are registered once"))
+}
+
+impl Metrics {
+    fn new() -> prometheus::Result<Self> {
+        let registry = Registry::new_custom(Some("words_bot".into()), None)?;
+        let latency_buckets = exponential_buckets(0.002, 2.0, 13)?;
+
+        let metrics = Self {
+            updates: IntCounterVec::new(Opts::new("updates_total", "Processed updates by type"), &["type"])?,
+            answers: IntCounterVec::new(Opts::new("answers_total", "Recorded answers"), &["result"])?,
+            handler_errors: IntCounterVec::new(
+                Opts::new("handler_errors_total", "Failed updates by error kind"),
+                &["kind"],
+            )?,
+            telegram_requests: HistogramVec::new(
+                HistogramOpts::new("telegram_request_seconds", "Telegram Bot API request latency")
+                    .buckets(latency_buckets.clone()),
+                &["method", "result"],
+            )?,
+            service_calls: HistogramVec::new(
+                HistogramOpts::new("service_call_seconds", "Storage latency by service method")
+                    .buckets(latency_buckets),
+                &["service", "method", "result"],
+            )?,
+            queue_refill_size: Histogram::with_opts(
+                HistogramOpts::new("queue_refill_size", "Tasks put into a chat queue at once")
+                    .buckets(exponential_buckets(1.0, 4.0, 8)?),
+            )?,
+            active_users: IntGauge::new(
+                "active_users",
+                "Users with updates in the last 24 hours since the start",
+            )?,
+            last_seen: Mutex::default(),
+            ready: AtomicBool::new(false),
+            registry,
+        };
+
+        metrics.registry.register(Box::new(metrics.updates.clone()))?;
+        metrics.registry.register(Box::new(metrics.answers.clone()))?;
+        metrics.registry.register(Box::new(metrics.handler_errors.clone()))?;
+        metrics.registry.register(Box::new(metrics.telegram_requests.clone()))?;
+        metrics.registry.register(Box::new(metrics.service_calls.clone()))?;
+        metrics.registry.register(Box::new(metrics.queue_refill_size.clone()))?;
+        metrics.registry.register(Box::new(metrics.active_users.clone()))?;
+        Ok(metrics)
+    }
+
+    pub fn record_active_user(&self, uid: i64) {
+        self.last_seen.lock().unwrap().insert(uid, Instant::now());
+    }
+
+    fn update_active_users(&self) {
+        let mut last_seen = self.last_seen.lock().unwrap();
+        last_seen.retain(|_, seen| seen.elapsed() < ACTIVE_PERIOD);
+        self.active_users.set(last_seen.len() as i64);
+    }
+
+    /// Ready once the bot receives updates, not ready again on shutdown.
+    pub fn set_ready(&self, ready: bool) {
+        self.ready.store(ready, Ordering::Relaxed);
+    }
+
+    fn render(&self) -> anyhow::Result<String> {
+        self.update_active_users();
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8(buffer)?)
+    }
+}
+
+fn result_label<T, E>(result: &Result<T, E>) -> &'static str {
+    match result {
+        Ok(_) => "ok",
+        Err(_) => "error",
+    }
+}
+
+/// Times a service call, the result is recorded as a label.
+pub async fn measure<T, E>(
+    service: &'static str,
+    method: &'static str,
+    call: impl Future<Output = Result<T, E>>,
+) -> Result<T, E> {
+    let start = Instant::now();
+    let result = call.await;
+    metrics()
+        .service_calls
+        .with_label_values(&[service, method, result_label(&result)])
+        .observe(start.elapsed().as_secs_f64());
+    result
+}
+
+/// Sends a Telegram request recording its latency by the API method name.
+pub trait SendMeasured: Request {
+    fn send_measured(self) -> impl Future<Output = Result<Output<Self>, Self::Err>> + Send;
+}
+
+impl<R> SendMeasured for R
+where
+    R: Request + Send,
+{
+    async fn send_measured(self) -> Result<Output<Self>, Self::Err> {
+        let method = <<Self as HasPayload>::Payload as Payload>::NAME;
+        let start = Instant::now();
+        let result = self.send().await;
+        metrics()
+            .telegram_requests
+            .with_label_values(&[method, result_label(&result)])
+            .observe(start.elapsed().as_secs_f64());
+        result
+    }
+}
+
+/// Serves `/metrics`, `/healthz` and `/readyz` until the process exits.
+pub async fn serve(listen: SocketAddr) -> anyhow::Result<()> {
+    let app = Router::new()
+        .route(
+            "/metrics",
+            get(|| async {
+                metrics()
+                    .render()
+                    .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))
+            }),
+        )
+        .route("/healthz", get(|| async { "ok" }))
+        .route(
+            "/readyz",
+            get(|| async {
+                match metrics().ready.load(Ordering::Relaxed) {
+                    true => (StatusCode::OK, "ready"),
+                    false => (StatusCode::SERVICE_UNAVAILABLE, "not ready"),
+                }
+            }),
+        );
+
+    log::info!("Serving metrics on {listen}");
+    axum::Server::try_bind(&listen)
+        .with_context(|| format!("Failed to listen on {listen} for metrics"))?
+        .serve(app.into_make_service())
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_render() -> anyhow::Result<()> {
+        metrics().updates.with_label_values(&["message"]).inc();
+        metrics().record_active_user(1);
+        let result: Result<(), ()> = measure("user_state", "touch_user", async { Err(()) }).await;
+        assert!(result.is_err());
+
+        let text = metrics().render()?;
+        assert!(text.contains(r#"words_bot_updates_total{type="message"}"#), "{text}");
+        assert!(text.contains("words_bot_active_users 1"), "{text}");
+        assert!(
+            text.contains(
+                r#"words_bot_service_call_seconds_count{method="touch_user",result="error",service="user_state"} 1"#
+            ),
+            "{text}"
+        );
+        Ok(())
+    }
+}
@@ -1,4 +1,8 @@
-use std::{fs, path::Path};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
 
 use serde::{Deserialize, Serialize};
 
@@ -11,12 +15,87 @@ pub struct Task {
     pub hash: i64,
     pub task: String,
     pub masked_task: String,
+    #[serde(default)]
     pub correct: String,
     pub base: String,
     pub info: Vec<String>,
     pub hints: Vec<Hint>,
     pub filters: Vec<FilterValue>,
-    pub wrong_answers: Vec<String>,
+    #[serde(default)]
+    pub wrong_answers: Vec<WrongAnswer>,
+    /// Per-gap answers for sentences with several `*****` masks, answered one gap at a time.
+    /// Tasks without `gaps` are answered as a whole using `correct` and `wrong_answers`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub gaps: Vec<Gap>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Gap {
+    pub correct: String,
+    pub wrong_answers: Vec<WrongAnswer>,
+}
+
+impl Gap {
+    pub fn explanation_for(&self, answer: &str) -> Option<&str> {
+        self.wrong_answers
+            .iter()
+            .find(|wrong_answer| wrong_answer.text == answer)
+            .and_then(|wrong_answer| wrong_answer.explanation.as_deref())
+    }
+}
+
+/// Distractor, written in YAML either as a plain string or as `{text, explanation}`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(from = "WrongAnswerData")]
+pub struct WrongAnswer {
+    pub text: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub explanation: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum WrongAnswerData {
+    Text(String),
+    Explained {
+        text: String,
+        #[serde(default)]
+        explanation: Option<String>,
+    },
+}
+
+impl From<WrongAnswerData> for WrongAnswer {
+    fn from(data: WrongAnswerData) -> Self {
+        match data {
+            WrongAnswerData::Text(text) => Self {
+                text,
+                explanation: None,
+            },
+            WrongAnswerData::Explained { text, explanation } => Self { text, explanation },
+        }
+    }
+}
+
+impl From<&str> for WrongAnswer {
+    fn from(text: &str) -> Self {
+        Self {
+            text: text.into(),
+            explanation: None,
+        }
+    }
+}
+
+impl Task {
+    pub fn gaps(&self) -> Vec<Gap> {
+        if self.gaps.is_empty() {
+            vec![Gap {
+                correct: self.correct.clone(),
+                wrong_answers: self.wrong_answers.clone(),
+            }]
+        } else {
+            self.gaps.clone()
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -33,37 +112,345 @@ pub struct FilterValue {
 
 #[derive(Debug, Deserialize)]
 pub struct TaskGroup {
-    pub theme: String,
-    pub category: String,
     pub tasks: Vec<Task>,
 }
 
+/// Reference about a filter value, e.g. a case, shown to learners who don't know it yet.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct GrammarCard {
+    /// Filter name and value of the tasks the card explains
+    pub name: String,
+    pub value: String,
+    pub title: String,
+    #[serde(default)]
+    pub usage: Vec<String>,
+    /// Declension table, shown in monospace
+    #[serde(default)]
+    pub table: String,
+    #[serde(default)]
+    pub examples: Vec<GrammarExample>,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct GrammarExample {
+    pub text: String,
+    #[serde(default)]
+    pub translation: String,
+}
+
+/// Ordered lessons, every lesson unlocks when the previous one is mastered.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct Curriculum {
+    /// Share of correct answers on the previous lesson tasks needed to unlock the next lesson
+    #[serde(default = "default_unlock_threshold")]
+    pub unlock_threshold: f64,
+    /// Answers on the previous lesson tasks needed before the share counts
+    #[serde(default = "default_min_answers")]
+    pub min_answers: i64,
+    pub lessons: Vec<Lesson>,
+}
+
+impl Default for Curriculum {
+    fn default() -> Self {
+        Self {
+            unlock_threshold: default_unlock_threshold(),
+            min_answers: default_min_answers(),
+            lessons: Vec::new(),
+        }
+    }
+}
+
+fn default_unlock_threshold() -> f64 {
+    0.8
+}
+
+fn default_min_answers() -> i64 {
+    10
+}
+
+/// Tasks of the lesson are selected with the usual filter: any of the themes and the extra filter.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct Lesson {
+    /// Stored in user progress and sent in buttons, so it must be stable and short
+    pub id: String,
+    pub title: String,
+    /// Themes of the task groups
+    #[serde(default)]
+    pub themes: Vec<String>,
+    #[serde(default)]
+    pub filter: Option<String>,
+}
+
+impl Lesson {
+    pub fn filter_text(&self) -> String {
+        let mut groups = Vec::new();
+        if !self.themes.is_empty() {
+            groups.push(self.themes.join(", "));
+        }
+        groups.extend(self.filter.clone());
+        groups.join("; ")
+    }
+}
+
 fn read_model_from_file(file_path: &str) -> anyhow::Result<TaskGroup> {
     let file_contents = std::fs::read_to_string(file_path)?;
-    let model: TaskGroup = serde_yaml::from_str(&file_contents)?;
+    parse_task_group(&file_contents)
+}
+
+fn parse_task_group(contents: &str) -> anyhow::Result<TaskGroup> {
+    let mut model: TaskGroup = serde_yaml::from_str(contents)?;
+    for task in &mut model.tasks {
+        if task.correct.is_empty() {
+            task.correct = task.gaps.iter().map(|gap| gap.correct.as_str()).collect::<Vec<_>>().join(" ");
+        }
+    }
     Ok(model)
 }
 
 pub fn scan_data_directory(directory_path: &str) -> anyhow::Result<Vec<TaskGroup>> {
     let mut task_groups = Vec::new();
-    let path = Path::new(directory_path);
+    for file_path in yaml_files(Path::new(directory_path))? {
+        if let Some(file_path_str) = file_path.to_str() {
+            match read_model_from_file(file_path_str) {
+                Ok(task_group) => task_groups.push(task_group),
+                Err(err) => {
+                    log::error!("Error reading file {:?}: {}", file_path, err);
+                }
+            }
+        }
+    }
+    Ok(task_groups)
+}
+
+/// Problems in the data directory, unlike the scans a broken file is reported instead of skipped.
+pub fn lint_data_directory(directory_path: &str) -> anyhow::Result<Vec<String>> {
+    let mut problems = Vec::new();
+    let mut hashes: HashMap<i64, PathBuf> = HashMap::new();
+    for file_path in yaml_files(Path::new(directory_path))? {
+        let task_group = match fs::read_to_string(&file_path)
+            .map_err(anyhow::Error::from)
+            .and_then(|contents| parse_task_group(&contents))
+        {
+            Ok(task_group) => task_group,
+            Err(err) => {
+                problems.push(format!("{}: {err}", file_path.display()));
+                continue;
+            }
+        };
+        for task in &task_group.tasks {
+            if let Some(other_path) = hashes.insert(task.hash, file_path.clone()) {
+                problems.push(format!(
+                    "{}: task {} has the same hash as a task in {}",
+                    file_path.display(),
+                    task.hash,
+                    other_path.display()
+                ));
+            }
+            problems.extend(
+                lint_task(task)
+                    .into_iter()
+                    .map(|problem| format!("{}: task {}: {problem}", file_path.display(), task.hash)),
+            );
+        }
+    }
+
+    for file_path in yaml_files(&Path::new(directory_path).join("grammar"))? {
+        if let Err(err) = read_grammar_card(&file_path) {
+            problems.push(format!("{}: {err}", file_path.display()));
+        }
+    }
+
+    match read_curriculum(directory_path) {
+        Ok(curriculum) => {
+            let mut ids = HashSet::new();
+            for lesson in &curriculum.lessons {
+                if !ids.insert(&lesson.id) {
+                    problems.push(format!("curriculum: lesson id {} is used twice", lesson.id));
+                }
+            }
+        }
+        Err(err) => problems.push(format!("curriculum: {err}")),
+    }
+
+    Ok(problems)
+}
+
+fn lint_task(task: &Task) -> Vec<String> {
+    let mut problems = Vec::new();
+    let masks = task.masked_task.matches("*****").count();
+    let gaps = task.gaps();
+    if masks != gaps.len() {
+        problems.push(format!("{masks} masks in masked_task, but {} gaps", gaps.len()));
+    }
+    for (index, gap) in gaps.iter().enumerate() {
+        if gap.correct.is_empty() {
+            problems.push(format!("gap {} has no correct answer", index + 1));
+        }
+        if !gap.wrong_answers.iter().any(|wrong_answer| wrong_answer.text != gap.correct) {
+            problems.push(format!("gap {} has no wrong answers", index + 1));
+        }
+    }
+    problems
+}
+
+/// Reads grammar cards from the `grammar` subdirectory of the data directory, one card per file.
+pub fn scan_grammar_directory(directory_path: &str) -> anyhow::Result<Vec<GrammarCard>> {
+    let mut cards = Vec::new();
+    for file_path in yaml_files(&Path::new(directory_path).join("grammar"))? {
+        match read_grammar_card(&file_path) {
+            Ok(card) => cards.push(card),
+            Err(err) => {
+                log::error!("Error reading grammar card {:?}: {}", file_path, err);
+            }
+        }
+    }
+    cards.sort_by(|a, b| (&a.name, &a.value).cmp(&(&b.name, &b.value)));
+    Ok(cards)
+}
+
+/// Reads `course/curriculum.yaml` from the data directory, without it there are no lessons.
+pub fn read_curriculum(directory_path: &str) -> anyhow::Result<Curriculum> {
+    let path = Path::new(directory_path).join("course").join("curriculum.yaml");
+    if !path.exists() {
+        return Ok(Curriculum::default());
+    }
+    let file_contents = fs::read_to_string(path)?;
+    Ok(serde_yaml::from_str(&file_contents)?)
+}
+
+fn read_grammar_card(file_path: &Path) -> anyhow::Result<GrammarCard> {
+    let file_contents = fs::read_to_string(file_path)?;
+    Ok(serde_yaml::from_str(&file_contents)?)
+}
+
+fn yaml_files(path: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
     if path.is_dir() {
         for entry in fs::read_dir(path)? {
-            let entry = entry?;
-            let file_path = entry.path();
+            let file_path = entry?.path();
             if let Some(extension) = file_path.extension() {
                 if extension == "yaml" || extension == "yml" {
-                    if let Some(file_path_str) = file_path.to_str() {
-                        match read_model_from_file(file_path_str) {
-                            Ok(task_group) => task_groups.push(task_group),
-                            Err(err) => {
-                                log::error!("Error reading file {:?}: {}", file_path, err);
-                            }
-                        }
-                    }
+                    files.push(file_path);
                 }
             }
         }
     }
-    Ok(task_groups)
+    Ok(files)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_single_answer_task() {
+        let group = parse_task_group(indoc::indoc! {"
+            theme: Shopping
+            category: cases
+            tasks:
+            - hash: 1
+              task: Ovo je moja kuća.
+              masked_task: Ovo je *****.
+              correct: moja kuća
+              base: moja kuća
+              info: []
+              hints: []
+              filters: []
+              wrong_answers:
+              - moju kuću
+        "})
+        .unwrap();
+
+        let task = &group.tasks[0];
+        assert!(task.gaps.is_empty());
+        assert_eq!(
+            task.gaps(),
+            vec![Gap {
+                correct: "moja kuća".into(),
+                wrong_answers: vec!["moju kuću".into()],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_parse_multi_gap_task() {
+        let group = parse_task_group(indoc::indoc! {"
+            theme: Shopping
+            category: cases
+            tasks:
+            - hash: 1
+              task: U velikom supermarketu.
+              masked_task: U ***** *****.
+              base: veliki supermarket
+              info: []
+              hints: []
+              filters: []
+              gaps:
+              - correct: velikom
+                wrong_answers:
+                - veliki
+                - text: velikog
+                  explanation: genitiv
+              - correct: supermarketu
+                wrong_answers: [supermarket, supermarketa]
+        "})
+        .unwrap();
+
+        let task = &group.tasks[0];
+        assert_eq!(task.correct, "velikom supermarketu");
+        assert_eq!(task.gaps().len(), 2);
+        assert_eq!(task.gaps()[1].correct, "supermarketu");
+        assert_eq!(task.gaps()[0].explanation_for("velikog"), Some("genitiv"));
+        assert_eq!(task.gaps()[0].explanation_for("veliki"), None);
+    }
+
+    #[test]
+    fn test_lint() {
+        assert_eq!(lint_data_directory("data").unwrap(), Vec::<String>::new());
+
+        let group = parse_task_group(indoc::indoc! {"
+            theme: Shopping
+            category: cases
+            tasks:
+            - hash: 1
+              task: U velikom supermarketu.
+              masked_task: U *****.
+              base: veliki supermarket
+              info: []
+              hints: []
+              filters: []
+              gaps:
+              - correct: velikom
+                wrong_answers: [velikom]
+              - correct: supermarketu
+                wrong_answers: [supermarket]
+        "})
+        .unwrap();
+        assert_eq!(
+            lint_task(&group.tasks[0]),
+            vec!["1 masks in masked_task, but 2 gaps", "gap 1 has no wrong answers"]
+        );
+    }
+
+    #[test]
+    fn test_scan_grammar_directory() {
+        let cards = scan_grammar_directory("data").unwrap();
+        let locative = cards.iter().find(|card| card.value == "locative").unwrap();
+        assert_eq!(locative.name, "Падеж");
+        assert!(!locative.usage.is_empty());
+        assert!(!locative.examples.is_empty());
+
+        assert!(scan_grammar_directory("no-such-directory").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_read_curriculum() {
+        let curriculum = read_curriculum("data").unwrap();
+        assert!(!curriculum.lessons.is_empty());
+        let ids = curriculum.lessons.iter().map(|lesson| &lesson.id).collect::<HashSet<_>>();
+        assert_eq!(ids.len(), curriculum.lessons.len());
+        assert_eq!(curriculum.lessons[0].filter_text(), "Shopping; locative");
+
+        assert_eq!(read_curriculum("no-such-directory").unwrap(), Curriculum::default());
+    }
 }
@@ -1,2 +1,4 @@
+pub mod sqlite_task_info_service;
+pub mod sqlite_user_state;
 pub mod task_info_service;
 pub mod user_state;
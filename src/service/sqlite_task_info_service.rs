@@ -0,0 +1,223 @@
+use std::collections::HashMap;
+
+use indoc::indoc;
+use sqlx::{types::Json, SqlitePool};
+use time::OffsetDateTime;
+
+use crate::{
+    bot::{
+        bot_filter::{Filter, FilterInfo},
+        bot_services::TaskInfoService,
+    },
+    model::{FilterValue, Task, TaskId},
+};
+
+#[derive(Clone, Debug)]
+pub struct SqliteTaskInfoService {
+    pool: SqlitePool,
+}
+
+impl SqliteTaskInfoService {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+}
+
+impl TaskInfoService for SqliteTaskInfoService {
+    async fn get_task_ids(&self, filter: Option<&Filter>) -> anyhow::Result<Vec<TaskId>> {
+        let tasks: Vec<(i64, Json<Task>)> = sqlx::query_as(indoc! {"
+                SELECT id, task_data
+                FROM task_info
+                WHERE active = true AND disabled_at IS NULL
+                ORDER BY id
+            "})
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(tasks
+            .into_iter()
+            .filter(|(_, task)| filter.map(|f| f.matches(task)).unwrap_or(true))
+            .map(|(id, _)| id)
+            .collect())
+    }
+
+    async fn collect_filter_info(&self) -> anyhow::Result<Vec<FilterInfo>> {
+        let values: Vec<(String, String)> = sqlx::query_as(indoc! {"
+                SELECT DISTINCT f.key, f.value
+                FROM task_info, json_each(task_info.filters) f
+                WHERE active = true AND disabled_at IS NULL
+                ORDER BY 1, 2
+            "})
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut result: Vec<FilterInfo> = Vec::new();
+        for (name, value) in values {
+            match result.last_mut() {
+                Some(info) if info.name == name => info.possible_values.push(value),
+                _ => result.push(FilterInfo {
+                    name,
+                    possible_values: vec![value],
+                }),
+            }
+        }
+        Ok(result)
+    }
+
+    async fn sample_task_ids(&self, per_value: usize) -> anyhow::Result<Vec<TaskId>> {
+        let rows: Vec<(i64,)> = sqlx::query_as(indoc! {"
+                SELECT DISTINCT id
+                FROM (
+                    SELECT task_info.id, row_number() OVER (PARTITION BY f.key, f.value ORDER BY random()) AS position
+                    FROM task_info, json_each(task_info.filters) f
+                    WHERE active = true AND disabled_at IS NULL
+                )
+                WHERE position <= $1
+                ORDER BY id
+            "})
+        .bind(per_value as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|(id,)| id).collect())
+    }
+
+    async fn get_task(&self, id: i64) -> anyhow::Result<Option<Task>> {
+        let task: Option<(i64, Json<Task>)> = sqlx::query_as("SELECT id, task_data FROM task_info WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(task.map(|(id, json)| Task { id, ..json.0 }))
+    }
+
+    async fn get_tasks(&self, ids: &[TaskId]) -> anyhow::Result<Vec<Task>> {
+        let tasks: Vec<(i64, Json<Task>)> = sqlx::query_as(indoc! {"
+                SELECT id, task_data
+                FROM task_info
+                WHERE id IN (SELECT value FROM json_each($1))
+            "})
+        .bind(Json(ids))
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(tasks.into_iter().map(|(id, json)| Task { id, ..json.0 }).collect())
+    }
+
+    async fn get_task_filters(&self, ids: &[TaskId]) -> anyhow::Result<HashMap<TaskId, Vec<FilterValue>>> {
+        let rows: Vec<(i64, Json<HashMap<String, String>>)> = sqlx::query_as(indoc! {"
+                SELECT id, filters
+                FROM task_info
+                WHERE id IN (SELECT value FROM json_each($1))
+            "})
+        .bind(Json(ids))
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(id, filters)| {
+                let filters = filters.0.into_iter().map(|(name, value)| FilterValue { name, value });
+                (id, filters.collect())
+            })
+            .collect())
+    }
+
+    async fn update_tasks(&self, tasks: &[Task]) -> anyhow::Result<(u64, u64)> {
+        let mut tx = self.pool.begin().await?;
+
+        let mut ids = Vec::new();
+        for task in tasks {
+            let filters: HashMap<&str, &str> = task
+                .filters
+                .iter()
+                .map(|filter| (filter.name.as_str(), filter.value.as_str()))
+                .collect();
+            let (id,): (i64,) = sqlx::query_as(indoc! {"
+                    INSERT INTO task_info (hash, filters, active, task_data)
+                    VALUES ($1, $2, true, $3)
+                    ON CONFLICT (hash) DO UPDATE
+                    SET filters = $2, active = true, task_data = $3
+                    RETURNING id
+                "})
+            .bind(task.hash)
+            .bind(Json(filters))
+            .bind(Json(task))
+            .fetch_one(&mut *tx)
+            .await?;
+
+            ids.push(id);
+        }
+        log::info!("Inserted {} tasks", ids.len());
+
+        let result = sqlx::query(indoc! {"
+                UPDATE task_info
+                SET active = false
+                WHERE active = true AND id NOT IN (SELECT value FROM json_each($1))
+            "})
+        .bind(Json(&ids))
+        .execute(&mut *tx)
+        .await?;
+
+        log::info!("Deactivated {} tasks", result.rows_affected());
+
+        tx.commit().await?;
+        Ok((ids.len() as u64, result.rows_affected()))
+    }
+
+    async fn deactivate_task(&self, id: TaskId, disabled_by: i64) -> anyhow::Result<bool> {
+        let result = sqlx::query(indoc! {"
+                UPDATE task_info
+                SET disabled_at = $3, disabled_by = $2
+                WHERE id = $1 AND disabled_at IS NULL
+            "})
+        .bind(id)
+        .bind(disabled_by)
+        .bind(OffsetDateTime::now_utc())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn find_task_by_hash(&self, hash: i64) -> anyhow::Result<Option<TaskId>> {
+        let row: Option<(i64,)> = sqlx::query_as("SELECT id FROM task_info WHERE hash = $1")
+            .bind(hash)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(row.map(|(id,)| id))
+    }
+
+    async fn annotate_task(&self, id: TaskId, annotation: &str) -> anyhow::Result<bool> {
+        let result = sqlx::query("UPDATE task_info SET annotation = $2 WHERE id = $1")
+            .bind(id)
+            .bind(annotation)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_task_annotation(&self, id: TaskId) -> anyhow::Result<Option<String>> {
+        let row: Option<(Option<String>,)> = sqlx::query_as("SELECT annotation FROM task_info WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(row.and_then(|(annotation,)| annotation))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{bot::conformance, test_db::setup_sqlite};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_conformance() -> anyhow::Result<()> {
+        let pool = setup_sqlite().await;
+        conformance::task_info(&SqliteTaskInfoService::new(pool)).await
+    }
+}
@@ -0,0 +1,1193 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use sqlx::{types::Json, SqlitePool};
+use teloxide::types::{ChatId, MessageId};
+use time::{Date, OffsetDateTime};
+
+use crate::{
+    bot::bot_services::{
+        Answer, AnswerStat, BlitzResult, BotStats, Broadcast, BroadcastStatus, DailyStat, FeedbackMessage, GroupScore,
+        LeaderboardEntry, ProfileChange, QueuedTask, TaskAnswerCount, TaskQueue, TaskReportReason, TaskStats, UserData,
+        UserInfo, UserStateService, XpLeaderboardEntry,
+    },
+    model::TaskId,
+};
+
+use super::user_state::{user_condition, AnswerRow, BroadcastRow, UserInfoRow, USER_TABLES};
+
+/// Times are stored as RFC 3339 text in UTC, so they are compared with `julianday` and cut off in Rust.
+#[derive(Debug)]
+pub struct SqliteUserService {
+    pool: SqlitePool,
+}
+
+impl SqliteUserService {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+}
+
+fn since(period: Duration) -> OffsetDateTime {
+    OffsetDateTime::now_utc() - period
+}
+
+impl UserStateService for SqliteUserService {
+    async fn touch_user(&self, user: &UserInfo) -> anyhow::Result<bool> {
+        let now = OffsetDateTime::now_utc();
+        let mut tx = self.pool.begin().await?;
+        let old: Option<UserInfoRow> = sqlx::query_as(indoc::indoc! {"
+                SELECT uid, username, full_name, language_code, is_premium, created_at, last_active_at
+                FROM user_info
+                WHERE uid = $1
+            "})
+        .bind(user.uid)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        sqlx::query(indoc::indoc! {"
+                INSERT INTO user_info (uid, username, full_name, language_code, is_premium, created_at, last_active_at)
+                VALUES ($1, $2, $3, $4, $5, $6, $6)
+                ON CONFLICT (uid) DO UPDATE SET
+                    last_active_at = excluded.last_active_at,
+                    username = excluded.username,
+                    full_name = excluded.full_name,
+                    language_code = excluded.language_code,
+                    is_premium = excluded.is_premium
+            "})
+        .bind(user.uid)
+        .bind(user.username.as_deref())
+        .bind(user.full_name.as_str())
+        .bind(user.language_code.as_deref())
+        .bind(user.is_premium)
+        .bind(now)
+        .execute(&mut *tx)
+        .await?;
+
+        let new_user = old.is_none();
+        let changes = old.map(|old| UserInfo::from(old).profile_changes(user, now));
+        for change in changes.unwrap_or_default() {
+            sqlx::query(indoc::indoc! {"
+                    INSERT INTO user_profile_change (uid, field, old_value, new_value, changed_at)
+                    VALUES ($1, $2, $3, $4, $5)
+                "})
+            .bind(user.uid)
+            .bind(change.field)
+            .bind(change.old_value)
+            .bind(change.new_value)
+            .bind(change.changed_at)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+
+        Ok(new_user)
+    }
+
+    async fn get_profile_changes(&self, user_id: i64, limit: usize) -> anyhow::Result<Vec<ProfileChange>> {
+        let rows: Vec<(String, Option<String>, Option<String>, OffsetDateTime)> = sqlx::query_as(indoc::indoc! {"
+                SELECT field, old_value, new_value, changed_at
+                FROM (
+                    SELECT id, field, old_value, new_value, changed_at
+                    FROM user_profile_change
+                    WHERE uid = $1
+                    ORDER BY id DESC
+                    LIMIT $2
+                ) last_changes
+                ORDER BY id
+            "})
+        .bind(user_id)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(field, old_value, new_value, changed_at)| ProfileChange {
+                field,
+                old_value,
+                new_value,
+                changed_at,
+            })
+            .collect())
+    }
+
+    async fn get_state(&self, chat_id: ChatId) -> anyhow::Result<UserData> {
+        let row: Option<(Option<String>,)> = sqlx::query_as("SELECT filter FROM user_state WHERE chat_id = $1")
+            .bind(chat_id.0)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        let (filter,) = row.unwrap_or_default();
+
+        Ok(UserData::new(filter))
+    }
+
+    async fn update_state(&self, chat_id: ChatId, update: UserData) -> anyhow::Result<()> {
+        sqlx::query(indoc::indoc! {"
+                INSERT INTO user_state (chat_id, filter)
+                VALUES ($1, $2)
+                ON CONFLICT (chat_id) DO UPDATE SET filter = $2
+            "})
+        .bind(chat_id.0)
+        .bind(update.filter)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn update_tasks(&self, chat_id: ChatId, tasks: &[TaskId]) -> anyhow::Result<()> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("DELETE FROM user_task WHERE chat_id = $1")
+            .bind(chat_id.0)
+            .execute(&mut *tx)
+            .await?;
+
+        // The key of json_each is the array index, so the queue keeps the given order
+        sqlx::query(indoc::indoc! {"
+                INSERT INTO user_task (chat_id, task_id)
+                SELECT $1, value
+                FROM json_each($2)
+                ORDER BY key
+            "})
+        .bind(chat_id.0)
+        .bind(Json(tasks))
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn take_next_task(&self, chat_id: ChatId) -> anyhow::Result<Option<TaskId>> {
+        let row: Option<(i64,)> = sqlx::query_as(indoc::indoc! {"
+                DELETE FROM user_task
+                WHERE id = (
+                    SELECT id
+                    FROM user_task
+                    WHERE chat_id = $1
+                    ORDER BY id
+                    LIMIT 1
+                )
+                RETURNING task_id
+            "})
+        .bind(chat_id.0)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|(task_id,)| task_id))
+    }
+
+    async fn peek_tasks(&self, chat_id: ChatId, limit: usize) -> anyhow::Result<TaskQueue> {
+        let (remaining,): (i64,) = sqlx::query_as("SELECT count(*) FROM user_task WHERE chat_id = $1")
+            .bind(chat_id.0)
+            .fetch_one(&self.pool)
+            .await?;
+        let rows: Vec<(i64,)> = sqlx::query_as("SELECT task_id FROM user_task WHERE chat_id = $1 ORDER BY id LIMIT $2")
+            .bind(chat_id.0)
+            .bind(limit as i64)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(TaskQueue {
+            remaining: remaining as usize,
+            next: rows
+                .into_iter()
+                .enumerate()
+                .map(|(position, (task_id,))| QueuedTask { position, task_id })
+                .collect(),
+        })
+    }
+
+    async fn record_anwer(&self, answer: Answer) -> anyhow::Result<()> {
+        sqlx::query(indoc::indoc! {"
+                INSERT INTO user_answer (uid, task_id, gap, answer_index, answer_text, correct, asked_at, answered_at)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            "})
+        .bind(answer.uid)
+        .bind(answer.task_id)
+        .bind(answer.gap)
+        .bind(answer.answer_index)
+        .bind(answer.answer_text)
+        .bind(answer.correct)
+        .bind(answer.asked_at)
+        .bind(answer.answered_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_answers(&self, period: Duration) -> anyhow::Result<Vec<Answer>> {
+        let rows: Vec<AnswerRow> = sqlx::query_as(indoc::indoc! {"
+                SELECT uid, task_id, gap, answer_index, answer_text, correct, asked_at, answered_at
+                FROM user_answer
+                WHERE julianday(answered_at) > julianday($1)
+                ORDER BY julianday(answered_at), id
+            "})
+        .bind(since(period))
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(Answer::from).collect())
+    }
+
+    async fn get_task_stats(&self, period: Duration) -> anyhow::Result<Vec<TaskStats>> {
+        // No median aggregate in SQLite, the middle rows are found by their position within the task.
+        // Later gaps share the time the task was asked, only first gaps have the response time
+        let rows: Vec<(TaskId, i64, i64, f64)> = sqlx::query_as(indoc::indoc! {"
+                WITH timed AS (
+                    SELECT task_id, correct, gap,
+                        max(julianday(answered_at) - julianday(asked_at), 0) * 86400000.0 AS response_ms,
+                        row_number() OVER (
+                            PARTITION BY task_id, gap = 0 ORDER BY julianday(answered_at) - julianday(asked_at)
+                        ) AS position,
+                        count(*) OVER (PARTITION BY task_id, gap = 0) AS timed
+                    FROM user_answer
+                    WHERE julianday(answered_at) > julianday($1)
+                )
+                SELECT task_id, count(*), sum(correct),
+                    coalesce(avg(
+                        CASE WHEN gap = 0 AND position IN ((timed + 1) / 2, (timed + 2) / 2) THEN response_ms END
+                    ), 0)
+                FROM timed
+                GROUP BY task_id
+                ORDER BY task_id
+            "})
+        .bind(since(period))
+        .fetch_all(&self.pool)
+        .await?;
+
+        // Answers recorded before the chosen text was stored are not counted
+        let distractors: Vec<(TaskId, String, i64)> = sqlx::query_as(indoc::indoc! {"
+                SELECT task_id, answer_text, count(*)
+                FROM user_answer
+                WHERE julianday(answered_at) > julianday($1) AND NOT correct AND answer_text IS NOT NULL
+                GROUP BY task_id, answer_text
+                ORDER BY task_id, count(*) DESC, answer_text
+            "})
+        .bind(since(period))
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut stats = rows
+            .into_iter()
+            .map(|(task_id, answered, correct, median_ms)| TaskStats {
+                task_id,
+                answered,
+                correct,
+                median_response: Duration::from_millis(median_ms.round() as u64),
+                distractors: Vec::new(),
+            })
+            .collect::<Vec<_>>();
+        for (task_id, text, count) in distractors {
+            if let Ok(index) = stats.binary_search_by_key(&task_id, |task_stats| task_stats.task_id) {
+                stats[index].distractors.push((text, count));
+            }
+        }
+        Ok(stats)
+    }
+
+    async fn get_answer_stat(&self, user_id: i64, period: Duration) -> anyhow::Result<AnswerStat> {
+        let (count, correct): (i64, i64) = sqlx::query_as(indoc::indoc! {"
+                SELECT count(*), coalesce(sum(correct), 0)
+                FROM (
+                    SELECT min(correct) AS correct
+                    FROM user_answer
+                    WHERE uid = $1 AND julianday(answered_at) > julianday($2)
+                    GROUP BY task_id, asked_at
+                )
+            "})
+        .bind(user_id)
+        .bind(since(period))
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(AnswerStat { count, correct })
+    }
+
+    async fn get_task_answer_counts(&self, user_id: i64) -> anyhow::Result<Vec<TaskAnswerCount>> {
+        let rows: Vec<(i64, i64, i64)> = sqlx::query_as(indoc::indoc! {"
+                SELECT task_id, count(*), coalesce(sum(correct), 0)
+                FROM user_answer
+                WHERE uid = $1
+                GROUP BY task_id
+                ORDER BY task_id
+            "})
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(task_id, answered, correct)| TaskAnswerCount {
+                task_id,
+                answered,
+                correct,
+            })
+            .collect())
+    }
+
+    async fn get_global_task_answer_counts(&self) -> anyhow::Result<Vec<TaskAnswerCount>> {
+        let rows: Vec<(i64, i64, i64)> = sqlx::query_as(indoc::indoc! {"
+                SELECT task_id, count(*), coalesce(sum(correct), 0)
+                FROM user_answer
+                GROUP BY task_id
+                ORDER BY task_id
+            "})
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(task_id, answered, correct)| TaskAnswerCount {
+                task_id,
+                answered,
+                correct,
+            })
+            .collect())
+    }
+
+    async fn get_task_answer_count(&self, task_id: TaskId) -> anyhow::Result<TaskAnswerCount> {
+        let (answered, correct): (i64, i64) =
+            sqlx::query_as("SELECT count(*), coalesce(sum(correct), 0) FROM user_answer WHERE task_id = $1")
+                .bind(task_id)
+                .fetch_one(&self.pool)
+                .await?;
+
+        Ok(TaskAnswerCount {
+            task_id,
+            answered,
+            correct,
+        })
+    }
+
+    async fn count_answered_tasks(&self, user_id: i64) -> anyhow::Result<i64> {
+        let (count,): (i64,) = sqlx::query_as(indoc::indoc! {"
+                SELECT count(*)
+                FROM (SELECT DISTINCT task_id, asked_at FROM user_answer WHERE uid = $1)
+            "})
+        .bind(user_id)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(count)
+    }
+
+    async fn get_answer_days(&self, user_id: i64, limit: usize) -> anyhow::Result<Vec<Date>> {
+        let rows: Vec<(Date,)> = sqlx::query_as(indoc::indoc! {"
+                SELECT DISTINCT date(answered_at) as day
+                FROM user_answer
+                WHERE uid = $1
+                ORDER BY day DESC
+                LIMIT $2
+            "})
+        .bind(user_id)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|(day,)| day).collect())
+    }
+
+    async fn add_xp(&self, user_id: i64, xp: i64) -> anyhow::Result<i64> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("INSERT INTO user_xp (uid, xp, earned_at) SELECT uid, $2, $3 FROM user_info WHERE uid = $1")
+            .bind(user_id)
+            .bind(xp)
+            .bind(OffsetDateTime::now_utc())
+            .execute(&mut *tx)
+            .await?;
+        let row: Option<(i64,)> = sqlx::query_as("UPDATE user_info SET xp = xp + $2 WHERE uid = $1 RETURNING xp")
+            .bind(user_id)
+            .bind(xp)
+            .fetch_optional(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(row.map(|(xp,)| xp).unwrap_or_default())
+    }
+
+    async fn get_xp(&self, user_id: i64) -> anyhow::Result<i64> {
+        let row: Option<(i64,)> = sqlx::query_as("SELECT xp FROM user_info WHERE uid = $1")
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(row.map(|(xp,)| xp).unwrap_or_default())
+    }
+
+    async fn award_achievement(&self, user_id: i64, achievement_id: &str) -> anyhow::Result<bool> {
+        let result = sqlx::query(indoc::indoc! {"
+                INSERT INTO user_achievement (uid, achievement_id, unlocked_at)
+                VALUES ($1, $2, $3)
+                ON CONFLICT DO NOTHING
+            "})
+        .bind(user_id)
+        .bind(achievement_id)
+        .bind(OffsetDateTime::now_utc())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_achievements(&self, user_id: i64) -> anyhow::Result<Vec<(String, OffsetDateTime)>> {
+        Ok(sqlx::query_as(indoc::indoc! {"
+                SELECT achievement_id, unlocked_at
+                FROM user_achievement
+                WHERE uid = $1
+                ORDER BY julianday(unlocked_at), achievement_id
+            "})
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    async fn record_blitz_result(&self, result: BlitzResult) -> anyhow::Result<()> {
+        sqlx::query(indoc::indoc! {"
+                INSERT INTO blitz_result (uid, duration_secs, answered, correct, avg_response_ms, finished_at)
+                VALUES ($1, $2, $3, $4, $5, $6)
+            "})
+        .bind(result.uid)
+        .bind(result.duration_secs)
+        .bind(result.answered)
+        .bind(result.correct)
+        .bind(result.avg_response_ms)
+        .bind(result.finished_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_best_blitz_result(&self, user_id: i64, duration_secs: i32) -> anyhow::Result<Option<BlitzResult>> {
+        let row: Option<(i64, i32, i64, i64, i64, OffsetDateTime)> = sqlx::query_as(indoc::indoc! {"
+                SELECT uid, duration_secs, answered, correct, avg_response_ms, finished_at
+                FROM blitz_result
+                WHERE uid = $1 AND duration_secs = $2
+                ORDER BY correct DESC, answered - correct, julianday(finished_at)
+                LIMIT 1
+            "})
+        .bind(user_id)
+        .bind(duration_secs)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(
+            |(uid, duration_secs, answered, correct, avg_response_ms, finished_at)| BlitzResult {
+                uid,
+                duration_secs,
+                answered,
+                correct,
+                avg_response_ms,
+                finished_at,
+            },
+        ))
+    }
+
+    async fn update_group_scores(&self, chat_id: ChatId, results: &[(i64, bool)]) -> anyhow::Result<()> {
+        let mut tx = self.pool.begin().await?;
+        for (uid, correct) in results {
+            sqlx::query(indoc::indoc! {"
+                    INSERT INTO group_score (chat_id, uid, answered, correct)
+                    VALUES ($1, $2, 1, $3)
+                    ON CONFLICT (chat_id, uid) DO UPDATE
+                    SET answered = answered + excluded.answered,
+                        correct = correct + excluded.correct
+                "})
+            .bind(chat_id.0)
+            .bind(uid)
+            .bind(*correct as i64)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn get_group_scoreboard(&self, chat_id: ChatId, limit: usize) -> anyhow::Result<Vec<GroupScore>> {
+        let rows: Vec<(i64, String, i64, i64)> = sqlx::query_as(indoc::indoc! {"
+                SELECT s.uid, u.full_name, s.answered, s.correct
+                FROM group_score s
+                JOIN user_info u ON u.uid = s.uid
+                WHERE s.chat_id = $1
+                ORDER BY s.correct DESC, s.answered, s.uid
+                LIMIT $2
+            "})
+        .bind(chat_id.0)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(uid, full_name, answered, correct)| GroupScore {
+                uid,
+                full_name,
+                answered,
+                correct,
+            })
+            .collect())
+    }
+
+    async fn set_public_profile(&self, user_id: i64, public: bool) -> anyhow::Result<()> {
+        sqlx::query("UPDATE user_info SET public_profile = $2 WHERE uid = $1")
+            .bind(user_id)
+            .bind(public)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn is_public_profile(&self, user_id: i64) -> anyhow::Result<bool> {
+        let row: Option<(bool,)> = sqlx::query_as("SELECT public_profile FROM user_info WHERE uid = $1")
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(row.map(|(public,)| public).unwrap_or_default())
+    }
+
+    async fn add_friend(&self, user_id: i64, friend_id: i64) -> anyhow::Result<bool> {
+        let result = sqlx::query(indoc::indoc! {"
+                INSERT INTO user_friend (uid, friend_uid, created_at)
+                SELECT u.uid, f.uid, $3
+                FROM user_info u, user_info f
+                WHERE (u.uid = $1 AND f.uid = $2) OR (u.uid = $2 AND f.uid = $1)
+                ON CONFLICT DO NOTHING
+            "})
+        .bind(user_id)
+        .bind(friend_id)
+        .bind(OffsetDateTime::now_utc())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_friends(&self, user_id: i64) -> anyhow::Result<Vec<i64>> {
+        let rows: Vec<(i64,)> = sqlx::query_as("SELECT friend_uid FROM user_friend WHERE uid = $1 ORDER BY friend_uid")
+            .bind(user_id)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(rows.into_iter().map(|(uid,)| uid).collect())
+    }
+
+    async fn get_invite_token(&self, user_id: i64, new_token: &str) -> anyhow::Result<String> {
+        sqlx::query(indoc::indoc! {"
+                INSERT INTO friend_invite (token, uid, created_at)
+                VALUES ($2, $1, $3)
+                ON CONFLICT (uid) DO NOTHING
+            "})
+        .bind(user_id)
+        .bind(new_token)
+        .bind(OffsetDateTime::now_utc())
+        .execute(&self.pool)
+        .await?;
+
+        let (token,): (String,) = sqlx::query_as("SELECT token FROM friend_invite WHERE uid = $1")
+            .bind(user_id)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(token)
+    }
+
+    async fn find_invite_owner(&self, token: &str) -> anyhow::Result<Option<i64>> {
+        let row: Option<(i64,)> = sqlx::query_as("SELECT uid FROM friend_invite WHERE token = $1")
+            .bind(token)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row.map(|(uid,)| uid))
+    }
+
+    async fn get_unlocked_lessons(&self, user_id: i64) -> anyhow::Result<Vec<String>> {
+        let rows: Vec<(String,)> =
+            sqlx::query_as("SELECT lesson_id FROM user_lesson WHERE uid = $1 ORDER BY lesson_id")
+                .bind(user_id)
+                .fetch_all(&self.pool)
+                .await?;
+
+        Ok(rows.into_iter().map(|(lesson_id,)| lesson_id).collect())
+    }
+
+    async fn unlock_lesson(&self, user_id: i64, lesson_id: &str) -> anyhow::Result<bool> {
+        let result = sqlx::query(indoc::indoc! {"
+                INSERT INTO user_lesson (uid, lesson_id, unlocked_at)
+                VALUES ($1, $2, $3)
+                ON CONFLICT DO NOTHING
+            "})
+        .bind(user_id)
+        .bind(lesson_id)
+        .bind(OffsetDateTime::now_utc())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_leaderboard(
+        &self,
+        period: Duration,
+        user_ids: Option<&[i64]>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<LeaderboardEntry>> {
+        let rows: Vec<(i64, String, i64, i64)> = sqlx::query_as(indoc::indoc! {"
+                SELECT t.uid, u.full_name, count(*) as answered, coalesce(sum(t.correct), 0) as correct
+                FROM (
+                    SELECT uid, min(correct) AS correct
+                    FROM user_answer
+                    WHERE julianday(answered_at) > julianday($1)
+                    GROUP BY uid, task_id, asked_at
+                ) t
+                JOIN user_info u ON u.uid = t.uid
+                WHERE ($2 IS NULL AND u.public_profile) OR t.uid IN (SELECT value FROM json_each($2))
+                GROUP BY t.uid, u.full_name
+                ORDER BY correct DESC, answered, t.uid
+                LIMIT $3
+            "})
+        .bind(since(period))
+        .bind(user_ids.map(Json))
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(uid, full_name, answered, correct)| LeaderboardEntry {
+                uid,
+                full_name,
+                answered,
+                correct,
+            })
+            .collect())
+    }
+
+    async fn get_xp_leaderboard(
+        &self,
+        period: Duration,
+        user_ids: Option<&[i64]>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<XpLeaderboardEntry>> {
+        let rows: Vec<(i64, String, i64)> = sqlx::query_as(indoc::indoc! {"
+                SELECT e.uid, u.full_name, sum(e.xp) as xp
+                FROM user_xp e
+                JOIN user_info u ON u.uid = e.uid
+                WHERE julianday(e.earned_at) > julianday($1)
+                    AND (($2 IS NULL AND u.public_profile) OR e.uid IN (SELECT value FROM json_each($2)))
+                GROUP BY e.uid, u.full_name
+                HAVING sum(e.xp) > 0
+                ORDER BY xp DESC, e.uid
+                LIMIT $3
+            "})
+        .bind(since(period))
+        .bind(user_ids.map(Json))
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(uid, full_name, xp)| XpLeaderboardEntry { uid, full_name, xp })
+            .collect())
+    }
+
+    async fn get_bot_stats(&self, period: Duration) -> anyhow::Result<BotStats> {
+        let from = since(period);
+        let (total_users, new_users): (i64, i64) = sqlx::query_as(indoc::indoc! {"
+                SELECT count(*), count(*) FILTER (WHERE julianday(created_at) > julianday($1))
+                FROM user_info
+            "})
+        .bind(from)
+        .fetch_one(&self.pool)
+        .await?;
+
+        let rows: Vec<(Date, i64, i64, i64)> = sqlx::query_as(indoc::indoc! {"
+                SELECT date(answered_at) as day, count(distinct uid), count(*), coalesce(sum(correct), 0)
+                FROM user_answer
+                WHERE julianday(answered_at) > julianday($1)
+                GROUP BY 1
+                ORDER BY 1
+            "})
+        .bind(from)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(BotStats {
+            total_users,
+            new_users,
+            daily: rows
+                .into_iter()
+                .map(|(day, active_users, answered, correct)| DailyStat {
+                    day,
+                    active_users,
+                    answered,
+                    correct,
+                })
+                .collect(),
+        })
+    }
+
+    async fn find_user(&self, username: &str) -> anyhow::Result<Option<UserInfo>> {
+        let row: Option<UserInfoRow> = sqlx::query_as(indoc::indoc! {"
+                SELECT uid, username, full_name, language_code, is_premium, created_at, last_active_at
+                FROM user_info
+                WHERE lower(username) = lower($1)
+            "})
+        .bind(username)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(UserInfo::from))
+    }
+
+    async fn create_broadcast(&self, author_uid: i64, text: &str) -> anyhow::Result<i64> {
+        let (id,): (i64,) = sqlx::query_as(indoc::indoc! {"
+                INSERT INTO broadcast (author_uid, text, status, created_at)
+                VALUES ($1, $2, $3, $4)
+                RETURNING id
+            "})
+        .bind(author_uid)
+        .bind(text)
+        .bind(BroadcastStatus::Draft.as_str())
+        .bind(OffsetDateTime::now_utc())
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(id)
+    }
+
+    async fn get_broadcast(&self, id: i64) -> anyhow::Result<Option<Broadcast>> {
+        let row: Option<BroadcastRow> = sqlx::query_as(indoc::indoc! {"
+                SELECT id, author_uid, text, status, last_chat_id, sent, failed
+                FROM broadcast
+                WHERE id = $1
+            "})
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        row.map(Broadcast::try_from).transpose()
+    }
+
+    async fn get_broadcasts(&self, status: BroadcastStatus) -> anyhow::Result<Vec<Broadcast>> {
+        let rows: Vec<BroadcastRow> = sqlx::query_as(indoc::indoc! {"
+                SELECT id, author_uid, text, status, last_chat_id, sent, failed
+                FROM broadcast
+                WHERE status = $1
+                ORDER BY id
+            "})
+        .bind(status.as_str())
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter().map(Broadcast::try_from).collect()
+    }
+
+    async fn update_broadcast_status(
+        &self,
+        id: i64,
+        from: BroadcastStatus,
+        to: BroadcastStatus,
+    ) -> anyhow::Result<bool> {
+        let result = sqlx::query("UPDATE broadcast SET status = $3 WHERE id = $1 AND status = $2")
+            .bind(id)
+            .bind(from.as_str())
+            .bind(to.as_str())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_broadcast_chats(&self, after_chat_id: Option<i64>, limit: usize) -> anyhow::Result<Vec<ChatId>> {
+        let rows: Vec<(i64,)> = sqlx::query_as(indoc::indoc! {"
+                SELECT c.chat_id
+                FROM (SELECT uid AS chat_id FROM user_info UNION SELECT chat_id FROM user_state) c
+                LEFT JOIN user_info u ON u.uid = c.chat_id
+                LEFT JOIN chat_blocked b ON b.chat_id = c.chat_id
+                WHERE ($1 IS NULL OR c.chat_id > $1)
+                    AND (b.blocked_at IS NULL OR julianday(b.blocked_at) < julianday(u.last_active_at))
+                ORDER BY c.chat_id
+                LIMIT $2
+            "})
+        .bind(after_chat_id)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|(chat_id,)| ChatId(chat_id)).collect())
+    }
+
+    async fn count_broadcast_chats(&self) -> anyhow::Result<i64> {
+        let (count,): (i64,) = sqlx::query_as(indoc::indoc! {"
+                SELECT count(*)
+                FROM (SELECT uid AS chat_id FROM user_info UNION SELECT chat_id FROM user_state) c
+                LEFT JOIN user_info u ON u.uid = c.chat_id
+                LEFT JOIN chat_blocked b ON b.chat_id = c.chat_id
+                WHERE b.blocked_at IS NULL OR julianday(b.blocked_at) < julianday(u.last_active_at)
+            "})
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(count)
+    }
+
+    async fn record_broadcast_delivery(&self, id: i64, chat_id: ChatId, delivered: bool) -> anyhow::Result<()> {
+        sqlx::query(indoc::indoc! {"
+                UPDATE broadcast
+                SET last_chat_id = $2, sent = sent + $3, failed = failed + (NOT $3)
+                WHERE id = $1
+            "})
+        .bind(id)
+        .bind(chat_id.0)
+        .bind(delivered)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn record_blocked_chat(&self, chat_id: ChatId) -> anyhow::Result<()> {
+        sqlx::query(indoc::indoc! {"
+                INSERT INTO chat_blocked (chat_id, blocked_at)
+                VALUES ($1, $2)
+                ON CONFLICT (chat_id) DO UPDATE SET blocked_at = excluded.blocked_at
+            "})
+        .bind(chat_id.0)
+        .bind(OffsetDateTime::now_utc())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn record_feedback(
+        &self,
+        chat_id: ChatId,
+        from_admin: bool,
+        text: &str,
+        feedback_message_id: MessageId,
+    ) -> anyhow::Result<()> {
+        sqlx::query(indoc::indoc! {"
+                INSERT INTO feedback (chat_id, from_admin, text, feedback_message_id, created_at)
+                VALUES ($1, $2, $3, $4, $5)
+            "})
+        .bind(chat_id.0)
+        .bind(from_admin)
+        .bind(text)
+        .bind(feedback_message_id.0)
+        .bind(OffsetDateTime::now_utc())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn find_feedback_chat(&self, feedback_message_id: MessageId) -> anyhow::Result<Option<ChatId>> {
+        let row: Option<(i64,)> = sqlx::query_as(indoc::indoc! {"
+                SELECT chat_id
+                FROM feedback
+                WHERE feedback_message_id = $1
+                ORDER BY id DESC
+                LIMIT 1
+            "})
+        .bind(feedback_message_id.0)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|(chat_id,)| ChatId(chat_id)))
+    }
+
+    async fn get_feedback_history(&self, chat_id: ChatId, limit: usize) -> anyhow::Result<Vec<FeedbackMessage>> {
+        let rows: Vec<(bool, String, OffsetDateTime)> = sqlx::query_as(indoc::indoc! {"
+                SELECT from_admin, text, created_at
+                FROM (
+                    SELECT id, from_admin, text, created_at
+                    FROM feedback
+                    WHERE chat_id = $1
+                    ORDER BY id DESC
+                    LIMIT $2
+                ) f
+                ORDER BY id
+            "})
+        .bind(chat_id.0)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(from_admin, text, created_at)| FeedbackMessage {
+                chat_id,
+                from_admin,
+                text,
+                created_at,
+            })
+            .collect())
+    }
+
+    async fn record_task_report(
+        &self,
+        uid: i64,
+        task_id: TaskId,
+        reason: TaskReportReason,
+    ) -> anyhow::Result<Option<i64>> {
+        let row: Option<(i64,)> = sqlx::query_as(indoc::indoc! {"
+                INSERT INTO task_report (task_id, uid, reason, created_at)
+                VALUES ($1, $2, $3, $4)
+                ON CONFLICT (task_id, uid, reason) WHERE NOT resolved DO NOTHING
+                RETURNING id
+            "})
+        .bind(task_id)
+        .bind(uid)
+        .bind(reason.as_str())
+        .bind(OffsetDateTime::now_utc())
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|(id,)| id))
+    }
+
+    async fn set_task_report_message(&self, report_id: i64, feedback_message_id: MessageId) -> anyhow::Result<()> {
+        sqlx::query("UPDATE task_report SET feedback_message_id = $2 WHERE id = $1")
+            .bind(report_id)
+            .bind(feedback_message_id.0)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn find_reported_task(&self, feedback_message_id: MessageId) -> anyhow::Result<Option<TaskId>> {
+        let row: Option<(i64,)> = sqlx::query_as(indoc::indoc! {"
+                SELECT task_id
+                FROM task_report
+                WHERE feedback_message_id = $1
+                ORDER BY id DESC
+                LIMIT 1
+            "})
+        .bind(feedback_message_id.0)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|(task_id,)| task_id))
+    }
+
+    async fn get_task_report_counts(&self, task_id: TaskId) -> anyhow::Result<Vec<(TaskReportReason, i64)>> {
+        let rows: Vec<(String, i64)> = sqlx::query_as(indoc::indoc! {"
+                SELECT reason, count(*) as count
+                FROM task_report
+                WHERE task_id = $1 AND NOT resolved
+                GROUP BY reason
+                ORDER BY count DESC, reason
+            "})
+        .bind(task_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter()
+            .map(|(reason, count)| Ok((TaskReportReason::parse(&reason)?, count)))
+            .collect()
+    }
+
+    async fn get_reported_tasks(&self, limit: usize) -> anyhow::Result<Vec<(TaskId, i64)>> {
+        let rows: Vec<(i64, i64)> = sqlx::query_as(indoc::indoc! {"
+                SELECT task_id, count(*) as count
+                FROM task_report
+                WHERE NOT resolved
+                GROUP BY task_id
+                ORDER BY count DESC, task_id
+                LIMIT $1
+            "})
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows)
+    }
+
+    async fn resolve_task_reports(&self, task_id: TaskId) -> anyhow::Result<()> {
+        sqlx::query("UPDATE task_report SET resolved = true WHERE task_id = $1 AND NOT resolved")
+            .bind(task_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn export_user_data(&self, user_id: i64) -> anyhow::Result<BTreeMap<String, serde_json::Value>> {
+        let mut data = BTreeMap::new();
+        for (table, columns) in USER_TABLES {
+            let all_columns: Vec<(String,)> = sqlx::query_as("SELECT name FROM pragma_table_info($1) ORDER BY cid")
+                .bind(table)
+                .fetch_all(&self.pool)
+                .await?;
+            let object = all_columns
+                .iter()
+                .map(|(column,)| format!(r#"'{column}', "{column}""#))
+                .collect::<Vec<_>>()
+                .join(", ");
+            let (Json(rows),): (Json<serde_json::Value>,) = sqlx::query_as(&format!(
+                r#"SELECT json_group_array(json_object({object})) FROM "{table}" WHERE {}"#,
+                user_condition(columns)
+            ))
+            .bind(user_id)
+            .fetch_one(&self.pool)
+            .await?;
+            data.insert(table.to_string(), rows);
+        }
+
+        Ok(data)
+    }
+
+    async fn forget_user(&self, user_id: i64) -> anyhow::Result<()> {
+        let mut tx = self.pool.begin().await?;
+        for (table, columns) in USER_TABLES {
+            sqlx::query(&format!(r#"DELETE FROM "{table}" WHERE {}"#, user_condition(columns)))
+                .bind(user_id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn flush(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{bot::conformance, service::user_state::check_user_tables, test_db::setup_sqlite};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_user_state() -> anyhow::Result<()> {
+        conformance::user_state(&SqliteUserService::new(setup_sqlite().await)).await
+    }
+
+    #[tokio::test]
+    async fn test_answer_stats() -> anyhow::Result<()> {
+        conformance::answer_stats(&SqliteUserService::new(setup_sqlite().await)).await
+    }
+
+    #[tokio::test]
+    async fn test_xp_leaderboard() -> anyhow::Result<()> {
+        conformance::xp_leaderboard(&SqliteUserService::new(setup_sqlite().await)).await
+    }
+
+    #[tokio::test]
+    async fn test_task_stats() -> anyhow::Result<()> {
+        conformance::task_stats(&SqliteUserService::new(setup_sqlite().await)).await
+    }
+
+    #[tokio::test]
+    async fn test_multi_gap_answer_stat() -> anyhow::Result<()> {
+        conformance::multi_gap_answer_stat(&SqliteUserService::new(setup_sqlite().await)).await
+    }
+
+    #[tokio::test]
+    async fn test_task_queue() -> anyhow::Result<()> {
+        conformance::task_queue(&SqliteUserService::new(setup_sqlite().await)).await
+    }
+
+    #[tokio::test]
+    async fn test_profile_changes_and_forget_user() -> anyhow::Result<()> {
+        let service = SqliteUserService::new(setup_sqlite().await);
+        let user = UserInfo::new(1, Some("first"), "First");
+        assert!(service.touch_user(&user).await?);
+        assert!(
+            !service
+                .touch_user(&UserInfo {
+                    is_premium: true,
+                    ..user
+                })
+                .await?
+        );
+        assert!(service.find_user("FIRST").await?.unwrap().is_premium);
+        let changes = service.get_profile_changes(1, 10).await?;
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].new_value.as_deref(), Some("true"));
+
+        service.touch_user(&UserInfo::new(2, None, "Second")).await?;
+        service.add_friend(1, 2).await?;
+        service.update_tasks(ChatId(1), &[10]).await?;
+        service.update_group_scores(ChatId(-100), &[(1, true), (2, false)]).await?;
+        service.update_group_scores(ChatId(-100), &[(1, true)]).await?;
+        let scores = service.get_group_scoreboard(ChatId(-100), 10).await?;
+        assert_eq!((scores[0].uid, scores[0].answered, scores[0].correct), (1, 2, 2));
+
+        let data = service.export_user_data(1).await?;
+        assert_eq!(data["user_info"][0]["full_name"], "First");
+        assert_eq!(data["user_task"][0]["task_id"], 10);
+        assert_eq!(data["user_friend"].as_array().map(Vec::len), Some(2));
+
+        service.forget_user(1).await?;
+        for (table, rows) in service.export_user_data(1).await? {
+            assert_eq!(rows, serde_json::json!([]), "{table}");
+        }
+        assert!(service.find_user("second").await?.is_none());
+        assert_eq!(service.get_group_scoreboard(ChatId(-100), 10).await?.len(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_stats_broadcasts_and_reports() -> anyhow::Result<()> {
+        let service = SqliteUserService::new(setup_sqlite().await);
+        let day = Duration::from_secs(24 * 60 * 60);
+        conformance::answer_stats(&service).await?;
+        service.set_public_profile(2, true).await?;
+
+        let leaderboard = service.get_leaderboard(day, None, 10).await?;
+        assert_eq!(leaderboard.iter().map(|entry| entry.uid).collect::<Vec<_>>(), vec![2]);
+        let leaderboard = service.get_leaderboard(day, Some(&[1, 2]), 10).await?;
+        assert_eq!((leaderboard[1].uid, leaderboard[1].answered), (1, 2));
+        assert_eq!(service.get_answer_days(1, 10).await?.len(), 2);
+
+        let stats = service.get_bot_stats(day * 7).await?;
+        assert_eq!((stats.total_users, stats.new_users), (2, 2));
+        assert_eq!(stats.daily.iter().map(|stat| stat.answered).sum::<i64>(), 4);
+
+        let id = service.create_broadcast(1, "Hello").await?;
+        service.record_blocked_chat(ChatId(2)).await?;
+        assert_eq!(service.get_broadcast_chats(None, 10).await?, vec![ChatId(1)]);
+        assert_eq!(service.count_broadcast_chats().await?, 1);
+        service.record_broadcast_delivery(id, ChatId(1), false).await?;
+        let broadcast = service.get_broadcast(id).await?.unwrap();
+        assert_eq!((broadcast.sent, broadcast.failed), (0, 1));
+
+        let reason = TaskReportReason::Typo;
+        assert!(service.record_task_report(1, 10, reason).await?.is_some());
+        assert!(service.record_task_report(1, 10, reason).await?.is_none());
+        assert_eq!(service.get_reported_tasks(10).await?, vec![(10, 1)]);
+        service.resolve_task_reports(10).await?;
+        assert!(service.record_task_report(1, 10, reason).await?.is_some());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_user_tables_match_schema() -> anyhow::Result<()> {
+        let service = SqliteUserService::new(setup_sqlite().await);
+        let schema: Vec<(String, String)> = sqlx::query_as(indoc::indoc! {"
+                SELECT m.name, c.name
+                FROM sqlite_master m, pragma_table_info(m.name) c
+                WHERE m.type = 'table'
+                  AND m.name NOT LIKE '\\_sqlx%' ESCAPE '\\'
+            "})
+        .fetch_all(&service.pool)
+        .await?;
+        check_user_tables(&schema);
+
+        Ok(())
+    }
+}
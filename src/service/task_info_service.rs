@@ -5,10 +5,10 @@ use sqlx::{types::Json, PgPool};
 
 use crate::{
     bot::{
-        bot_filter::{match_task, Filter, FilterInfo},
+        bot_filter::{Filter, FilterInfo},
         bot_services::TaskInfoService,
     },
-    model::{Task, TaskId},
+    model::{FilterValue, Task, TaskId},
 };
 
 #[derive(Clone, Debug)]
@@ -30,51 +30,6 @@ impl PgTaskInfoService {
     pub fn new(pool: PgPool) -> Self {
         Self { pool }
     }
-
-    pub async fn update_tasks(&self, tasks: &[Task]) -> anyhow::Result<(u64, u64)> {
-        let mut tx = self.pool.begin().await?;
-
-        let mut ids = Vec::new();
-        for task in tasks {
-            let filters: HashMap<String, String> = task
-                .filters
-                .iter()
-                .map(|filter| (filter.name.clone(), filter.value.clone()))
-                .collect();
-            let filters = Json(filters);
-            let task_data = Json(task.clone());
-            let (id,): (i64,) = sqlx::query_as(indoc! {"
-                    INSERT INTO task_info (hash, filters, active, task_data)
-                    VALUES ($1, $2, true, $3)
-                    ON CONFLICT (hash) DO UPDATE
-                    SET filters = $2, active = true, task_data = $3
-                    RETURNING id
-                "})
-            .bind(task.hash)
-            .bind(filters)
-            .bind(task_data)
-            .fetch_one(&mut *tx)
-            .await?;
-
-            ids.push(id);
-        }
-        log::info!("Inserted {} tasks", ids.len());
-        let inserted_count = ids.len();
-
-        let result = sqlx::query(indoc! {"
-                UPDATE task_info
-                SET active = false
-                WHERE id != all($1)
-            "})
-        .bind(ids)
-        .execute(&mut *tx)
-        .await?;
-
-        log::info!("Deactivated {} tasks", result.rows_affected());
-
-        tx.commit().await?;
-        Ok((inserted_count as u64, result.rows_affected()))
-    }
 }
 
 #[derive(Debug, sqlx::FromRow)]
@@ -88,13 +43,14 @@ impl TaskInfoService for PgTaskInfoService {
         let task_ids = sqlx::query_as::<_, TaskInfo>(indoc! {"
                 SELECT id, hash, filters, task_data
                 FROM task_info
-                WHERE active = true
+                WHERE active = true AND disabled_at IS NULL
+                ORDER BY id
             "})
         .fetch_all(&self.pool)
         .await?
         .into_iter()
         // very inefficient, but will replace this later with other mechanism
-        .filter(|task| filter.map(|f| match_task(&task.task_data.filters, f)).unwrap_or(true))
+        .filter(|task| filter.map(|f| f.matches(&task.task_data)).unwrap_or(true))
         .map(|task| task.id)
         .collect::<Vec<_>>();
         Ok(task_ids)
@@ -106,7 +62,7 @@ impl TaskInfoService for PgTaskInfoService {
                 from (
                     SELECT jsonb_each_text(filters) as r
                     FROM task_info 
-                    WHERE active = true
+                    WHERE active = true AND disabled_at IS NULL
                 ) as r
                 group by 1
                 order by 1
@@ -124,9 +80,27 @@ impl TaskInfoService for PgTaskInfoService {
         Ok(result)
     }
 
+    async fn sample_task_ids(&self, per_value: usize) -> anyhow::Result<Vec<TaskId>> {
+        let rows: Vec<(i64,)> = sqlx::query_as(indoc! {"
+                SELECT DISTINCT id
+                FROM (
+                    SELECT id, row_number() OVER (PARTITION BY f.key, f.value ORDER BY random()) AS position
+                    FROM task_info, jsonb_each_text(filters) f
+                    WHERE active = true AND disabled_at IS NULL
+                ) sampled
+                WHERE position <= $1
+                ORDER BY id
+            "})
+        .bind(per_value as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|(id,)| id).collect())
+    }
+
     async fn get_task(&self, id: i64) -> anyhow::Result<Option<Task>> {
-        let task: Option<(Json<Task>,)> = sqlx::query_as(indoc! {"
-                SELECT task_data
+        let task: Option<(i64, Json<Task>)> = sqlx::query_as(indoc! {"
+                SELECT id, task_data
                 FROM task_info
                 WHERE id = $1
             "})
@@ -134,7 +108,127 @@ impl TaskInfoService for PgTaskInfoService {
         .fetch_optional(&self.pool)
         .await?;
 
-        Ok(task.map(|(json,)| json.0))
+        // Stored task data keeps the id of the source file
+        Ok(task.map(|(id, json)| Task { id, ..json.0 }))
+    }
+
+    async fn get_tasks(&self, ids: &[TaskId]) -> anyhow::Result<Vec<Task>> {
+        let tasks: Vec<(i64, Json<Task>)> = sqlx::query_as(indoc! {"
+                SELECT id, task_data
+                FROM task_info
+                WHERE id = any($1)
+            "})
+        .bind(ids)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(tasks.into_iter().map(|(id, json)| Task { id, ..json.0 }).collect())
+    }
+
+    async fn get_task_filters(&self, ids: &[TaskId]) -> anyhow::Result<HashMap<TaskId, Vec<FilterValue>>> {
+        let rows: Vec<(i64, Json<HashMap<String, String>>)> = sqlx::query_as(indoc! {"
+                SELECT id, filters
+                FROM task_info
+                WHERE id = any($1)
+            "})
+        .bind(ids)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(id, filters)| {
+                let filters = filters.0.into_iter().map(|(name, value)| FilterValue { name, value });
+                (id, filters.collect())
+            })
+            .collect())
+    }
+
+    async fn update_tasks(&self, tasks: &[Task]) -> anyhow::Result<(u64, u64)> {
+        let mut tx = self.pool.begin().await?;
+
+        let mut ids = Vec::new();
+        for task in tasks {
+            let filters: HashMap<String, String> = task
+                .filters
+                .iter()
+                .map(|filter| (filter.name.clone(), filter.value.clone()))
+                .collect();
+            let filters = Json(filters);
+            let task_data = Json(task.clone());
+            let (id,): (i64,) = sqlx::query_as(indoc! {"
+                    INSERT INTO task_info (hash, filters, active, task_data)
+                    VALUES ($1, $2, true, $3)
+                    ON CONFLICT (hash) DO UPDATE
+                    SET filters = $2, active = true, task_data = $3
+                    RETURNING id
+                "})
+            .bind(task.hash)
+            .bind(filters)
+            .bind(task_data)
+            .fetch_one(&mut *tx)
+            .await?;
+
+            ids.push(id);
+        }
+        log::info!("Inserted {} tasks", ids.len());
+        let inserted_count = ids.len();
+
+        let result = sqlx::query(indoc! {"
+                UPDATE task_info
+                SET active = false
+                WHERE id != all($1)
+            "})
+        .bind(ids)
+        .execute(&mut *tx)
+        .await?;
+
+        log::info!("Deactivated {} tasks", result.rows_affected());
+
+        tx.commit().await?;
+        Ok((inserted_count as u64, result.rows_affected()))
+    }
+
+    async fn deactivate_task(&self, id: TaskId, disabled_by: i64) -> anyhow::Result<bool> {
+        let result = sqlx::query(indoc! {"
+                UPDATE task_info
+                SET disabled_at = now(), disabled_by = $2
+                WHERE id = $1 AND disabled_at IS NULL
+            "})
+        .bind(id)
+        .bind(disabled_by)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn find_task_by_hash(&self, hash: i64) -> anyhow::Result<Option<TaskId>> {
+        let row: Option<(i64,)> = sqlx::query_as("SELECT id FROM task_info WHERE hash = $1")
+            .bind(hash)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(row.map(|(id,)| id))
+    }
+
+    async fn annotate_task(&self, id: TaskId, annotation: &str) -> anyhow::Result<bool> {
+        let result = sqlx::query("UPDATE task_info SET annotation = $2 WHERE id = $1")
+            .bind(id)
+            .bind(annotation)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_task_annotation(&self, id: TaskId) -> anyhow::Result<Option<String>> {
+        let row: Option<(Option<String>,)> = sqlx::query_as("SELECT annotation FROM task_info WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(row.and_then(|(annotation,)| annotation))
     }
 }
 
@@ -144,11 +238,17 @@ mod test {
     use anyhow::Result;
 
     use crate::{
-        bot::bot_filter::FilterGroup,
+        bot::{bot_filter::FilterGroup, conformance},
         model::{FilterValue, Task},
         test_db::setup_db,
     };
 
+    #[tokio::test]
+    async fn test_conformance() -> Result<()> {
+        let pg = setup_db().await;
+        conformance::task_info(&super::PgTaskInfoService::new(pg.pool.clone())).await
+    }
+
     #[tokio::test]
     async fn test_update_tasks() -> Result<()> {
         let pg = setup_db().await;
@@ -166,6 +266,7 @@ mod test {
                     hints: Vec::new(),
                     filters: Vec::new(),
                     wrong_answers: Vec::new(),
+                    gaps: Vec::new(),
                 },
                 Task {
                     id: 0,
@@ -178,6 +279,7 @@ mod test {
                     hints: Vec::new(),
                     filters: Vec::new(),
                     wrong_answers: Vec::new(),
+                    gaps: Vec::new(),
                 },
             ])
             .await?;
@@ -200,6 +302,7 @@ mod test {
                     hints: Vec::new(),
                     filters: Vec::new(),
                     wrong_answers: Vec::new(),
+                    gaps: Vec::new(),
                 },
                 Task {
                     id: 0,
@@ -212,6 +315,7 @@ mod test {
                     hints: Vec::new(),
                     filters: Vec::new(),
                     wrong_answers: Vec::new(),
+                    gaps: Vec::new(),
                 },
             ])
             .await?;
@@ -241,6 +345,7 @@ mod test {
                     hints: Vec::new(),
                     filters: Vec::new(),
                     wrong_answers: Vec::new(),
+                    gaps: Vec::new(),
                 },
                 Task {
                     id: 0,
@@ -253,6 +358,7 @@ mod test {
                     hints: Vec::new(),
                     filters: Vec::new(),
                     wrong_answers: Vec::new(),
+                    gaps: Vec::new(),
                 },
             ])
             .await?;
@@ -295,6 +401,7 @@ mod test {
                         },
                     ],
                     wrong_answers: Vec::new(),
+                    gaps: Vec::new(),
                 },
                 Task {
                     id: 0,
@@ -316,6 +423,7 @@ mod test {
                         },
                     ],
                     wrong_answers: Vec::new(),
+                    gaps: Vec::new(),
                 },
             ])
             .await?;
@@ -369,6 +477,7 @@ mod test {
                         },
                     ],
                     wrong_answers: Vec::new(),
+                    gaps: Vec::new(),
                 },
                 Task {
                     id: 0,
@@ -390,6 +499,7 @@ mod test {
                         },
                     ],
                     wrong_answers: Vec::new(),
+                    gaps: Vec::new(),
                 },
             ])
             .await?;
@@ -403,6 +513,7 @@ mod test {
                 groups: vec![FilterGroup {
                     values: vec!["value2".into(), "value3".into()],
                 }],
+                single_gap: false,
             }))
             .await?;
         assert_eq!(task_ids, vec![1, 2]);
@@ -417,10 +528,86 @@ mod test {
                         values: vec!["value2".into(), "value3".into()],
                     },
                 ],
+                single_gap: false,
             }))
             .await?;
         assert_eq!(task_ids, vec![1]);
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_deactivate_and_annotate_task() -> Result<()> {
+        let pg = setup_db().await;
+        let service = super::PgTaskInfoService::new(pg.pool.clone());
+        service
+            .update_tasks(&[
+                Task {
+                    id: 0,
+                    hash: 10,
+                    task: "task1".into(),
+                    masked_task: "task1".into(),
+                    correct: "correct1".into(),
+                    base: "base1".into(),
+                    info: Vec::new(),
+                    hints: Vec::new(),
+                    filters: Vec::new(),
+                    wrong_answers: Vec::new(),
+                    gaps: Vec::new(),
+                },
+                Task {
+                    id: 0,
+                    hash: 20,
+                    task: "task2".into(),
+                    masked_task: "task2".into(),
+                    correct: "correct2".into(),
+                    base: "base2".into(),
+                    info: Vec::new(),
+                    hints: Vec::new(),
+                    filters: Vec::new(),
+                    wrong_answers: Vec::new(),
+                    gaps: Vec::new(),
+                },
+                // Its hash is the id of the first task
+                Task {
+                    id: 0,
+                    hash: 1,
+                    task: "task3".into(),
+                    masked_task: "task3".into(),
+                    correct: "correct3".into(),
+                    base: "base3".into(),
+                    info: Vec::new(),
+                    hints: Vec::new(),
+                    filters: Vec::new(),
+                    wrong_answers: Vec::new(),
+                    gaps: Vec::new(),
+                },
+            ])
+            .await?;
+
+        assert!(service.deactivate_task(1, 1).await?);
+        assert_eq!(service.get_task_ids(None).await?, vec![2, 3]);
+        assert_eq!(service.find_task_by_hash(20).await?, Some(2));
+        assert!(service.deactivate_task(2, 1).await?);
+        assert!(!service.deactivate_task(1, 1).await?);
+        assert!(!service.deactivate_task(30, 1).await?);
+        assert!(service.deactivate_task(3, 1).await?);
+
+        assert!(service.get_task_ids(None).await?.is_empty());
+        // Deactivated tasks still can be answered from the queues
+        assert!(service.get_task(1).await?.is_some());
+        let tasks = service.get_tasks(&[2, 1, 4]).await?;
+        assert_eq!(tasks.len(), 2);
+        assert!(tasks.iter().any(|task| task.id == 2 && task.task == "task2"));
+
+        assert_eq!(service.get_task_annotation(1).await?, None);
+        assert!(service.annotate_task(1, "Checked, the answer is fine").await?);
+        assert!(!service.annotate_task(4, "Unknown task").await?);
+        assert_eq!(
+            service.get_task_annotation(1).await?.as_deref(),
+            Some("Checked, the answer is fine")
+        );
+
+        Ok(())
+    }
 }
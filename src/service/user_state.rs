@@ -1,9 +1,15 @@
 use crate::{
-    bot::bot_services::{Answer, AnswerStat, UserData, UserInfo, UserStateService},
+    bot::bot_services::{
+        Answer, AnswerStat, BlitzResult, BotStats, Broadcast, BroadcastStatus, DailyStat, FeedbackMessage, GroupScore,
+        LeaderboardEntry, ProfileChange, QueuedTask, TaskAnswerCount, TaskQueue, TaskReportReason, TaskStats, UserData,
+        UserInfo, UserStateService, XpLeaderboardEntry,
+    },
     model::TaskId,
 };
-use sqlx::{postgres::types::PgInterval, PgPool};
-use teloxide::types::ChatId;
+use sqlx::{postgres::types::PgInterval, types::Json, PgPool};
+use std::collections::BTreeMap;
+use teloxide::types::{ChatId, MessageId};
+use time::{Date, OffsetDateTime};
 
 #[derive(Debug)]
 pub struct PgUserService {
@@ -16,17 +22,119 @@ impl PgUserService {
     }
 }
 
+#[derive(Debug, sqlx::FromRow)]
+pub(super) struct UserInfoRow {
+    uid: i64,
+    username: Option<String>,
+    full_name: String,
+    language_code: Option<String>,
+    is_premium: bool,
+    created_at: OffsetDateTime,
+    last_active_at: OffsetDateTime,
+}
+
+impl From<UserInfoRow> for UserInfo {
+    fn from(row: UserInfoRow) -> Self {
+        Self {
+            uid: row.uid,
+            username: row.username,
+            full_name: row.full_name,
+            language_code: row.language_code,
+            is_premium: row.is_premium,
+            created_at: row.created_at,
+            last_active_at: row.last_active_at,
+        }
+    }
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub(super) struct AnswerRow {
+    uid: i64,
+    task_id: i64,
+    gap: i32,
+    answer_index: Option<i32>,
+    answer_text: Option<String>,
+    correct: Option<bool>,
+    asked_at: OffsetDateTime,
+    answered_at: OffsetDateTime,
+}
+
+impl From<AnswerRow> for Answer {
+    fn from(row: AnswerRow) -> Self {
+        Answer {
+            uid: row.uid,
+            task_id: row.task_id,
+            gap: row.gap,
+            answer_index: row.answer_index.unwrap_or_default(),
+            answer_text: row.answer_text,
+            correct: row.correct.unwrap_or_default(),
+            asked_at: row.asked_at,
+            answered_at: row.answered_at,
+        }
+    }
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub(super) struct BroadcastRow {
+    id: i64,
+    author_uid: i64,
+    text: String,
+    status: String,
+    last_chat_id: Option<i64>,
+    sent: i64,
+    failed: i64,
+}
+
+impl TryFrom<BroadcastRow> for Broadcast {
+    type Error = anyhow::Error;
+
+    fn try_from(row: BroadcastRow) -> anyhow::Result<Self> {
+        Ok(Broadcast {
+            id: row.id,
+            author_uid: row.author_uid,
+            text: row.text,
+            status: BroadcastStatus::parse(&row.status)?,
+            last_chat_id: row.last_chat_id,
+            sent: row.sent,
+            failed: row.failed,
+        })
+    }
+}
+
 impl UserStateService for PgUserService {
     async fn touch_user(&self, user: &UserInfo) -> anyhow::Result<bool> {
+        // All parts see the row before the upsert, so changes are compared with the old values
         let row: Option<(bool,)> = sqlx::query_as(indoc::indoc! {"
-                INSERT INTO user_info (uid, username, full_name, created_at, last_active_at)
-                VALUES ($1, $2, $3, now(), now())
-                ON CONFLICT (uid) DO UPDATE SET last_active_at = now()
-                RETURNING last_active_at = created_at
+                WITH old AS (
+                    SELECT username, full_name, language_code, is_premium FROM user_info WHERE uid = $1
+                ), upsert AS (
+                    INSERT INTO user_info (uid, username, full_name, language_code, is_premium, created_at, last_active_at)
+                    VALUES ($1, $2, $3, $4, $5, now(), now())
+                    ON CONFLICT (uid) DO UPDATE SET
+                        last_active_at = now(),
+                        username = excluded.username,
+                        full_name = excluded.full_name,
+                        language_code = excluded.language_code,
+                        is_premium = excluded.is_premium
+                    RETURNING last_active_at = created_at
+                ), changes AS (
+                    INSERT INTO user_profile_change (uid, field, old_value, new_value, changed_at)
+                    SELECT $1, field, old_value, new_value, now()
+                    FROM old, LATERAL (VALUES
+                        ('username', old.username, $2),
+                        ('full_name', old.full_name, $3),
+                        ('language_code', old.language_code, $4),
+                        ('is_premium', old.is_premium::text, $5::text)
+                    ) AS change (field, old_value, new_value)
+                    WHERE old_value IS DISTINCT FROM new_value
+                )
+                SELECT * FROM upsert
             "})
         .bind(user.uid)
         .bind(user.username.as_deref())
         .bind(user.full_name.as_str())
+        .bind(user.language_code.as_deref())
+        .bind(user.is_premium)
         .fetch_optional(&self.pool)
         .await?;
 
@@ -35,6 +143,34 @@ impl UserStateService for PgUserService {
         Ok(new_user)
     }
 
+    async fn get_profile_changes(&self, user_id: i64, limit: usize) -> anyhow::Result<Vec<ProfileChange>> {
+        let rows: Vec<(String, Option<String>, Option<String>, OffsetDateTime)> = sqlx::query_as(indoc::indoc! {"
+                SELECT field, old_value, new_value, changed_at
+                FROM (
+                    SELECT id, field, old_value, new_value, changed_at
+                    FROM user_profile_change
+                    WHERE uid = $1
+                    ORDER BY id DESC
+                    LIMIT $2
+                ) last_changes
+                ORDER BY id
+            "})
+        .bind(user_id)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(field, old_value, new_value, changed_at)| ProfileChange {
+                field,
+                old_value,
+                new_value,
+                changed_at,
+            })
+            .collect())
+    }
+
     async fn get_state(&self, chat_id: ChatId) -> anyhow::Result<UserData> {
         let row: Option<(Option<String>,)> = sqlx::query_as(indoc::indoc! {"
                 SELECT filter
@@ -71,13 +207,17 @@ impl UserStateService for PgUserService {
             .execute(&mut *tx)
             .await?;
 
-        for task_id in tasks {
-            sqlx::query("INSERT INTO user_task (chat_id, task_id) VALUES ($1, $2)")
-                .bind(chat_id.0)
-                .bind(task_id)
-                .execute(&mut *tx)
-                .await?;
-        }
+        // Ids are assigned in the sorted order, so the queue keeps the given order
+        sqlx::query(indoc::indoc! {"
+                INSERT INTO user_task (chat_id, task_id)
+                SELECT $1, task_id
+                FROM unnest($2::bigint[]) WITH ORDINALITY AS queue (task_id, position)
+                ORDER BY position
+            "})
+        .bind(chat_id.0)
+        .bind(tasks)
+        .execute(&mut *tx)
+        .await?;
 
         tx.commit().await?;
 
@@ -85,14 +225,16 @@ impl UserStateService for PgUserService {
     }
 
     async fn take_next_task(&self, chat_id: ChatId) -> anyhow::Result<Option<TaskId>> {
+        // Rows are deleted by id, so duplicates of the task stay, concurrent takes get different rows
         let row: Option<(i64,)> = sqlx::query_as(indoc::indoc! {"
                 DELETE FROM user_task
-                WHERE chat_id = $1 and task_id = (
-                    SELECT task_id
+                WHERE id = (
+                    SELECT id
                     FROM user_task
                     WHERE chat_id = $1
                     ORDER BY id
                     LIMIT 1
+                    FOR UPDATE SKIP LOCKED
                 )
                 RETURNING task_id
             "})
@@ -103,13 +245,37 @@ impl UserStateService for PgUserService {
         Ok(row.map(|(task_id,)| task_id))
     }
 
+    async fn peek_tasks(&self, chat_id: ChatId, limit: usize) -> anyhow::Result<TaskQueue> {
+        let (remaining,): (i64,) = sqlx::query_as("SELECT count(*) FROM user_task WHERE chat_id = $1")
+            .bind(chat_id.0)
+            .fetch_one(&self.pool)
+            .await?;
+        let rows: Vec<(i64,)> = sqlx::query_as("SELECT task_id FROM user_task WHERE chat_id = $1 ORDER BY id LIMIT $2")
+            .bind(chat_id.0)
+            .bind(limit as i64)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(TaskQueue {
+            remaining: remaining as usize,
+            next: rows
+                .into_iter()
+                .enumerate()
+                .map(|(position, (task_id,))| QueuedTask { position, task_id })
+                .collect(),
+        })
+    }
+
     async fn record_anwer(&self, answer: Answer) -> anyhow::Result<()> {
         sqlx::query(indoc::indoc! {"
-                INSERT INTO user_answer (uid, task_id, correct, asked_at, answered_at)
-                VALUES ($1, $2, $3, $4, $5)
+                INSERT INTO user_answer (uid, task_id, gap, answer_index, answer_text, correct, asked_at, answered_at)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
             "})
         .bind(answer.uid)
         .bind(answer.task_id)
+        .bind(answer.gap)
+        .bind(answer.answer_index)
+        .bind(answer.answer_text)
         .bind(answer.correct)
         .bind(answer.asked_at)
         .bind(answer.answered_at)
@@ -119,14 +285,87 @@ impl UserStateService for PgUserService {
         Ok(())
     }
 
+    async fn get_answers(&self, period: std::time::Duration) -> anyhow::Result<Vec<Answer>> {
+        let interval = PgInterval::try_from(period)
+            .map_err(|e| anyhow::format_err!("Failed to convert duration to interval: {}", e))?;
+
+        let rows: Vec<AnswerRow> = sqlx::query_as(indoc::indoc! {"
+                SELECT uid, task_id, gap, answer_index, answer_text, correct, asked_at, answered_at
+                FROM user_answer
+                WHERE answered_at > now() - $1
+                ORDER BY answered_at, id
+            "})
+        .bind(interval)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(Answer::from).collect())
+    }
+
+    async fn get_task_stats(&self, period: std::time::Duration) -> anyhow::Result<Vec<TaskStats>> {
+        let interval = PgInterval::try_from(period)
+            .map_err(|e| anyhow::format_err!("Failed to convert duration to interval: {}", e))?;
+
+        // Later gaps share the time the task was asked, only first gaps have the response time
+        let rows: Vec<(TaskId, i64, i64, f64)> = sqlx::query_as(indoc::indoc! {"
+                SELECT task_id, count(*), coalesce(sum(correct::int), 0),
+                    coalesce(
+                        percentile_cont(0.5) WITHIN GROUP (
+                            ORDER BY greatest(extract(epoch FROM answered_at - asked_at)::float8, 0)
+                        ) FILTER (WHERE gap = 0),
+                        0
+                    )
+                FROM user_answer
+                WHERE answered_at > now() - $1
+                GROUP BY task_id
+                ORDER BY task_id
+            "})
+        .bind(interval.clone())
+        .fetch_all(&self.pool)
+        .await?;
+
+        // Answers recorded before the chosen text was stored are not counted
+        let distractors: Vec<(TaskId, String, i64)> = sqlx::query_as(indoc::indoc! {"
+                SELECT task_id, answer_text, count(*)
+                FROM user_answer
+                WHERE answered_at > now() - $1 AND NOT correct AND answer_text IS NOT NULL
+                GROUP BY task_id, answer_text
+                ORDER BY task_id, count(*) DESC, answer_text
+            "})
+        .bind(interval)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut stats = rows
+            .into_iter()
+            .map(|(task_id, answered, correct, median_secs)| TaskStats {
+                task_id,
+                answered,
+                correct,
+                median_response: std::time::Duration::from_millis((median_secs * 1000.0).round() as u64),
+                distractors: Vec::new(),
+            })
+            .collect::<Vec<_>>();
+        for (task_id, text, count) in distractors {
+            if let Ok(index) = stats.binary_search_by_key(&task_id, |task_stats| task_stats.task_id) {
+                stats[index].distractors.push((text, count));
+            }
+        }
+        Ok(stats)
+    }
+
     async fn get_answer_stat(&self, user_id: i64, period: std::time::Duration) -> anyhow::Result<AnswerStat> {
         let interval = PgInterval::try_from(period)
             .map_err(|e| anyhow::format_err!("Failed to convert duration to interval: {}", e))?;
 
         let row: Option<(i64, i64)> = sqlx::query_as(indoc::indoc! {"
                 SELECT count(*) as count, coalesce(sum(correct::int), 0) as correct
-                FROM user_answer
-                WHERE uid = $1 AND answered_at > now() - $2
+                FROM (
+                    SELECT bool_and(correct) AS correct
+                    FROM user_answer
+                    WHERE uid = $1 AND answered_at > now() - $2
+                    GROUP BY task_id, asked_at
+                ) tasks
             "})
         .bind(user_id)
         .bind(interval)
@@ -137,114 +376,1451 @@ impl UserStateService for PgUserService {
 
         Ok(AnswerStat { count, correct })
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use crate::test_db::setup_db;
-    use anyhow::Result;
-    use time::OffsetDateTime;
+    async fn get_task_answer_counts(&self, user_id: i64) -> anyhow::Result<Vec<TaskAnswerCount>> {
+        let rows: Vec<(i64, i64, i64)> = sqlx::query_as(indoc::indoc! {"
+                SELECT task_id, count(*), coalesce(sum(correct::int), 0)
+                FROM user_answer
+                WHERE uid = $1
+                GROUP BY task_id
+                ORDER BY task_id
+            "})
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
 
-    #[tokio::test]
-    async fn test_touch_user() -> Result<()> {
-        let pg = setup_db().await;
-        let service = PgUserService { pool: pg.pool };
-        let user = UserInfo::new(1, Some("username"), "full_name");
+        Ok(rows
+            .into_iter()
+            .map(|(task_id, answered, correct)| TaskAnswerCount {
+                task_id,
+                answered,
+                correct,
+            })
+            .collect())
+    }
 
-        let new_user = service.touch_user(&user).await?;
-        assert!(new_user);
+    async fn get_global_task_answer_counts(&self) -> anyhow::Result<Vec<TaskAnswerCount>> {
+        let rows: Vec<(i64, i64, i64)> = sqlx::query_as(indoc::indoc! {"
+                SELECT task_id, count(*), coalesce(sum(correct::int), 0)
+                FROM user_answer
+                GROUP BY task_id
+                ORDER BY task_id
+            "})
+        .fetch_all(&self.pool)
+        .await?;
 
-        let new_user = service.touch_user(&user).await?;
-        assert!(!new_user);
+        Ok(rows
+            .into_iter()
+            .map(|(task_id, answered, correct)| TaskAnswerCount {
+                task_id,
+                answered,
+                correct,
+            })
+            .collect())
+    }
 
-        Ok(())
+    async fn get_task_answer_count(&self, task_id: TaskId) -> anyhow::Result<TaskAnswerCount> {
+        let (answered, correct): (i64, i64) = sqlx::query_as(indoc::indoc! {"
+                SELECT count(*), coalesce(sum(correct::int), 0)
+                FROM user_answer
+                WHERE task_id = $1
+            "})
+        .bind(task_id)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(TaskAnswerCount {
+            task_id,
+            answered,
+            correct,
+        })
     }
 
-    #[tokio::test]
-    async fn test_state() -> Result<()> {
-        let pg = setup_db().await;
-        let service = PgUserService { pool: pg.pool };
-        let chat_id = ChatId(1);
+    async fn count_answered_tasks(&self, user_id: i64) -> anyhow::Result<i64> {
+        let (count,): (i64,) = sqlx::query_as(indoc::indoc! {"
+                SELECT count(DISTINCT (task_id, asked_at))
+                FROM user_answer
+                WHERE uid = $1
+            "})
+        .bind(user_id)
+        .fetch_one(&self.pool)
+        .await?;
 
-        let state = service.get_state(chat_id).await?;
-        assert_eq!(state.filter, None);
+        Ok(count)
+    }
 
-        let state = UserData::new(Some("filter".into()));
-        service.update_state(chat_id, state.clone()).await?;
+    async fn get_answer_days(&self, user_id: i64, limit: usize) -> anyhow::Result<Vec<Date>> {
+        let rows: Vec<(Date,)> = sqlx::query_as(indoc::indoc! {"
+                SELECT DISTINCT (answered_at AT TIME ZONE 'UTC')::date as day
+                FROM user_answer
+                WHERE uid = $1
+                ORDER BY day DESC
+                LIMIT $2
+            "})
+        .bind(user_id)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
 
-        let state = service.get_state(chat_id).await?;
-        assert_eq!(state.filter, Some("filter".into()));
+        Ok(rows.into_iter().map(|(day,)| day).collect())
+    }
 
-        let state = UserData::new(None);
-        service.update_state(chat_id, state.clone()).await?;
+    async fn add_xp(&self, user_id: i64, xp: i64) -> anyhow::Result<i64> {
+        let row: Option<(i64,)> = sqlx::query_as(indoc::indoc! {"
+                WITH award AS (
+                    INSERT INTO user_xp (uid, xp, earned_at)
+                    SELECT uid, $2, now() FROM user_info WHERE uid = $1
+                )
+                UPDATE user_info SET xp = xp + $2 WHERE uid = $1 RETURNING xp
+            "})
+        .bind(user_id)
+        .bind(xp)
+        .fetch_optional(&self.pool)
+        .await?;
 
-        let state = service.get_state(chat_id).await?;
-        assert_eq!(state.filter, None);
+        Ok(row.map(|(xp,)| xp).unwrap_or_default())
+    }
+
+    async fn get_xp(&self, user_id: i64) -> anyhow::Result<i64> {
+        let row: Option<(i64,)> = sqlx::query_as("SELECT xp FROM user_info WHERE uid = $1")
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(row.map(|(xp,)| xp).unwrap_or_default())
+    }
+
+    async fn award_achievement(&self, user_id: i64, achievement_id: &str) -> anyhow::Result<bool> {
+        let result = sqlx::query(indoc::indoc! {"
+                INSERT INTO user_achievement (uid, achievement_id, unlocked_at)
+                VALUES ($1, $2, now())
+                ON CONFLICT DO NOTHING
+            "})
+        .bind(user_id)
+        .bind(achievement_id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_achievements(&self, user_id: i64) -> anyhow::Result<Vec<(String, OffsetDateTime)>> {
+        Ok(sqlx::query_as(indoc::indoc! {"
+                SELECT achievement_id, unlocked_at
+                FROM user_achievement
+                WHERE uid = $1
+                ORDER BY unlocked_at, achievement_id
+            "})
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    async fn record_blitz_result(&self, result: BlitzResult) -> anyhow::Result<()> {
+        sqlx::query(indoc::indoc! {"
+                INSERT INTO blitz_result (uid, duration_secs, answered, correct, avg_response_ms, finished_at)
+                VALUES ($1, $2, $3, $4, $5, $6)
+            "})
+        .bind(result.uid)
+        .bind(result.duration_secs)
+        .bind(result.answered)
+        .bind(result.correct)
+        .bind(result.avg_response_ms)
+        .bind(result.finished_at)
+        .execute(&self.pool)
+        .await?;
 
         Ok(())
     }
 
-    #[tokio::test]
-    async fn test_tasks() -> Result<()> {
-        let pg = setup_db().await;
-        let service = PgUserService { pool: pg.pool };
-        let chat_id = ChatId(1);
+    async fn get_best_blitz_result(&self, user_id: i64, duration_secs: i32) -> anyhow::Result<Option<BlitzResult>> {
+        let row: Option<(i64, i32, i64, i64, i64, OffsetDateTime)> = sqlx::query_as(indoc::indoc! {"
+                SELECT uid, duration_secs, answered, correct, avg_response_ms, finished_at
+                FROM blitz_result
+                WHERE uid = $1 AND duration_secs = $2
+                ORDER BY correct DESC, answered - correct, finished_at
+                LIMIT 1
+            "})
+        .bind(user_id)
+        .bind(duration_secs)
+        .fetch_optional(&self.pool)
+        .await?;
 
-        let tasks = vec![1, 2, 3];
-        service.update_tasks(chat_id, &tasks).await?;
+        Ok(row.map(
+            |(uid, duration_secs, answered, correct, avg_response_ms, finished_at)| BlitzResult {
+                uid,
+                duration_secs,
+                answered,
+                correct,
+                avg_response_ms,
+                finished_at,
+            },
+        ))
+    }
 
-        let task = service.take_next_task(chat_id).await?;
-        assert_eq!(task, Some(1));
+    async fn update_group_scores(&self, chat_id: ChatId, results: &[(i64, bool)]) -> anyhow::Result<()> {
+        let uids = results.iter().map(|(uid, _)| *uid).collect::<Vec<_>>();
+        let correct = results.iter().map(|(_, correct)| *correct).collect::<Vec<_>>();
 
-        let task = service.take_next_task(chat_id).await?;
-        assert_eq!(task, Some(2));
+        sqlx::query(indoc::indoc! {"
+                INSERT INTO group_score (chat_id, uid, answered, correct)
+                SELECT $1, uid, 1, correct::int
+                FROM unnest($2::bigint[], $3::boolean[]) AS r(uid, correct)
+                ON CONFLICT (chat_id, uid) DO UPDATE
+                SET answered = group_score.answered + excluded.answered,
+                    correct = group_score.correct + excluded.correct
+            "})
+        .bind(chat_id.0)
+        .bind(uids)
+        .bind(correct)
+        .execute(&self.pool)
+        .await?;
 
-        let task = service.take_next_task(chat_id).await?;
-        assert_eq!(task, Some(3));
+        Ok(())
+    }
 
-        let task = service.take_next_task(chat_id).await?;
-        assert_eq!(task, None);
+    async fn get_group_scoreboard(&self, chat_id: ChatId, limit: usize) -> anyhow::Result<Vec<GroupScore>> {
+        let rows: Vec<(i64, String, i64, i64)> = sqlx::query_as(indoc::indoc! {"
+                SELECT s.uid, u.full_name, s.answered, s.correct
+                FROM group_score s
+                JOIN user_info u ON u.uid = s.uid
+                WHERE s.chat_id = $1
+                ORDER BY s.correct DESC, s.answered, s.uid
+                LIMIT $2
+            "})
+        .bind(chat_id.0)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(uid, full_name, answered, correct)| GroupScore {
+                uid,
+                full_name,
+                answered,
+                correct,
+            })
+            .collect())
+    }
+
+    async fn set_public_profile(&self, user_id: i64, public: bool) -> anyhow::Result<()> {
+        sqlx::query("UPDATE user_info SET public_profile = $2 WHERE uid = $1")
+            .bind(user_id)
+            .bind(public)
+            .execute(&self.pool)
+            .await?;
 
         Ok(())
     }
 
-    #[tokio::test]
-    async fn test_answer_and_answer_stat() -> Result<()> {
-        let pg = setup_db().await;
-        let service = PgUserService { pool: pg.pool };
-        let user_id = 1;
+    async fn is_public_profile(&self, user_id: i64) -> anyhow::Result<bool> {
+        let row: Option<(bool,)> = sqlx::query_as("SELECT public_profile FROM user_info WHERE uid = $1")
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await?;
 
-        service.touch_user(&UserInfo::new(user_id, Some("test"), "test")).await?;
+        Ok(row.map(|(public,)| public).unwrap_or_default())
+    }
 
-        let stat = service.get_answer_stat(user_id, std::time::Duration::from_secs(1)).await?;
-        assert_eq!(stat.count, 0);
-        assert_eq!(stat.correct, 0);
+    async fn add_friend(&self, user_id: i64, friend_id: i64) -> anyhow::Result<bool> {
+        let result = sqlx::query(indoc::indoc! {"
+                INSERT INTO user_friend (uid, friend_uid, created_at)
+                SELECT u.uid, f.uid, now()
+                FROM user_info u, user_info f
+                WHERE (u.uid, f.uid) IN (($1, $2), ($2, $1))
+                ON CONFLICT DO NOTHING
+            "})
+        .bind(user_id)
+        .bind(friend_id)
+        .execute(&self.pool)
+        .await?;
 
-        let answer = Answer {
-            uid: user_id,
-            task_id: 1,
-            correct: true,
-            asked_at: OffsetDateTime::now_utc() - std::time::Duration::from_secs(15),
-            answered_at: OffsetDateTime::now_utc() - std::time::Duration::from_secs(15),
-        };
-        service.record_anwer(answer).await?;
-        let answer = Answer {
-            uid: user_id,
-            task_id: 1,
-            correct: false,
-            asked_at: OffsetDateTime::now_utc() - std::time::Duration::from_secs(15),
-            answered_at: OffsetDateTime::now_utc() - std::time::Duration::from_secs(15),
-        };
-        service.record_anwer(answer).await?;
+        Ok(result.rows_affected() > 0)
+    }
 
-        let stat = service.get_answer_stat(user_id, std::time::Duration::from_secs(60)).await?;
-        assert_eq!(stat.count, 2);
-        assert_eq!(stat.correct, 1);
+    async fn get_friends(&self, user_id: i64) -> anyhow::Result<Vec<i64>> {
+        let rows: Vec<(i64,)> = sqlx::query_as("SELECT friend_uid FROM user_friend WHERE uid = $1 ORDER BY friend_uid")
+            .bind(user_id)
+            .fetch_all(&self.pool)
+            .await?;
 
-        let stat = service.get_answer_stat(user_id, std::time::Duration::from_secs(0)).await?;
-        assert_eq!(stat.count, 0);
-        assert_eq!(stat.correct, 0);
+        Ok(rows.into_iter().map(|(uid,)| uid).collect())
+    }
+
+    async fn get_invite_token(&self, user_id: i64, new_token: &str) -> anyhow::Result<String> {
+        sqlx::query(indoc::indoc! {"
+                INSERT INTO friend_invite (token, uid, created_at)
+                VALUES ($2, $1, now())
+                ON CONFLICT (uid) DO NOTHING
+            "})
+        .bind(user_id)
+        .bind(new_token)
+        .execute(&self.pool)
+        .await?;
+
+        let (token,): (String,) = sqlx::query_as("SELECT token FROM friend_invite WHERE uid = $1")
+            .bind(user_id)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(token)
+    }
+
+    async fn find_invite_owner(&self, token: &str) -> anyhow::Result<Option<i64>> {
+        let row: Option<(i64,)> = sqlx::query_as("SELECT uid FROM friend_invite WHERE token = $1")
+            .bind(token)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row.map(|(uid,)| uid))
+    }
+
+    async fn get_unlocked_lessons(&self, user_id: i64) -> anyhow::Result<Vec<String>> {
+        let rows: Vec<(String,)> =
+            sqlx::query_as("SELECT lesson_id FROM user_lesson WHERE uid = $1 ORDER BY lesson_id")
+                .bind(user_id)
+                .fetch_all(&self.pool)
+                .await?;
+
+        Ok(rows.into_iter().map(|(lesson_id,)| lesson_id).collect())
+    }
+
+    async fn unlock_lesson(&self, user_id: i64, lesson_id: &str) -> anyhow::Result<bool> {
+        let result = sqlx::query(indoc::indoc! {"
+                INSERT INTO user_lesson (uid, lesson_id, unlocked_at)
+                VALUES ($1, $2, now())
+                ON CONFLICT DO NOTHING
+            "})
+        .bind(user_id)
+        .bind(lesson_id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_leaderboard(
+        &self,
+        period: std::time::Duration,
+        user_ids: Option<&[i64]>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<LeaderboardEntry>> {
+        let interval = PgInterval::try_from(period)
+            .map_err(|e| anyhow::format_err!("Failed to convert duration to interval: {}", e))?;
+
+        let rows: Vec<(i64, String, i64, i64)> = sqlx::query_as(indoc::indoc! {"
+                SELECT t.uid, u.full_name, count(*) as answered, coalesce(sum(t.correct::int), 0) as correct
+                FROM (
+                    SELECT uid, bool_and(correct) AS correct
+                    FROM user_answer
+                    WHERE answered_at > now() - $1
+                    GROUP BY uid, task_id, asked_at
+                ) t
+                JOIN user_info u ON u.uid = t.uid
+                WHERE ($2::bigint[] IS NULL AND u.public_profile) OR t.uid = any($2)
+                GROUP BY t.uid, u.full_name
+                ORDER BY correct DESC, answered, t.uid
+                LIMIT $3
+            "})
+        .bind(interval)
+        .bind(user_ids)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(uid, full_name, answered, correct)| LeaderboardEntry {
+                uid,
+                full_name,
+                answered,
+                correct,
+            })
+            .collect())
+    }
+
+    async fn get_xp_leaderboard(
+        &self,
+        period: std::time::Duration,
+        user_ids: Option<&[i64]>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<XpLeaderboardEntry>> {
+        let interval = PgInterval::try_from(period)
+            .map_err(|e| anyhow::format_err!("Failed to convert duration to interval: {}", e))?;
+
+        let rows: Vec<(i64, String, i64)> = sqlx::query_as(indoc::indoc! {"
+                SELECT e.uid, u.full_name, sum(e.xp)::bigint as xp
+                FROM user_xp e
+                JOIN user_info u ON u.uid = e.uid
+                WHERE e.earned_at > now() - $1
+                    AND (($2::bigint[] IS NULL AND u.public_profile) OR e.uid = any($2))
+                GROUP BY e.uid, u.full_name
+                HAVING sum(e.xp) > 0
+                ORDER BY xp DESC, e.uid
+                LIMIT $3
+            "})
+        .bind(interval)
+        .bind(user_ids)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(uid, full_name, xp)| XpLeaderboardEntry { uid, full_name, xp })
+            .collect())
+    }
+
+    async fn get_bot_stats(&self, period: std::time::Duration) -> anyhow::Result<BotStats> {
+        let interval = PgInterval::try_from(period)
+            .map_err(|e| anyhow::format_err!("Failed to convert duration to interval: {}", e))?;
+
+        let (total_users, new_users): (i64, i64) = sqlx::query_as(indoc::indoc! {"
+                SELECT count(*), count(*) FILTER (WHERE created_at > now() - $1)
+                FROM user_info
+            "})
+        .bind(interval.clone())
+        .fetch_one(&self.pool)
+        .await?;
+
+        let rows: Vec<(Date, i64, i64, i64)> = sqlx::query_as(indoc::indoc! {"
+                SELECT (answered_at AT TIME ZONE 'UTC')::date as day,
+                    count(distinct uid), count(*), coalesce(sum(correct::int), 0)
+                FROM user_answer
+                WHERE answered_at > now() - $1
+                GROUP BY 1
+                ORDER BY 1
+            "})
+        .bind(interval)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(BotStats {
+            total_users,
+            new_users,
+            daily: rows
+                .into_iter()
+                .map(|(day, active_users, answered, correct)| DailyStat {
+                    day,
+                    active_users,
+                    answered,
+                    correct,
+                })
+                .collect(),
+        })
+    }
+
+    async fn find_user(&self, username: &str) -> anyhow::Result<Option<UserInfo>> {
+        let row: Option<UserInfoRow> = sqlx::query_as(indoc::indoc! {"
+                SELECT uid, username, full_name, language_code, is_premium, created_at, last_active_at
+                FROM user_info
+                WHERE lower(username) = lower($1)
+            "})
+        .bind(username)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(UserInfo::from))
+    }
+
+    async fn create_broadcast(&self, author_uid: i64, text: &str) -> anyhow::Result<i64> {
+        let (id,): (i64,) = sqlx::query_as(indoc::indoc! {"
+                INSERT INTO broadcast (author_uid, text, status, created_at)
+                VALUES ($1, $2, $3, now())
+                RETURNING id
+            "})
+        .bind(author_uid)
+        .bind(text)
+        .bind(BroadcastStatus::Draft.as_str())
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(id)
+    }
+
+    async fn get_broadcast(&self, id: i64) -> anyhow::Result<Option<Broadcast>> {
+        let row: Option<BroadcastRow> = sqlx::query_as(indoc::indoc! {"
+                SELECT id, author_uid, text, status, last_chat_id, sent, failed
+                FROM broadcast
+                WHERE id = $1
+            "})
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        row.map(Broadcast::try_from).transpose()
+    }
+
+    async fn get_broadcasts(&self, status: BroadcastStatus) -> anyhow::Result<Vec<Broadcast>> {
+        let rows: Vec<BroadcastRow> = sqlx::query_as(indoc::indoc! {"
+                SELECT id, author_uid, text, status, last_chat_id, sent, failed
+                FROM broadcast
+                WHERE status = $1
+                ORDER BY id
+            "})
+        .bind(status.as_str())
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter().map(Broadcast::try_from).collect()
+    }
+
+    async fn update_broadcast_status(
+        &self,
+        id: i64,
+        from: BroadcastStatus,
+        to: BroadcastStatus,
+    ) -> anyhow::Result<bool> {
+        let result = sqlx::query("UPDATE broadcast SET status = $3 WHERE id = $1 AND status = $2")
+            .bind(id)
+            .bind(from.as_str())
+            .bind(to.as_str())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn get_broadcast_chats(&self, after_chat_id: Option<i64>, limit: usize) -> anyhow::Result<Vec<ChatId>> {
+        let rows: Vec<(i64,)> = sqlx::query_as(indoc::indoc! {"
+                SELECT c.chat_id
+                FROM (SELECT uid AS chat_id FROM user_info UNION SELECT chat_id FROM user_state) c
+                LEFT JOIN user_info u ON u.uid = c.chat_id
+                LEFT JOIN chat_blocked b ON b.chat_id = c.chat_id
+                WHERE ($1::bigint IS NULL OR c.chat_id > $1)
+                    AND (b.blocked_at IS NULL OR b.blocked_at < u.last_active_at)
+                ORDER BY c.chat_id
+                LIMIT $2
+            "})
+        .bind(after_chat_id)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|(chat_id,)| ChatId(chat_id)).collect())
+    }
+
+    async fn count_broadcast_chats(&self) -> anyhow::Result<i64> {
+        let (count,): (i64,) = sqlx::query_as(indoc::indoc! {"
+                SELECT count(*)
+                FROM (SELECT uid AS chat_id FROM user_info UNION SELECT chat_id FROM user_state) c
+                LEFT JOIN user_info u ON u.uid = c.chat_id
+                LEFT JOIN chat_blocked b ON b.chat_id = c.chat_id
+                WHERE b.blocked_at IS NULL OR b.blocked_at < u.last_active_at
+            "})
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(count)
+    }
+
+    async fn record_broadcast_delivery(&self, id: i64, chat_id: ChatId, delivered: bool) -> anyhow::Result<()> {
+        sqlx::query(indoc::indoc! {"
+                UPDATE broadcast
+                SET last_chat_id = $2, sent = sent + $3::int, failed = failed + (NOT $3)::int
+                WHERE id = $1
+            "})
+        .bind(id)
+        .bind(chat_id.0)
+        .bind(delivered)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn record_blocked_chat(&self, chat_id: ChatId) -> anyhow::Result<()> {
+        sqlx::query(indoc::indoc! {"
+                INSERT INTO chat_blocked (chat_id, blocked_at)
+                VALUES ($1, now())
+                ON CONFLICT (chat_id) DO UPDATE SET blocked_at = now()
+            "})
+        .bind(chat_id.0)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn record_feedback(
+        &self,
+        chat_id: ChatId,
+        from_admin: bool,
+        text: &str,
+        feedback_message_id: MessageId,
+    ) -> anyhow::Result<()> {
+        sqlx::query(indoc::indoc! {"
+                INSERT INTO feedback (chat_id, from_admin, text, feedback_message_id, created_at)
+                VALUES ($1, $2, $3, $4, now())
+            "})
+        .bind(chat_id.0)
+        .bind(from_admin)
+        .bind(text)
+        .bind(feedback_message_id.0)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn find_feedback_chat(&self, feedback_message_id: MessageId) -> anyhow::Result<Option<ChatId>> {
+        let row: Option<(i64,)> = sqlx::query_as(indoc::indoc! {"
+                SELECT chat_id
+                FROM feedback
+                WHERE feedback_message_id = $1
+                ORDER BY id DESC
+                LIMIT 1
+            "})
+        .bind(feedback_message_id.0)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|(chat_id,)| ChatId(chat_id)))
+    }
+
+    async fn get_feedback_history(&self, chat_id: ChatId, limit: usize) -> anyhow::Result<Vec<FeedbackMessage>> {
+        let rows: Vec<(bool, String, OffsetDateTime)> = sqlx::query_as(indoc::indoc! {"
+                SELECT from_admin, text, created_at
+                FROM (
+                    SELECT id, from_admin, text, created_at
+                    FROM feedback
+                    WHERE chat_id = $1
+                    ORDER BY id DESC
+                    LIMIT $2
+                ) f
+                ORDER BY id
+            "})
+        .bind(chat_id.0)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(from_admin, text, created_at)| FeedbackMessage {
+                chat_id,
+                from_admin,
+                text,
+                created_at,
+            })
+            .collect())
+    }
+
+    async fn record_task_report(
+        &self,
+        uid: i64,
+        task_id: TaskId,
+        reason: TaskReportReason,
+    ) -> anyhow::Result<Option<i64>> {
+        let row: Option<(i64,)> = sqlx::query_as(indoc::indoc! {"
+                INSERT INTO task_report (task_id, uid, reason, created_at)
+                VALUES ($1, $2, $3, now())
+                ON CONFLICT (task_id, uid, reason) WHERE NOT resolved DO NOTHING
+                RETURNING id
+            "})
+        .bind(task_id)
+        .bind(uid)
+        .bind(reason.as_str())
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|(id,)| id))
+    }
+
+    async fn set_task_report_message(&self, report_id: i64, feedback_message_id: MessageId) -> anyhow::Result<()> {
+        sqlx::query("UPDATE task_report SET feedback_message_id = $2 WHERE id = $1")
+            .bind(report_id)
+            .bind(feedback_message_id.0)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn find_reported_task(&self, feedback_message_id: MessageId) -> anyhow::Result<Option<TaskId>> {
+        let row: Option<(i64,)> = sqlx::query_as(indoc::indoc! {"
+                SELECT task_id
+                FROM task_report
+                WHERE feedback_message_id = $1
+                ORDER BY id DESC
+                LIMIT 1
+            "})
+        .bind(feedback_message_id.0)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|(task_id,)| task_id))
+    }
+
+    async fn get_task_report_counts(&self, task_id: TaskId) -> anyhow::Result<Vec<(TaskReportReason, i64)>> {
+        let rows: Vec<(String, i64)> = sqlx::query_as(indoc::indoc! {"
+                SELECT reason, count(*) as count
+                FROM task_report
+                WHERE task_id = $1 AND NOT resolved
+                GROUP BY reason
+                ORDER BY count DESC, reason
+            "})
+        .bind(task_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter()
+            .map(|(reason, count)| Ok((TaskReportReason::parse(&reason)?, count)))
+            .collect()
+    }
+
+    async fn get_reported_tasks(&self, limit: usize) -> anyhow::Result<Vec<(TaskId, i64)>> {
+        let rows: Vec<(i64, i64)> = sqlx::query_as(indoc::indoc! {"
+                SELECT task_id, count(*) as count
+                FROM task_report
+                WHERE NOT resolved
+                GROUP BY task_id
+                ORDER BY count DESC, task_id
+                LIMIT $1
+            "})
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows)
+    }
+
+    async fn resolve_task_reports(&self, task_id: TaskId) -> anyhow::Result<()> {
+        sqlx::query("UPDATE task_report SET resolved = true WHERE task_id = $1 AND NOT resolved")
+            .bind(task_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn export_user_data(&self, user_id: i64) -> anyhow::Result<BTreeMap<String, serde_json::Value>> {
+        let mut data = BTreeMap::new();
+        for (table, columns) in USER_TABLES {
+            let (Json(rows),): (Json<serde_json::Value>,) = sqlx::query_as(&format!(
+                r#"SELECT coalesce(json_agg(t), '[]'::json) FROM "{table}" t WHERE {}"#,
+                user_condition(columns)
+            ))
+            .bind(user_id)
+            .fetch_one(&self.pool)
+            .await?;
+            data.insert(table.to_string(), rows);
+        }
+
+        Ok(data)
+    }
+
+    async fn forget_user(&self, user_id: i64) -> anyhow::Result<()> {
+        let mut tx = self.pool.begin().await?;
+        for (table, columns) in USER_TABLES {
+            sqlx::query(&format!(r#"DELETE FROM "{table}" WHERE {}"#, user_condition(columns)))
+                .bind(user_id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn flush(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// Tables holding user data and their columns with a user id or the user's private chat id.
+/// Other tables reference user_info, it goes last.
+pub(super) const USER_TABLES: &[(&str, &[&str])] = &[
+    ("blitz_result", &["uid"]),
+    ("broadcast", &["author_uid"]),
+    ("chat_blocked", &["chat_id"]),
+    ("feedback", &["chat_id"]),
+    ("friend_invite", &["uid"]),
+    ("group_score", &["chat_id", "uid"]),
+    ("task_report", &["uid"]),
+    ("user_achievement", &["uid"]),
+    ("user_answer", &["uid"]),
+    ("user_friend", &["uid", "friend_uid"]),
+    ("user_lesson", &["uid"]),
+    ("user_profile_change", &["uid"]),
+    ("user_state", &["chat_id"]),
+    ("user_task", &["chat_id"]),
+    ("user_xp", &["uid"]),
+    ("user_info", &["uid"]),
+];
+
+pub(super) fn user_condition(columns: &[&str]) -> String {
+    columns
+        .iter()
+        .map(|column| format!(r#""{column}" = $1"#))
+        .collect::<Vec<_>>()
+        .join(" OR ")
+}
+
+/// Checks [USER_TABLES] against the (table, column) pairs of the schema: listed columns exist
+/// and every user column of the schema is listed.
+#[cfg(test)]
+pub(super) fn check_user_tables(schema: &[(String, String)]) {
+    let listed =
+        |table: &str, column: &str| USER_TABLES.iter().any(|(t, columns)| *t == table && columns.contains(&column));
+    for (table, columns) in USER_TABLES {
+        for column in *columns {
+            assert!(
+                schema.contains(&(table.to_string(), column.to_string())),
+                "{table}.{column} is not in the schema"
+            );
+        }
+    }
+    for (table, column) in schema {
+        if column == "uid" || column == "chat_id" || column.ends_with("_uid") {
+            assert!(listed(table, column), "{table}.{column} is missing from USER_TABLES");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{bot::conformance, test_db::setup_db};
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn test_touch_user() -> Result<()> {
+        let pg = setup_db().await;
+        let service = PgUserService { pool: pg.pool };
+        let user = UserInfo::new(1, Some("username"), "full_name");
+
+        let new_user = service.touch_user(&user).await?;
+        assert!(new_user);
+
+        let new_user = service.touch_user(&user).await?;
+        assert!(!new_user);
+        assert!(service.get_profile_changes(1, 10).await?.is_empty());
+
+        let renamed = UserInfo {
+            username: None,
+            full_name: "New name".into(),
+            language_code: Some("sr".into()),
+            ..user.clone()
+        };
+        assert!(!service.touch_user(&renamed).await?);
+        assert!(!service.touch_user(&renamed).await?);
+
+        let found = service.find_user("username").await?;
+        assert!(found.is_none());
+        let changes = service.get_profile_changes(1, 10).await?;
+        assert_eq!(
+            changes
+                .iter()
+                .map(|change| (
+                    change.field.as_str(),
+                    change.old_value.as_deref(),
+                    change.new_value.as_deref()
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                ("username", Some("username"), None),
+                ("full_name", Some("full_name"), Some("New name")),
+                ("language_code", None, Some("sr")),
+            ]
+        );
+        assert_eq!(service.get_profile_changes(1, 1).await?[0].field, "language_code");
+
+        service
+            .touch_user(&UserInfo {
+                is_premium: true,
+                ..user
+            })
+            .await?;
+        let found = service.find_user("username").await?.unwrap();
+        assert_eq!(found.full_name, "full_name");
+        assert!(found.is_premium);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_state() -> Result<()> {
+        let pg = setup_db().await;
+        let service = PgUserService { pool: pg.pool };
+        let chat_id = ChatId(1);
+
+        let state = service.get_state(chat_id).await?;
+        assert_eq!(state.filter, None);
+
+        let state = UserData::new(Some("filter".into()));
+        service.update_state(chat_id, state.clone()).await?;
+
+        let state = service.get_state(chat_id).await?;
+        assert_eq!(state.filter, Some("filter".into()));
+
+        let state = UserData::new(None);
+        service.update_state(chat_id, state.clone()).await?;
+
+        let state = service.get_state(chat_id).await?;
+        assert_eq!(state.filter, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_tasks() -> Result<()> {
+        let pg = setup_db().await;
+        let service = PgUserService { pool: pg.pool };
+        let chat_id = ChatId(1);
+
+        let tasks = vec![1, 2, 3];
+        service.update_tasks(chat_id, &tasks).await?;
+
+        let task = service.take_next_task(chat_id).await?;
+        assert_eq!(task, Some(1));
+
+        let task = service.take_next_task(chat_id).await?;
+        assert_eq!(task, Some(2));
+
+        let task = service.take_next_task(chat_id).await?;
+        assert_eq!(task, Some(3));
+
+        let task = service.take_next_task(chat_id).await?;
+        assert_eq!(task, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_user_state_conformance() -> Result<()> {
+        let pg = setup_db().await;
+        conformance::user_state(&PgUserService { pool: pg.pool }).await
+    }
+
+    #[tokio::test]
+    async fn test_answer_stats_conformance() -> Result<()> {
+        let pg = setup_db().await;
+        conformance::answer_stats(&PgUserService { pool: pg.pool }).await
+    }
+
+    #[tokio::test]
+    async fn test_xp_leaderboard_conformance() -> Result<()> {
+        let pg = setup_db().await;
+        conformance::xp_leaderboard(&PgUserService { pool: pg.pool }).await
+    }
+
+    #[tokio::test]
+    async fn test_task_stats_conformance() -> Result<()> {
+        let pg = setup_db().await;
+        conformance::task_stats(&PgUserService { pool: pg.pool }).await
+    }
+
+    #[tokio::test]
+    async fn test_multi_gap_answer_stat_conformance() -> Result<()> {
+        let pg = setup_db().await;
+        conformance::multi_gap_answer_stat(&PgUserService { pool: pg.pool }).await
+    }
+
+    #[tokio::test]
+    async fn test_task_queue() -> Result<()> {
+        let pg = setup_db().await;
+        conformance::task_queue(&PgUserService { pool: pg.pool }).await
+    }
+
+    #[tokio::test]
+    async fn test_update_tasks_large_queue() -> Result<()> {
+        let pg = setup_db().await;
+        let service = PgUserService { pool: pg.pool };
+        let chat_id = ChatId(1);
+        let tasks = (1..=10_000).rev().collect::<Vec<TaskId>>();
+
+        // A refill replaces the previous queue, the second run deletes and inserts.
+        // Timings are reported with --nocapture, not asserted as they depend on the machine
+        for run in ["insert", "refill"] {
+            let start = std::time::Instant::now();
+            service.update_tasks(chat_id, &tasks).await?;
+            eprintln!(
+                "update_tasks {run} with {} tasks took {:?}",
+                tasks.len(),
+                start.elapsed()
+            );
+        }
+
+        assert_eq!(service.take_next_task(chat_id).await?, Some(10_000));
+        assert_eq!(service.take_next_task(chat_id).await?, Some(9_999));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_answer_and_answer_stat() -> Result<()> {
+        let pg = setup_db().await;
+        let service = PgUserService { pool: pg.pool };
+        let user_id = 1;
+
+        service.touch_user(&UserInfo::new(user_id, Some("test"), "test")).await?;
+
+        let stat = service.get_answer_stat(user_id, std::time::Duration::from_secs(1)).await?;
+        assert_eq!(stat.count, 0);
+        assert_eq!(stat.correct, 0);
+
+        let answer = Answer {
+            uid: user_id,
+            task_id: 1,
+            gap: 0,
+            answer_index: 0,
+            answer_text: None,
+            correct: true,
+            asked_at: OffsetDateTime::now_utc() - std::time::Duration::from_secs(15),
+            answered_at: OffsetDateTime::now_utc() - std::time::Duration::from_secs(15),
+        };
+        service.record_anwer(answer).await?;
+        let answer = Answer {
+            uid: user_id,
+            task_id: 1,
+            gap: 0,
+            answer_index: 2,
+            answer_text: Some("kuća".to_owned()),
+            correct: false,
+            asked_at: OffsetDateTime::now_utc() - std::time::Duration::from_secs(15),
+            answered_at: OffsetDateTime::now_utc() - std::time::Duration::from_secs(15),
+        };
+        service.record_anwer(answer).await?;
+
+        let stat = service.get_answer_stat(user_id, std::time::Duration::from_secs(60)).await?;
+        assert_eq!(stat.count, 2);
+        assert_eq!(stat.correct, 1);
+
+        let answers = service.get_answers(std::time::Duration::from_secs(60)).await?;
+        assert_eq!(answers.len(), 2);
+        assert_eq!(answers[1].answer_index, 2);
+        assert_eq!(answers[1].answer_text.as_deref(), Some("kuća"));
+
+        let stat = service.get_answer_stat(user_id, std::time::Duration::from_secs(0)).await?;
+        assert_eq!(stat.count, 0);
+        assert_eq!(stat.correct, 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_blitz_results() -> Result<()> {
+        let pg = setup_db().await;
+        let service = PgUserService { pool: pg.pool };
+        let user_id = 1;
+
+        service.touch_user(&UserInfo::new(user_id, Some("test"), "test")).await?;
+
+        let best = service.get_best_blitz_result(user_id, 60).await?;
+        assert!(best.is_none());
+
+        for (answered, correct) in [(10, 5), (12, 7), (7, 7), (20, 3)] {
+            service
+                .record_blitz_result(BlitzResult {
+                    uid: user_id,
+                    duration_secs: 60,
+                    answered,
+                    correct,
+                    avg_response_ms: 3000,
+                    finished_at: OffsetDateTime::now_utc(),
+                })
+                .await?;
+        }
+
+        let best = service.get_best_blitz_result(user_id, 60).await?.unwrap();
+        assert_eq!(best.correct, 7);
+        assert_eq!(best.answered, 7);
+
+        let best = service.get_best_blitz_result(user_id, 30).await?;
+        assert!(best.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_group_scores() -> Result<()> {
+        let pg = setup_db().await;
+        let service = PgUserService { pool: pg.pool };
+        let chat_id = ChatId(-100);
+
+        service.touch_user(&UserInfo::new(1, Some("first"), "First")).await?;
+        service.touch_user(&UserInfo::new(2, Some("second"), "Second")).await?;
+
+        service.update_group_scores(chat_id, &[(1, true), (2, false)]).await?;
+        service.update_group_scores(chat_id, &[(1, false), (2, true)]).await?;
+        service.update_group_scores(chat_id, &[(2, true)]).await?;
+        service.update_group_scores(ChatId(-200), &[(1, true)]).await?;
+
+        let scores = service.get_group_scoreboard(chat_id, 10).await?;
+        assert_eq!(scores.len(), 2);
+        assert_eq!((scores[0].uid, scores[0].answered, scores[0].correct), (2, 3, 2));
+        assert_eq!(scores[0].full_name, "Second");
+        assert_eq!((scores[1].uid, scores[1].answered, scores[1].correct), (1, 2, 1));
+
+        let scores = service.get_group_scoreboard(chat_id, 1).await?;
+        assert_eq!(scores.len(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_friends() -> Result<()> {
+        let pg = setup_db().await;
+        let service = PgUserService { pool: pg.pool };
+
+        service.touch_user(&UserInfo::new(1, Some("first"), "First")).await?;
+        service.touch_user(&UserInfo::new(2, Some("second"), "Second")).await?;
+
+        assert!(service.add_friend(1, 2).await?);
+        assert!(!service.add_friend(2, 1).await?);
+        assert!(!service.add_friend(1, 3).await?);
+
+        assert_eq!(service.get_friends(1).await?, vec![2]);
+        assert_eq!(service.get_friends(2).await?, vec![1]);
+        assert!(service.get_friends(3).await?.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_xp_and_achievements() -> Result<()> {
+        let pg = setup_db().await;
+        let service = PgUserService { pool: pg.pool };
+
+        service.touch_user(&UserInfo::new(1, Some("first"), "First")).await?;
+        assert_eq!(service.get_xp(1).await?, 0);
+        assert_eq!(service.add_xp(1, 15).await?, 15);
+        assert_eq!(service.add_xp(1, 5).await?, 20);
+        assert_eq!(service.get_xp(1).await?, 20);
+        assert_eq!(service.add_xp(2, 5).await?, 0);
+
+        assert!(service.award_achievement(1, "answers_1").await?);
+        assert!(!service.award_achievement(1, "answers_1").await?);
+        let achievements = service.get_achievements(1).await?;
+        assert_eq!(achievements.len(), 1);
+        assert_eq!(achievements[0].0, "answers_1");
+
+        let now = OffsetDateTime::now_utc();
+        for (task_id, answered_at) in [
+            (1, now),
+            (1, now - std::time::Duration::from_secs(60)),
+            (2, now - time::Duration::days(2)),
+        ] {
+            service
+                .record_anwer(Answer {
+                    uid: 1,
+                    task_id,
+                    gap: 0,
+                    answer_index: 0,
+                    answer_text: None,
+                    correct: task_id == 1,
+                    asked_at: answered_at,
+                    answered_at,
+                })
+                .await?;
+        }
+        let days = service.get_answer_days(1, 10).await?;
+        assert_eq!(days.len(), 2);
+        assert!(days[0] > days[1]);
+        assert_eq!(service.get_answer_days(1, 1).await?.len(), 1);
+
+        let count = service.get_task_answer_count(1).await?;
+        assert_eq!((count.answered, count.correct), (2, 2));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_lessons() -> Result<()> {
+        let pg = setup_db().await;
+        let service = PgUserService { pool: pg.pool };
+
+        service.touch_user(&UserInfo::new(1, Some("first"), "First")).await?;
+        assert!(service.get_unlocked_lessons(1).await?.is_empty());
+
+        assert!(service.unlock_lesson(1, "shopping-locative").await?);
+        assert!(!service.unlock_lesson(1, "shopping-locative").await?);
+        assert!(service.unlock_lesson(1, "shopping-dative").await?);
+
+        assert_eq!(
+            service.get_unlocked_lessons(1).await?,
+            vec!["shopping-dative".to_owned(), "shopping-locative".to_owned()]
+        );
+        assert!(service.get_unlocked_lessons(2).await?.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_export_and_forget_user() -> Result<()> {
+        let pg = setup_db().await;
+        let service = PgUserService { pool: pg.pool };
+
+        for uid in [1, 2] {
+            service.touch_user(&UserInfo::new(uid, None, "User")).await?;
+            service.update_tasks(ChatId(uid), &[10, 20]).await?;
+            service
+                .record_anwer(Answer {
+                    uid,
+                    task_id: 10,
+                    gap: 0,
+                    answer_index: 1,
+                    answer_text: None,
+                    correct: true,
+                    asked_at: OffsetDateTime::now_utc(),
+                    answered_at: OffsetDateTime::now_utc(),
+                })
+                .await?;
+            service
+                .record_feedback(ChatId(uid), false, "Hello", MessageId(uid as i32))
+                .await?;
+        }
+        service.add_friend(2, 1).await?;
+        service.update_group_scores(ChatId(-100), &[(1, true), (2, true)]).await?;
+        service.unlock_lesson(1, "shopping-dative").await?;
+        service.create_broadcast(1, "News").await?;
+
+        let data = service.export_user_data(1).await?;
+        assert_eq!(data["user_info"][0]["uid"], 1);
+        assert_eq!(data["user_task"].as_array().map(Vec::len), Some(2));
+        assert_eq!(data["user_answer"][0]["task_id"], 10);
+        assert_eq!(data["feedback"][0]["text"], "Hello");
+        assert_eq!(data["user_friend"].as_array().map(Vec::len), Some(2));
+        assert_eq!(data["user_lesson"][0]["lesson_id"], "shopping-dative");
+        assert_eq!(data["group_score"][0]["chat_id"], -100);
+        assert_eq!(data["broadcast"][0]["text"], "News");
+
+        service.forget_user(1).await?;
+        let data = service.export_user_data(1).await?;
+        for (table, rows) in &data {
+            assert_eq!(rows, &serde_json::json!([]), "{table}");
+        }
+        assert!(data.contains_key("user_achievement"));
+
+        let other = service.export_user_data(2).await?;
+        assert_eq!(other["user_info"][0]["uid"], 2);
+        assert_eq!(other["user_answer"].as_array().map(Vec::len), Some(1));
+        assert_eq!(other["user_friend"], serde_json::json!([]));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_leaderboard() -> Result<()> {
+        let pg = setup_db().await;
+        let service = PgUserService { pool: pg.pool };
+
+        for (uid, name) in [(1, "First"), (2, "Second"), (3, "Third")] {
+            service.touch_user(&UserInfo::new(uid, None, name)).await?;
+        }
+        service.set_public_profile(1, true).await?;
+        service.set_public_profile(2, true).await?;
+        assert!(service.is_public_profile(1).await?);
+        assert!(!service.is_public_profile(3).await?);
+
+        let answered_at = OffsetDateTime::now_utc() - std::time::Duration::from_secs(15);
+        // Every answer is a separate task
+        for (task_id, (uid, correct)) in [
+            (1, true),
+            (1, false),
+            (2, true),
+            (2, true),
+            (3, true),
+            (3, true),
+            (3, true),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            service
+                .record_anwer(Answer {
+                    uid,
+                    task_id: task_id as TaskId,
+                    gap: 0,
+                    answer_index: 0,
+                    answer_text: None,
+                    correct,
+                    asked_at: answered_at,
+                    answered_at,
+                })
+                .await?;
+        }
+
+        let week = std::time::Duration::from_secs(60 * 60 * 24 * 7);
+        let top = service.get_leaderboard(week, None, 10).await?;
+        assert_eq!(top.iter().map(|entry| entry.uid).collect::<Vec<_>>(), vec![2, 1]);
+        assert_eq!((top[0].answered, top[0].correct), (2, 2));
+        assert_eq!(top[0].full_name, "Second");
+
+        let top = service.get_leaderboard(week, Some(&[1, 3]), 10).await?;
+        assert_eq!(top.iter().map(|entry| entry.uid).collect::<Vec<_>>(), vec![3, 1]);
+
+        let top = service.get_leaderboard(std::time::Duration::from_secs(1), None, 10).await?;
+        assert!(top.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_bot_stats_and_find_user() -> Result<()> {
+        let pg = setup_db().await;
+        let service = PgUserService { pool: pg.pool };
+
+        service.touch_user(&UserInfo::new(1, Some("First"), "First")).await?;
+        service.touch_user(&UserInfo::new(2, None, "Second")).await?;
+
+        let answered_at = OffsetDateTime::now_utc() - std::time::Duration::from_secs(15);
+        for (uid, correct) in [(1, true), (1, false), (2, true)] {
+            service
+                .record_anwer(Answer {
+                    uid,
+                    task_id: 1,
+                    gap: 0,
+                    answer_index: 0,
+                    answer_text: None,
+                    correct,
+                    asked_at: answered_at,
+                    answered_at,
+                })
+                .await?;
+        }
+
+        let stats = service.get_bot_stats(std::time::Duration::from_secs(60 * 60)).await?;
+        assert_eq!((stats.total_users, stats.new_users), (2, 2));
+        assert_eq!(stats.daily.len(), 1);
+        assert_eq!(stats.daily[0].day, answered_at.date());
+        assert_eq!(
+            (
+                stats.daily[0].active_users,
+                stats.daily[0].answered,
+                stats.daily[0].correct
+            ),
+            (2, 3, 2)
+        );
+
+        let user = service.find_user("first").await?;
+        assert_eq!(user.map(|user| user.uid), Some(1));
+        assert!(service.find_user("second").await?.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_broadcast() -> Result<()> {
+        let pg = setup_db().await;
+        let service = PgUserService { pool: pg.pool };
+
+        for uid in [1, 2, 3] {
+            service.touch_user(&UserInfo::new(uid, None, "User")).await?;
+        }
+        service.update_state(ChatId(-100), UserData::default()).await?;
+        service.record_blocked_chat(ChatId(2)).await?;
+
+        assert_eq!(service.count_broadcast_chats().await?, 3);
+        assert_eq!(
+            service.get_broadcast_chats(None, 2).await?,
+            vec![ChatId(-100), ChatId(1)]
+        );
+        assert_eq!(service.get_broadcast_chats(Some(1), 10).await?, vec![ChatId(3)]);
+
+        let id = service.create_broadcast(1, "News").await?;
+        assert!(
+            service
+                .update_broadcast_status(id, BroadcastStatus::Draft, BroadcastStatus::Sending)
+                .await?
+        );
+        assert!(
+            !service
+                .update_broadcast_status(id, BroadcastStatus::Draft, BroadcastStatus::Sending)
+                .await?
+        );
+
+        service.record_broadcast_delivery(id, ChatId(-100), false).await?;
+        service.record_broadcast_delivery(id, ChatId(1), true).await?;
+
+        let sending = service.get_broadcasts(BroadcastStatus::Sending).await?;
+        assert_eq!(sending.len(), 1);
+        assert_eq!(sending[0].text, "News");
+        assert_eq!(sending[0].last_chat_id, Some(1));
+        assert_eq!((sending[0].sent, sending[0].failed), (1, 1));
+
+        // User is back after blocking the bot
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        service.touch_user(&UserInfo::new(2, None, "User")).await?;
+        assert_eq!(service.count_broadcast_chats().await?, 4);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_feedback() -> Result<()> {
+        let pg = setup_db().await;
+        let service = PgUserService { pool: pg.pool };
+
+        service.record_feedback(ChatId(1), false, "Hello", MessageId(10)).await?;
+        service.record_feedback(ChatId(2), false, "Other", MessageId(11)).await?;
+        service.record_feedback(ChatId(1), true, "Hi!", MessageId(12)).await?;
+        service.record_feedback(ChatId(1), false, "Thanks", MessageId(13)).await?;
+
+        assert_eq!(service.find_feedback_chat(MessageId(12)).await?, Some(ChatId(1)));
+        assert_eq!(service.find_feedback_chat(MessageId(11)).await?, Some(ChatId(2)));
+        assert_eq!(service.find_feedback_chat(MessageId(14)).await?, None);
+
+        let history = service.get_feedback_history(ChatId(1), 2).await?;
+        assert_eq!(
+            history
+                .iter()
+                .map(|message| (message.from_admin, message.text.as_str()))
+                .collect::<Vec<_>>(),
+            vec![(true, "Hi!"), (false, "Thanks")]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_task_reports() -> Result<()> {
+        let pg = setup_db().await;
+        let service = PgUserService { pool: pg.pool };
+
+        service.touch_user(&UserInfo::new(1, None, "First")).await?;
+        service.touch_user(&UserInfo::new(2, None, "Second")).await?;
+
+        let report_id = service.record_task_report(1, 10, TaskReportReason::Typo).await?;
+        assert!(report_id.is_some());
+        assert!(service.record_task_report(1, 10, TaskReportReason::Typo).await?.is_none());
+        service.record_task_report(2, 10, TaskReportReason::Typo).await?;
+        service.record_task_report(2, 10, TaskReportReason::WrongAnswer).await?;
+        service.record_task_report(2, 20, TaskReportReason::BadDistractor).await?;
+
+        service.set_task_report_message(report_id.unwrap(), MessageId(5)).await?;
+        assert_eq!(service.find_reported_task(MessageId(5)).await?, Some(10));
+        assert_eq!(service.find_reported_task(MessageId(6)).await?, None);
+
+        assert_eq!(
+            service.get_task_report_counts(10).await?,
+            vec![(TaskReportReason::Typo, 2), (TaskReportReason::WrongAnswer, 1)]
+        );
+        assert_eq!(service.get_reported_tasks(10).await?, vec![(10, 3), (20, 1)]);
+
+        service.resolve_task_reports(10).await?;
+        assert!(service.get_task_report_counts(10).await?.is_empty());
+        assert_eq!(service.get_reported_tasks(10).await?, vec![(20, 1)]);
+        // Resolved reports do not prevent new ones
+        assert!(service.record_task_report(1, 10, TaskReportReason::Typo).await?.is_some());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_user_tables_match_schema() -> Result<()> {
+        let pg = setup_db().await;
+        let schema: Vec<(String, String)> = sqlx::query_as(indoc::indoc! {"
+                SELECT c.table_name::text, c.column_name::text
+                FROM information_schema.columns c
+                JOIN information_schema.tables t USING (table_schema, table_name)
+                WHERE c.table_schema = current_schema()
+                  AND t.table_type = 'BASE TABLE'
+                  AND c.table_name NOT LIKE '\\_sqlx%'
+            "})
+        .fetch_all(&pg.pool)
+        .await?;
+        check_user_tables(&schema);
 
         Ok(())
     }
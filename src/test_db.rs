@@ -7,7 +7,7 @@ use std::{
 use anyhow::Result;
 use indoc::indoc;
 use libc::atexit;
-use sqlx::{migrate, postgres::PgPoolOptions, PgPool};
+use sqlx::{migrate, postgres::PgPoolOptions, sqlite::SqlitePoolOptions, PgPool, SqlitePool};
 use testcontainers::{clients::Cli, Container};
 use testcontainers_modules::postgres::Postgres;
 
@@ -89,6 +89,19 @@ pub async fn setup_db() -> AcquiredPg {
     AcquiredPg { pool, lock }
 }
 
+/// Fresh in-memory database, every connection would get its own one, so the pool keeps a single connection.
+pub async fn setup_sqlite() -> SqlitePool {
+    init_logger();
+
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+    migrate!("./migrations_sqlite").run(&pool).await.unwrap();
+    pool
+}
+
 async fn cleanup_db(pool: &PgPool) -> Result<()> {
     let start = Instant::now();
 